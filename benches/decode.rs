@@ -0,0 +1,89 @@
+//! Benchmarks of decoding pre-multiplied ARGB buffers into RGBA images
+//!
+//! Run with `cargo bench --bench decode`, and with `--features parallel` to include the
+//! multi-threaded path. The `reference` benchmarks run the per-pixel implementation that
+//! `utils::decode_buffer` had before it was vectorised, for comparison.
+//!
+
+#[macro_use]
+extern crate criterion;
+extern crate image;
+extern crate openslide;
+
+use criterion::{black_box, BenchmarkId, Criterion, Throughput};
+use image::{Rgba, RgbaImage};
+use openslide::utils::{self, WordRepresentation};
+
+/// Sizes (width = height) of the decoded regions.
+const SIZES: [u32; 3] = [256, 1024, 2048];
+
+/// A buffer of pre-multiplied ARGB pixels that looks like a slide: opaque tissue and background,
+/// with a transparent and a semi-transparent border at the edge of the slide.
+fn slide_buffer(size: u32) -> Vec<u32> {
+    (0..size * size)
+        .map(|index| {
+            let (col, row) = (index % size, index / size);
+            let alpha = if col >= size - 16 {
+                0
+            } else if col >= size - 32 {
+                128
+            } else {
+                255
+            };
+            let red = (col * 255 / size) * alpha / 255;
+            let green = (row * 255 / size) * alpha / 255;
+            let blue = ((col + row) % 256) * alpha / 255;
+            alpha << 24 | red << 16 | green << 8 | blue
+        })
+        .collect()
+}
+
+/// The previous implementation of `utils::decode_buffer`.
+fn decode_buffer_reference(buffer: &[u32], height: u32, width: u32) -> RgbaImage {
+    let mut rgba_image = RgbaImage::new(width, height);
+    for (col, row, pixel) in rgba_image.enumerate_pixels_mut() {
+        let value = buffer[(row * width + col) as usize];
+        let [alpha, mut red, mut green, mut blue] = value.to_be_bytes();
+        if alpha != 0 && alpha != 255 {
+            red = (f32::from(red) * (255.0 / f32::from(alpha))).round().clamp(0.0, 255.0) as u8;
+            green = (f32::from(green) * (255.0 / f32::from(alpha))).round().clamp(0.0, 255.0) as u8;
+            blue = (f32::from(blue) * (255.0 / f32::from(alpha))).round().clamp(0.0, 255.0) as u8;
+        }
+        *pixel = Rgba([red, green, blue, alpha]);
+    }
+    rgba_image
+}
+
+fn bench_decode_buffer(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode_buffer");
+    for &size in SIZES.iter() {
+        let buffer = slide_buffer(size);
+        group.throughput(Throughput::Elements(u64::from(size * size)));
+        group.bench_with_input(BenchmarkId::new("reference", size), &buffer, |b, buffer| {
+            b.iter(|| decode_buffer_reference(black_box(buffer), size, size))
+        });
+        group.bench_with_input(BenchmarkId::new("lut", size), &buffer, |b, buffer| {
+            b.iter(|| {
+                utils::decode_buffer(black_box(buffer), size, size, WordRepresentation::BigEndian)
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_unpremultiply_in_place(c: &mut Criterion) {
+    let mut group = c.benchmark_group("unpremultiply_in_place");
+    for &size in SIZES.iter() {
+        let buffer = slide_buffer(size);
+        group.throughput(Throughput::Elements(u64::from(size * size)));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &buffer, |b, buffer| {
+            let mut words = buffer.clone();
+            b.iter(|| utils::unpremultiply_in_place(black_box(&mut words)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_decode_buffer, bench_unpremultiply_in_place);
+criterion_main!(benches);
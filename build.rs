@@ -0,0 +1,94 @@
+//! Build script that finds libopenslide and detects its version.
+//!
+//! The library is found with pkg-config, or in the directory given by the `OPENSLIDE_LIB_DIR`
+//! environment variable. If the installed version is known (or given in the `OPENSLIDE_VERSION`
+//! environment variable), the OpenSlide 4.x bindings are compiled if and only if it is 4.x or
+//! newer. If it is not known, they are compiled only with the `openslide4` feature.
+//!
+//! With the `dynamic` feature, nothing is linked, and the 4.x bindings follow the `openslide4`
+//! feature, since the library is not known until runtime.
+
+extern crate pkg_config;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+const LIB_DIR_VARIABLE: &str = "OPENSLIDE_LIB_DIR";
+const VERSION_VARIABLE: &str = "OPENSLIDE_VERSION";
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed={}", LIB_DIR_VARIABLE);
+    println!("cargo:rerun-if-env-changed={}", VERSION_VARIABLE);
+    println!("cargo:rustc-check-cfg=cfg(openslide4)");
+
+    let feature_requested = env::var_os("CARGO_FEATURE_OPENSLIDE4").is_some();
+
+    if env::var_os("CARGO_FEATURE_DYNAMIC").is_some() {
+        if feature_requested {
+            println!("cargo:rustc-cfg=openslide4");
+        }
+        return;
+    }
+
+    // An explicit version overrides the detected one
+    let detected = link();
+    let version = env::var(VERSION_VARIABLE).ok().or(detected);
+
+    match version.as_ref().and_then(|version| major_version(version)) {
+        Some(major) if major >= 4 => println!("cargo:rustc-cfg=openslide4"),
+        Some(_) => {
+            if feature_requested {
+                println!(
+                    "cargo:warning=The openslide4 feature is enabled, but the installed OpenSlide \
+                     is version {}. The OpenSlide 4.x bindings are not compiled.",
+                    version.unwrap_or_default()
+                );
+            }
+        }
+        None => {
+            if feature_requested {
+                println!("cargo:rustc-cfg=openslide4");
+            }
+        }
+    }
+}
+
+/// Emit the link flags for libopenslide, and return its version if it can be found.
+fn link() -> Option<String> {
+    if let Ok(lib_dir) = env::var(LIB_DIR_VARIABLE) {
+        println!("cargo:rustc-link-search=native={}", lib_dir);
+        println!("cargo:rustc-link-lib=openslide");
+        return version_from_pc_file(&Path::new(&lib_dir).join("pkgconfig").join("openslide.pc"));
+    }
+
+    match pkg_config::Config::new().probe("openslide") {
+        // pkg-config has emitted the link flags
+        Ok(library) => Some(library.version),
+        Err(err) => {
+            println!(
+                "cargo:warning=Could not find OpenSlide with pkg-config ({}). Set {} to the \
+                 directory that contains libopenslide if linking fails.",
+                err.to_string().lines().find(|line| !line.trim().is_empty()).unwrap_or(""),
+                LIB_DIR_VARIABLE
+            );
+            println!("cargo:rustc-link-lib=openslide");
+            None
+        }
+    }
+}
+
+/// Read the `Version:` field of a pkg-config file.
+fn version_from_pc_file(path: &Path) -> Option<String> {
+    let contents = fs::read_to_string(path).ok()?;
+    contents
+        .lines()
+        .filter_map(|line| line.strip_prefix("Version:"))
+        .map(|version| String::from(version.trim()))
+        .next()
+}
+
+fn major_version(version: &str) -> Option<u32> {
+    version.split('.').next()?.trim().parse().ok()
+}
@@ -18,9 +18,13 @@ fn basic_usage(
     println!("Downsample factor at level 0: {}", os.get_level_downsample(0)?);
     println!("Best level for downsampling factor 4.5: {}", os.get_best_level_for_downsample(4.5)?);
 
-    let im = os.read_region(1500, 1000, 0, 512, 512)?;
+    let im = os.read_region(1500u32, 1000u32, 0u32, 512u32, 512u32)?;
     im.save("/tmp/wsi_region_2.png")?;
 
+    println!("\nAssociated images: {:?}", os.associated_image_names()?);
+    let label = os.read_associated_image("label")?;
+    label.save("/tmp/wsi_label.png")?;
+
     println!("\nPrint properties from the dictionary");
     for (key, val) in os.get_properties()? {
         println!("{0:<40} {1}", key, val);
@@ -35,7 +39,7 @@ fn main() {
     let filename = Path::new("assets/CMU-1-Small-Region.svs");
     println!("Analyzing {}", filename.display());
 
-    match basic_usage(&filename) {
+    match basic_usage(filename) {
         Ok(_) => println!("Basic usage functions are working okay"),
         Err(msg) => {
             println!("Basic usage functions not working");
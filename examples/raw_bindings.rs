@@ -12,44 +12,47 @@ fn basic_usage(
     filename: &str
 ) -> Result<(), Error> {
 
-    let vendor = bindings::detect_vendor(filename)?;
-    println!("Vendor: {}", vendor);
+    match bindings::detect_vendor(filename)? {
+        Some(vendor) => println!("Vendor: {}", vendor),
+        None => println!("Vendor: Not recognized"),
+    }
 
     let osr = bindings::open(filename)?;
 
-    let levels = bindings::get_level_count(osr)?;
-    println!("Slide has {} levels", levels);
+    unsafe {
+        let levels = bindings::get_level_count(osr)?;
+        println!("Slide has {} levels", levels);
 
-    let (height, width) = bindings::get_level0_dimensions(osr)?;
-    println!("Slide has dimension {} x {} at level 0", height, width);
+        let (height, width) = bindings::get_level0_dimensions(osr)?;
+        println!("Slide has dimension {} x {} at level 0", height, width);
 
-    let level = 0;
-    let (height, width) = bindings::get_level_dimensions(osr, level)?;
-    println!("Slide has dimension {} x {} at level {}", height, width, level);
+        let level = 0;
+        let (height, width) = bindings::get_level_dimensions(osr, level)?;
+        println!("Slide has dimension {} x {} at level {}", height, width, level);
 
-    let factor = bindings::get_level_downsample(osr, level)?;
-    println!("Slide at level {} is downsampled with factor {}", level, factor);
+        let factor = bindings::get_level_downsample(osr, level)?;
+        println!("Slide at level {} is downsampled with factor {}", level, factor);
 
-    let downsample_factor = 5.6;
-    let level = bindings::get_best_level_for_downsample(osr, downsample_factor)?;
-    println!("Best level for downsample factor {} is {}", downsample_factor, level);
+        let downsample_factor = 5.6;
+        let level = bindings::get_best_level_for_downsample(osr, downsample_factor)?;
+        println!("Best level for downsample factor {} is {}", downsample_factor, level);
 
-    let x = 1000;
-    let y = 1500;
-    let level = 0;
-    let h = 512;
-    let w = 512;
-    let word_repr = utils::WordRepresentation::BigEndian;
-    let buffer = bindings::read_region(osr, x, y, level, w, h)?;
-    let im = utils::decode_buffer(&buffer, h as u32, w as u32, word_repr)?;
-    im.save(Path::new("/tmp/wsi_region_1.png"))?;
-    println!("Region is written");
+        let x = 1000;
+        let y = 1500;
+        let level = 0;
+        let h = 512;
+        let w = 512;
+        let word_repr = utils::WordRepresentation::BigEndian;
+        let buffer = bindings::read_region(osr, x, y, level, w, h)?;
+        let im = utils::decode_buffer(&buffer, h as u32, w as u32, word_repr)?;
+        im.save(Path::new("/tmp/wsi_region_1.png"))?;
+        println!("Region is written");
 
-    // Test error
-    //let factor = bindings::get_level_downsample(osr, 2)?;
-    //println!("{:?}", bindings::get_error(osr));
+        // None as long as OpenSlide has not entered its error state
+        println!("Error state: {:?}", bindings::get_error(osr)?);
 
-    bindings::close(osr);
+        bindings::close(osr);
+    }
 
     Ok(())
 }
@@ -60,12 +63,21 @@ fn properties(
     let osr = bindings::open(filename)?;
 
     println!("Slide in {} has the following properties:", filename);
-    println!("{0:<40} {1}", "Property key", "Property value");
-    for name in bindings::get_property_names(osr)? {
-        println!("{0:<40} {1}", name, bindings::get_property_value(osr, &name)?);
+    println!("{0:<40} Property value", "Property key");
+    unsafe {
+        for name in bindings::get_property_names(osr)? {
+            let value = bindings::get_property_value(osr, &name)?.unwrap_or_default();
+            println!("{0:<40} {1}", name, value);
+        }
+
+        println!("Slide in {} has the following associated images:", filename);
+        for name in bindings::get_associated_image_names(osr)? {
+            let (width, height) = bindings::get_associated_image_dimensions(osr, &name)?;
+            println!("{0:<40} {1} x {2}", name, width, height);
+        }
+
+        bindings::close(osr);
     }
-
-    bindings::close(osr);
     Ok(())
 }
 
@@ -0,0 +1,260 @@
+//! The default backend, which reads slides with the OpenSlide C library
+//!
+
+use std::collections::HashMap;
+use std::path::Path;
+#[cfg(openslide4)]
+use std::sync::Arc;
+
+use num::ToPrimitive;
+
+use bindings;
+use error::{conversion_error, OpenSlideError};
+use super::SlideBackend;
+
+/// A slide opened with the OpenSlide C library.
+///
+/// This owns the raw OpenSlide handle, and closes the slide when it is dropped. Every method checks
+/// the OpenSlide error state after calling into the library.
+pub struct LibOpenSlideBackend {
+    osr: *const bindings::OpenSlideT,
+}
+
+impl Drop for LibOpenSlideBackend {
+    /// This method is called when the backend is dropped, and tries to close the slide.
+    fn drop(&mut self) {
+        unsafe { bindings::close(self.osr) };
+    }
+}
+
+// OpenSlide objects are thread-safe (see the OpenSlide C API documentation), so the handle can be
+// used and closed from any thread.
+unsafe impl Send for LibOpenSlideBackend {}
+unsafe impl Sync for LibOpenSlideBackend {}
+
+impl LibOpenSlideBackend {
+    /// Open the slide at the given filename location.
+    pub fn open(filename: &Path) -> Result<LibOpenSlideBackend, OpenSlideError> {
+        if !filename.exists() {
+            return Err(OpenSlideError::InvalidPath(format!(
+                "Nonexisting path: {}",
+                filename.display()
+            )));
+        }
+
+        let filename_str = filename.to_str().ok_or_else(|| {
+            OpenSlideError::InvalidPath(format!("Path is not valid UTF-8: {}", filename.display()))
+        })?;
+        let osr = bindings::open(filename_str)?;
+
+        // From here on, the slide is closed when `backend` is dropped, also on errors.
+        let backend = LibOpenSlideBackend { osr };
+        backend.check_error()?;
+        Ok(backend)
+    }
+
+    /// Check whether OpenSlide has entered its error state.
+    ///
+    /// OpenSlide errors are sticky: once an operation has failed, every subsequent operation on
+    /// the same slide fails as well, and the values returned by the C library are not to be
+    /// trusted. This method returns the error message from OpenSlide, if any.
+    fn check_error(&self) -> Result<(), OpenSlideError> {
+        match unsafe { bindings::get_error(self.osr)? } {
+            Some(msg) => Err(OpenSlideError::Library(msg)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl SlideBackend for LibOpenSlideBackend {
+    fn level_count(&self) -> Result<u32, OpenSlideError> {
+        let num_levels = unsafe { bindings::get_level_count(self.osr)? };
+        self.check_error()?;
+
+        if num_levels < 0 {
+            return Err(OpenSlideError::Library(format!(
+                "Number of levels is {}, but OpenSlide did not report an error",
+                num_levels
+            )));
+        }
+
+        Ok(num_levels as u32)
+    }
+
+    fn level_dimensions(&self, level: u32) -> Result<(u64, u64), OpenSlideError> {
+        let level = level.to_i32().ok_or_else(conversion_error)?;
+        let (width, height) = unsafe { bindings::get_level_dimensions(self.osr, level)? };
+        self.check_error()?;
+
+        if width < 0 || height < 0 {
+            return Err(OpenSlideError::Library(format!(
+                "Level {} dimensions are ({}, {}), but OpenSlide did not report an error",
+                level, width, height
+            )));
+        }
+
+        Ok((width as u64, height as u64))
+    }
+
+    fn level_downsample(&self, level: u32) -> Result<f64, OpenSlideError> {
+        let level = level.to_i32().ok_or_else(conversion_error)?;
+        let downsample_factor = unsafe { bindings::get_level_downsample(self.osr, level)? };
+        self.check_error()?;
+
+        if downsample_factor < 0.0 {
+            return Err(OpenSlideError::Library(format!(
+                "Downsample factor for level {} is {}, but OpenSlide did not report an error",
+                level, downsample_factor
+            )));
+        }
+
+        Ok(downsample_factor)
+    }
+
+    fn best_level_for_downsample(&self, downsample: f64) -> Result<u32, OpenSlideError> {
+        let level = unsafe { bindings::get_best_level_for_downsample(self.osr, downsample)? };
+        self.check_error()?;
+
+        if level < 0 {
+            return Err(OpenSlideError::Library(format!(
+                "Returned level is {}, but OpenSlide did not report an error",
+                level
+            )));
+        }
+
+        Ok(level as u32)
+    }
+
+    fn read_region(
+        &self,
+        dest: &mut [u32],
+        x: i64,
+        y: i64,
+        level: u32,
+        width: u64,
+        height: u64,
+    ) -> Result<(), OpenSlideError> {
+        unsafe {
+            bindings::read_region_into(
+                self.osr,
+                dest,
+                x,
+                y,
+                level.to_i32().ok_or_else(conversion_error)?,
+                width.to_i64().ok_or_else(conversion_error)?,
+                height.to_i64().ok_or_else(conversion_error)?,
+            )?
+        };
+        // A failed read leaves the buffer filled with garbage, so the caller must not use it.
+        self.check_error()
+    }
+
+    fn properties(&self) -> Result<HashMap<String, String>, OpenSlideError> {
+        let mut properties = HashMap::<String, String>::new();
+        for name in unsafe { bindings::get_property_names(self.osr)? } {
+            if let Some(value) = unsafe { bindings::get_property_value(self.osr, &name)? } {
+                properties.insert(name, value);
+            }
+        }
+        self.check_error()?;
+        Ok(properties)
+    }
+
+    fn associated_image_names(&self) -> Result<Vec<String>, OpenSlideError> {
+        let names = unsafe { bindings::get_associated_image_names(self.osr)? };
+        self.check_error()?;
+        Ok(names)
+    }
+
+    fn associated_image_dimensions(&self, name: &str) -> Result<(u64, u64), OpenSlideError> {
+        let (width, height) = unsafe { bindings::get_associated_image_dimensions(self.osr, name)? };
+        self.check_error()?;
+
+        if width < 0 || height < 0 {
+            return Err(OpenSlideError::InvalidArgument(format!(
+                "Slide has no associated image named {}",
+                name
+            )));
+        }
+
+        Ok((width as u64, height as u64))
+    }
+
+    fn read_associated_image(&self, name: &str, dest: &mut [u32]) -> Result<(), OpenSlideError> {
+        unsafe { bindings::read_associated_image_into(self.osr, name, dest)? };
+        self.check_error()
+    }
+
+    #[cfg(openslide4)]
+    fn icc_profile(&self) -> Result<Option<Vec<u8>>, OpenSlideError> {
+        let size = unsafe { bindings::get_icc_profile_size(self.osr)? };
+        self.check_error()?;
+        if size == 0 {
+            return Ok(None);
+        }
+        let profile = unsafe { bindings::read_icc_profile(self.osr)? };
+        self.check_error()?;
+        Ok(Some(profile))
+    }
+
+    #[cfg(openslide4)]
+    fn associated_image_icc_profile(&self, name: &str) -> Result<Option<Vec<u8>>, OpenSlideError> {
+        let size = unsafe { bindings::get_associated_image_icc_profile_size(self.osr, name)? };
+        self.check_error()?;
+        if size == 0 {
+            return Ok(None);
+        }
+        let profile = unsafe { bindings::read_associated_image_icc_profile(self.osr, name)? };
+        self.check_error()?;
+        Ok(Some(profile))
+    }
+
+    #[cfg(openslide4)]
+    fn set_cache(&self, cache: &TileCache) -> Result<(), OpenSlideError> {
+        unsafe { bindings::set_cache(self.osr, cache.handle.cache)? };
+        self.check_error()
+    }
+}
+
+/// Owner of the raw OpenSlide cache handle, which releases the cache when it is dropped.
+#[cfg(openslide4)]
+struct CacheHandle {
+    cache: *mut bindings::OpenSlideCacheT,
+}
+
+#[cfg(openslide4)]
+impl Drop for CacheHandle {
+    fn drop(&mut self) {
+        unsafe { bindings::cache_release(self.cache) };
+    }
+}
+
+// The OpenSlide cache is protected by its own lock.
+#[cfg(openslide4)]
+unsafe impl Send for CacheHandle {}
+#[cfg(openslide4)]
+unsafe impl Sync for CacheHandle {}
+
+/// A tile cache that can be shared between several open slides (requires OpenSlide 4.x).
+///
+/// By default, every slide has its own private tile cache. Slides that are given the same
+/// `TileCache` with `OpenSlide::set_cache()` share one cache, and thereby one memory bound.
+///
+/// Cloning is cheap, and clones refer to the same cache. A slide keeps the cache alive as long as
+/// it uses it, so the `TileCache` may be dropped after it has been set.
+#[cfg(openslide4)]
+#[derive(Clone)]
+pub struct TileCache {
+    handle: Arc<CacheHandle>,
+}
+
+#[cfg(openslide4)]
+impl TileCache {
+    /// Create a new tile cache with the given capacity in bytes.
+    pub fn new(capacity: usize) -> Result<TileCache, OpenSlideError> {
+        let cache = bindings::cache_create(capacity)?;
+        Ok(TileCache {
+            handle: Arc::new(CacheHandle { cache }),
+        })
+    }
+}
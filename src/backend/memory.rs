@@ -0,0 +1,328 @@
+//! An in-memory backend, for testing without the OpenSlide C library or slide files
+//!
+
+use std::collections::HashMap;
+
+use image::{Rgba, RgbaImage};
+
+use error::OpenSlideError;
+use super::SlideBackend;
+
+/// Tile size that is reported in the level properties, unless another one is set.
+const DEFAULT_TILE_SIZE: u32 = 256;
+
+/// Vendor that is reported in the properties, unless another one is set.
+const DEFAULT_VENDOR: &str = "memory";
+
+/// One level of the pyramid, stored as pre-multiplied ARGB.
+struct Level {
+    width: u64,
+    height: u64,
+    downsample: u32,
+    pixels: Vec<u32>,
+}
+
+/// A slide that lives in memory.
+///
+/// The pyramid is built from an `RgbaImage` (level 0) and a list of downsample factors. Every
+/// other level is computed by averaging blocks of level 0 pixels. The properties contain the
+/// standard `openslide.*` properties (vendor, level count and level dimensions, downsamples and
+/// tile sizes, and optionally the resolution), and any property can be added or overridden.
+///
+/// ```rust
+/// use openslide::backend::MemoryBackend;
+/// use openslide::OpenSlide;
+///
+/// let backend = MemoryBackend::generated(1024, 512, &[1, 4, 16])
+///     .unwrap()
+///     .with_mpp(0.25, 0.25)
+///     .with_tile_size(128, 128);
+/// let slide = OpenSlide::from_backend(backend).unwrap();
+/// assert_eq!(slide.get_level_dimensions(2u32).unwrap(), (64, 32));
+/// ```
+pub struct MemoryBackend {
+    levels: Vec<Level>,
+    tile_size: (u32, u32),
+    vendor: String,
+    mpp: Option<(f64, f64)>,
+    extra_properties: HashMap<String, String>,
+    associated_images: HashMap<String, (u64, u64, Vec<u32>)>,
+}
+
+impl MemoryBackend {
+    /// Build a pyramid from the given level 0 image.
+    ///
+    /// The downsample factors must start with 1 (level 0), and be strictly increasing. Returns an
+    /// error if they are not, or if the image is empty.
+    pub fn new(image: &RgbaImage, downsamples: &[u32]) -> Result<MemoryBackend, OpenSlideError> {
+        let (width, height) = image.dimensions();
+        if width == 0 || height == 0 {
+            return Err(OpenSlideError::InvalidArgument(String::from(
+                "Can not build a slide from an empty image",
+            )));
+        }
+        if downsamples.first() != Some(&1) {
+            return Err(OpenSlideError::InvalidArgument(format!(
+                "The first downsample factor must be 1, got {:?}",
+                downsamples
+            )));
+        }
+        if downsamples.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(OpenSlideError::InvalidArgument(format!(
+                "Downsample factors must be strictly increasing, got {:?}",
+                downsamples
+            )));
+        }
+
+        let base = image.pixels().map(|pixel| premultiply(*pixel)).collect::<Vec<u32>>();
+        let levels = downsamples
+            .iter()
+            .map(|&downsample| downsample_level(&base, width as u64, height as u64, downsample))
+            .collect();
+
+        Ok(MemoryBackend {
+            levels,
+            tile_size: (DEFAULT_TILE_SIZE, DEFAULT_TILE_SIZE),
+            vendor: String::from(DEFAULT_VENDOR),
+            mpp: None,
+            extra_properties: HashMap::new(),
+            associated_images: HashMap::new(),
+        })
+    }
+
+    /// Build a pyramid from a generated test pattern of the given size.
+    ///
+    /// The pattern is opaque, with the column (modulo 256) in the red channel, the row (modulo
+    /// 256) in the green channel, and a checkerboard of 64 x 64 pixel squares in the blue channel
+    /// (0 or 255), such that every level 0 pixel value can be predicted from its position.
+    pub fn generated(
+        width: u32,
+        height: u32,
+        downsamples: &[u32],
+    ) -> Result<MemoryBackend, OpenSlideError> {
+        let image = RgbaImage::from_fn(width, height, |col, row| {
+            let blue = if (col / 64 + row / 64) % 2 == 0 { 0 } else { 255 };
+            Rgba([(col % 256) as u8, (row % 256) as u8, blue, 255])
+        });
+        MemoryBackend::new(&image, downsamples)
+    }
+
+    /// Set the tile size (width, height) that is reported for every level.
+    pub fn with_tile_size(mut self, tile_width: u32, tile_height: u32) -> MemoryBackend {
+        self.tile_size = (tile_width, tile_height);
+        self
+    }
+
+    /// Set the vendor that is reported in `openslide.vendor`.
+    pub fn with_vendor(mut self, vendor: &str) -> MemoryBackend {
+        self.vendor = String::from(vendor);
+        self
+    }
+
+    /// Set the resolution in microns per pixel that is reported in `openslide.mpp-x` and
+    /// `openslide.mpp-y`.
+    pub fn with_mpp(mut self, mpp_x: f64, mpp_y: f64) -> MemoryBackend {
+        self.mpp = Some((mpp_x, mpp_y));
+        self
+    }
+
+    /// Add a property, or override one of the generated properties.
+    pub fn with_property(mut self, name: &str, value: &str) -> MemoryBackend {
+        self.extra_properties
+            .insert(String::from(name), String::from(value));
+        self
+    }
+
+    /// Add an associated image with the given name, e.g. `label` or `thumbnail`.
+    pub fn with_associated_image(mut self, name: &str, image: &RgbaImage) -> MemoryBackend {
+        let (width, height) = image.dimensions();
+        let pixels = image.pixels().map(|pixel| premultiply(*pixel)).collect();
+        self.associated_images
+            .insert(String::from(name), (width as u64, height as u64, pixels));
+        self
+    }
+
+    fn level(&self, level: u32) -> Result<&Level, OpenSlideError> {
+        self.levels
+            .get(level as usize)
+            .ok_or(OpenSlideError::InvalidLevel {
+                level: i64::from(level),
+                level_count: self.levels.len() as u32,
+            })
+    }
+}
+
+impl SlideBackend for MemoryBackend {
+    fn level_count(&self) -> Result<u32, OpenSlideError> {
+        Ok(self.levels.len() as u32)
+    }
+
+    fn level_dimensions(&self, level: u32) -> Result<(u64, u64), OpenSlideError> {
+        let level = self.level(level)?;
+        Ok((level.width, level.height))
+    }
+
+    fn level_downsample(&self, level: u32) -> Result<f64, OpenSlideError> {
+        Ok(f64::from(self.level(level)?.downsample))
+    }
+
+    fn read_region(
+        &self,
+        dest: &mut [u32],
+        x: i64,
+        y: i64,
+        level: u32,
+        width: u64,
+        height: u64,
+    ) -> Result<(), OpenSlideError> {
+        let level = self.level(level)?;
+        let len = width
+            .checked_mul(height)
+            .ok_or_else(|| OpenSlideError::InvalidArgument(String::from("Region is too large")))?;
+        if (dest.len() as u64) < len {
+            return Err(OpenSlideError::InvalidArgument(format!(
+                "Buffer of length {} is too small for a region of {} pixels",
+                dest.len(),
+                len
+            )));
+        }
+
+        // Top left corner in the reference frame of the level
+        let downsample = i64::from(level.downsample);
+        let left = x.div_euclid(downsample);
+        let top = y.div_euclid(downsample);
+        for row in 0..height {
+            let level_row = top + row as i64;
+            for col in 0..width {
+                let level_col = left + col as i64;
+                let inside = level_row >= 0
+                    && level_col >= 0
+                    && (level_row as u64) < level.height
+                    && (level_col as u64) < level.width;
+                dest[(row * width + col) as usize] = if inside {
+                    level.pixels[(level_row as u64 * level.width + level_col as u64) as usize]
+                } else {
+                    0
+                };
+            }
+        }
+        Ok(())
+    }
+
+    fn properties(&self) -> Result<HashMap<String, String>, OpenSlideError> {
+        let mut properties = HashMap::new();
+        properties.insert(String::from("openslide.vendor"), self.vendor.clone());
+        properties.insert(
+            String::from("openslide.level-count"),
+            self.levels.len().to_string(),
+        );
+        for (index, level) in self.levels.iter().enumerate() {
+            let prefix = format!("openslide.level[{}]", index);
+            properties.insert(format!("{}.width", prefix), level.width.to_string());
+            properties.insert(format!("{}.height", prefix), level.height.to_string());
+            properties.insert(format!("{}.downsample", prefix), level.downsample.to_string());
+            properties.insert(format!("{}.tile-width", prefix), self.tile_size.0.to_string());
+            properties.insert(format!("{}.tile-height", prefix), self.tile_size.1.to_string());
+        }
+        if let Some((mpp_x, mpp_y)) = self.mpp {
+            properties.insert(String::from("openslide.mpp-x"), mpp_x.to_string());
+            properties.insert(String::from("openslide.mpp-y"), mpp_y.to_string());
+        }
+        for (name, value) in &self.extra_properties {
+            properties.insert(name.clone(), value.clone());
+        }
+        Ok(properties)
+    }
+
+    fn associated_image_names(&self) -> Result<Vec<String>, OpenSlideError> {
+        let mut names = self.associated_images.keys().cloned().collect::<Vec<String>>();
+        names.sort();
+        Ok(names)
+    }
+
+    fn associated_image_dimensions(&self, name: &str) -> Result<(u64, u64), OpenSlideError> {
+        match self.associated_images.get(name) {
+            Some(&(width, height, _)) => Ok((width, height)),
+            None => Err(OpenSlideError::InvalidArgument(format!(
+                "Slide has no associated image named {}",
+                name
+            ))),
+        }
+    }
+
+    fn read_associated_image(&self, name: &str, dest: &mut [u32]) -> Result<(), OpenSlideError> {
+        let pixels = match self.associated_images.get(name) {
+            Some((_, _, pixels)) => pixels,
+            None => {
+                return Err(OpenSlideError::InvalidArgument(format!(
+                    "Slide has no associated image named {}",
+                    name
+                )))
+            }
+        };
+        if dest.len() < pixels.len() {
+            return Err(OpenSlideError::InvalidArgument(format!(
+                "Buffer of length {} is too small for associated image {} of {} pixels",
+                dest.len(),
+                name,
+                pixels.len()
+            )));
+        }
+        dest[..pixels.len()].copy_from_slice(pixels);
+        Ok(())
+    }
+}
+
+/// Convert an RGBA pixel into a pre-multiplied ARGB word.
+fn premultiply(pixel: Rgba<u8>) -> u32 {
+    let [red, green, blue, alpha] = pixel.data;
+    let scale = |value: u8| (u32::from(value) * u32::from(alpha) + 127) / 255;
+    u32::from(alpha) << 24 | scale(red) << 16 | scale(green) << 8 | scale(blue)
+}
+
+/// Compute a level by averaging `downsample x downsample` blocks of the level 0 pixels.
+///
+/// Blocks at the right and bottom edges may be smaller, and are averaged over the pixels that
+/// exist.
+fn downsample_level(base: &[u32], width: u64, height: u64, downsample: u32) -> Level {
+    let factor = u64::from(downsample);
+    let level_width = width.div_ceil(factor);
+    let level_height = height.div_ceil(factor);
+    if downsample == 1 {
+        return Level {
+            width,
+            height,
+            downsample,
+            pixels: base.to_vec(),
+        };
+    }
+
+    let mut pixels = Vec::with_capacity((level_width * level_height) as usize);
+    for level_row in 0..level_height {
+        let rows = level_row * factor..((level_row + 1) * factor).min(height);
+        for level_col in 0..level_width {
+            let cols = level_col * factor..((level_col + 1) * factor).min(width);
+            let mut sums = [0u64; 4];
+            for row in rows.clone() {
+                for col in cols.clone() {
+                    let word = base[(row * width + col) as usize];
+                    for (channel, sum) in sums.iter_mut().enumerate() {
+                        *sum += u64::from(word >> (24 - 8 * channel) & 0xff);
+                    }
+                }
+            }
+            let count = (rows.end - rows.start) * (cols.end - cols.start);
+            let word = sums
+                .iter()
+                .fold(0u32, |word, &sum| word << 8 | ((sum + count / 2) / count) as u32);
+            pixels.push(word);
+        }
+    }
+
+    Level {
+        width: level_width,
+        height: level_height,
+        downsample,
+        pixels,
+    }
+}
@@ -0,0 +1,121 @@
+//! Slide backends
+//!
+//! A backend is the reader that an `OpenSlide` object gets its levels, pixels, properties and
+//! associated images from. The default backend uses the OpenSlide C library, but any type that
+//! implements `SlideBackend` can be used with `OpenSlide::from_backend()`, e.g. a pure-Rust reader
+//! or a test double.
+//!
+//! The `MemoryBackend` builds a slide from an image in memory, such that code that uses `OpenSlide`
+//! can be tested without the OpenSlide C library or slide files.
+//!
+//! With the `tiff` feature, the `TiffBackend` reads Aperio SVS and generic tiled TIFF slides in
+//! pure Rust.
+//!
+
+mod libopenslide;
+mod memory;
+#[cfg(feature = "tiff")]
+mod tiff;
+
+use std::collections::HashMap;
+
+use error::OpenSlideError;
+#[cfg(openslide4)]
+pub use self::libopenslide::TileCache;
+pub use self::libopenslide::LibOpenSlideBackend;
+pub use self::memory::MemoryBackend;
+#[cfg(feature = "tiff")]
+pub use self::tiff::TiffBackend;
+
+/// The interface of a slide reader.
+///
+/// Levels are numbered from 0 (the largest) to `level_count() - 1`. Pixels are pre-multiplied
+/// ARGB, with one `u32` per pixel (`0xAARRGGBB`), in row-major order, just as in the OpenSlide C
+/// library.
+///
+/// The `OpenSlide` front-end checks level numbers against `level_count()` and clips regions to the
+/// level before it calls the backend, but a backend should still return an error in stead of
+/// panicking on invalid arguments.
+pub trait SlideBackend: Send + Sync {
+    /// Get the number of levels in the whole slide image.
+    fn level_count(&self) -> Result<u32, OpenSlideError>;
+
+    /// Get the (width, height) dimensions of the given level.
+    fn level_dimensions(&self, level: u32) -> Result<(u64, u64), OpenSlideError>;
+
+    /// Get the downsampling factor of the given level, relative to level 0.
+    fn level_downsample(&self, level: u32) -> Result<f64, OpenSlideError>;
+
+    /// Get the best level to use for displaying the given downsample factor.
+    ///
+    /// The default implementation picks the largest level with a downsample factor that is not
+    /// larger than the given one, as OpenSlide does.
+    fn best_level_for_downsample(&self, downsample: f64) -> Result<u32, OpenSlideError> {
+        let level_count = self.level_count()?;
+        for level in 1..level_count {
+            if downsample < self.level_downsample(level)? {
+                return Ok(level - 1);
+            }
+        }
+        Ok(level_count.saturating_sub(1))
+    }
+
+    /// Copy pre-multiplied ARGB data from a region of the whole slide image into `dest`.
+    ///
+    /// The top left corner (`x`, `y`) is given in the level 0 reference frame, and the region is
+    /// `width * height` pixels at the given level. `dest` must have room for at least
+    /// `width * height` pixels. Pixels outside the slide are transparent (0).
+    fn read_region(
+        &self,
+        dest: &mut [u32],
+        x: i64,
+        y: i64,
+        level: u32,
+        width: u64,
+        height: u64,
+    ) -> Result<(), OpenSlideError>;
+
+    /// Get all properties of the slide as key-value pairs.
+    fn properties(&self) -> Result<HashMap<String, String>, OpenSlideError>;
+
+    /// Get the names of the associated images of the slide.
+    fn associated_image_names(&self) -> Result<Vec<String>, OpenSlideError>;
+
+    /// Get the (width, height) dimensions of the associated image with the given name.
+    fn associated_image_dimensions(&self, name: &str) -> Result<(u64, u64), OpenSlideError>;
+
+    /// Copy pre-multiplied ARGB data from the associated image with the given name into `dest`,
+    /// which must have room for at least `width * height` pixels.
+    fn read_associated_image(&self, name: &str, dest: &mut [u32]) -> Result<(), OpenSlideError>;
+
+    /// Get the ICC color profile of the whole slide image, or `None` if the slide has none.
+    ///
+    /// The default implementation returns an `Unsupported` error.
+    fn icc_profile(&self) -> Result<Option<Vec<u8>>, OpenSlideError> {
+        Err(OpenSlideError::Unsupported(String::from(
+            "The slide backend does not support ICC profiles",
+        )))
+    }
+
+    /// Get the ICC color profile of the associated image with the given name, or `None` if it has
+    /// none.
+    ///
+    /// The default implementation returns an `Unsupported` error.
+    fn associated_image_icc_profile(&self, name: &str) -> Result<Option<Vec<u8>>, OpenSlideError> {
+        Err(OpenSlideError::Unsupported(format!(
+            "The slide backend does not support ICC profiles (associated image {})",
+            name
+        )))
+    }
+
+    /// Use the given OpenSlide tile cache in stead of the private cache of the backend.
+    ///
+    /// The default implementation returns an `Unsupported` error.
+    #[cfg(openslide4)]
+    fn set_cache(&self, cache: &TileCache) -> Result<(), OpenSlideError> {
+        let _ = cache;
+        Err(OpenSlideError::Unsupported(String::from(
+            "The slide backend does not support OpenSlide tile caches",
+        )))
+    }
+}
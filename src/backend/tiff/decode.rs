@@ -0,0 +1,234 @@
+//! Decoding of TIFF tiles and strips into pre-multiplied ARGB
+//!
+
+use weezl;
+
+use super::ifd::{self, Directory};
+use super::jpeg::{self, ColorSpace};
+use error::OpenSlideError;
+
+const COMPRESSION_NONE: u64 = 1;
+const COMPRESSION_LZW: u64 = 5;
+const COMPRESSION_JPEG: u64 = 7;
+
+const PHOTOMETRIC_MIN_IS_BLACK: u64 = 1;
+const PHOTOMETRIC_RGB: u64 = 2;
+const PHOTOMETRIC_YCBCR: u64 = 6;
+
+const PREDICTOR_HORIZONTAL: u64 = 2;
+
+const EXTRA_SAMPLE_ASSOCIATED_ALPHA: u64 = 1;
+
+/// Maximum number of pixels of a tile or strip, which bounds the memory that a corrupt file can
+/// make a read allocate: 64 megapixels, or 256 MiB of ARGB pixels.
+const MAX_PIXELS: u64 = 1 << 26;
+
+/// How the pixels of a directory are encoded.
+///
+/// Only 8 bit, chunky (interleaved) images are supported, which covers Aperio slides and most
+/// other tiled TIFF pyramids.
+#[derive(Clone, Debug)]
+pub struct Encoding {
+    compression: u64,
+    photometric: u64,
+    samples_per_pixel: u64,
+    predictor: u64,
+    associated_alpha: bool,
+    jpeg_tables: Option<Vec<u8>>,
+}
+
+impl Encoding {
+    /// Read the encoding of the given directory, or return an `Unsupported` error if this backend
+    /// can not decode it.
+    pub fn from_directory(directory: &Directory) -> Result<Encoding, OpenSlideError> {
+        let compression = directory.u64(ifd::COMPRESSION).unwrap_or(COMPRESSION_NONE);
+        if ![COMPRESSION_NONE, COMPRESSION_LZW, COMPRESSION_JPEG].contains(&compression) {
+            return Err(unsupported(format!("compression {}", compression)));
+        }
+
+        let samples_per_pixel = directory.u64(ifd::SAMPLES_PER_PIXEL).unwrap_or(1);
+        let photometric =
+            directory
+                .u64(ifd::PHOTOMETRIC_INTERPRETATION)
+                .unwrap_or(if samples_per_pixel >= 3 {
+                    PHOTOMETRIC_RGB
+                } else {
+                    PHOTOMETRIC_MIN_IS_BLACK
+                });
+        let supported_photometric = match photometric {
+            PHOTOMETRIC_MIN_IS_BLACK => samples_per_pixel == 1,
+            PHOTOMETRIC_RGB => samples_per_pixel == 3 || samples_per_pixel == 4,
+            PHOTOMETRIC_YCBCR => samples_per_pixel == 3 && compression == COMPRESSION_JPEG,
+            _ => false,
+        };
+        if !supported_photometric {
+            return Err(unsupported(format!(
+                "photometric interpretation {} with {} samples per pixel",
+                photometric, samples_per_pixel
+            )));
+        }
+
+        let bits = directory
+            .u64s(ifd::BITS_PER_SAMPLE)
+            .unwrap_or_else(|| vec![1]);
+        if bits.iter().any(|&bits| bits != 8) {
+            return Err(unsupported(format!("{:?} bits per sample", bits)));
+        }
+        if directory.u64(ifd::PLANAR_CONFIGURATION).unwrap_or(1) != 1 {
+            return Err(unsupported(String::from("planar sample configuration")));
+        }
+
+        Ok(Encoding {
+            compression,
+            photometric,
+            samples_per_pixel,
+            predictor: directory.u64(ifd::PREDICTOR).unwrap_or(1),
+            associated_alpha: directory.u64(ifd::EXTRA_SAMPLES)
+                == Some(EXTRA_SAMPLE_ASSOCIATED_ALPHA),
+            jpeg_tables: directory
+                .bytes(ifd::JPEG_TABLES)
+                .map(|tables| tables.to_vec()),
+        })
+    }
+
+    /// Decode one tile or strip of `width x height` pixels into pre-multiplied ARGB.
+    ///
+    /// If the encoded image is smaller than `width x height`, the missing pixels are transparent.
+    pub fn decode(&self, data: &[u8], width: u32, height: u32) -> Result<Vec<u32>, OpenSlideError> {
+        let pixel_count = pixel_count(u64::from(width), u64::from(height))?;
+        let (samples, samples_per_pixel, data_width, data_height) = match self.compression {
+            COMPRESSION_JPEG => self.decode_jpeg(data, width, height)?,
+            COMPRESSION_LZW => {
+                let mut samples =
+                    weezl::decode::Decoder::with_tiff_size_switch(weezl::BitOrder::Msb, 8)
+                        .decode(data)
+                        .map_err(|err| {
+                            OpenSlideError::Library(format!("LZW decoding failed: {}", err))
+                        })?;
+                self.undo_predictor(&mut samples, width);
+                (samples, self.samples_per_pixel as usize, width, height)
+            }
+            _ => {
+                let mut samples = data.to_vec();
+                self.undo_predictor(&mut samples, width);
+                (samples, self.samples_per_pixel as usize, width, height)
+            }
+        };
+
+        let mut pixels = vec![0u32; pixel_count];
+        let rows = height.min(data_height) as usize;
+        let cols = width.min(data_width) as usize;
+        let row_len = data_width as usize * samples_per_pixel;
+        for row in 0..rows {
+            let start = row * row_len;
+            if start >= samples.len() {
+                break;
+            }
+            let row_samples = &samples[start..samples.len().min(start + row_len)];
+            let dest = &mut pixels[row * width as usize..row * width as usize + cols];
+            for (pixel, sample) in dest.iter_mut().zip(row_samples.chunks(samples_per_pixel)) {
+                *pixel = self.to_argb(sample);
+            }
+        }
+        Ok(pixels)
+    }
+
+    /// Decode a JPEG tile or strip of at most `width x height` pixels, returning the samples, the
+    /// number of samples per pixel, and the dimensions.
+    fn decode_jpeg(
+        &self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+    ) -> Result<(Vec<u8>, usize, u32, u32), OpenSlideError> {
+        // The tables are stored once in the directory, as an abbreviated JPEG stream. They are
+        // spliced in front of the tile, without the end of image marker of the tables and the
+        // start of image marker of the tile.
+        let stream = match self.jpeg_tables {
+            Some(ref tables) if tables.len() >= 4 && data.len() >= 2 => {
+                let mut stream = Vec::with_capacity(tables.len() + data.len());
+                stream.extend_from_slice(&tables[..tables.len() - 2]);
+                stream.extend_from_slice(&data[2..]);
+                stream
+            }
+            _ => data.to_vec(),
+        };
+
+        let image = jpeg::decode(
+            &stream,
+            match self.photometric {
+                PHOTOMETRIC_YCBCR => ColorSpace::YCbCr,
+                PHOTOMETRIC_MIN_IS_BLACK => ColorSpace::Gray,
+                // Aperio stores RGB data in the JPEG tiles, which must not be converted from YCbCr
+                _ => ColorSpace::Rgb,
+            },
+            (width as usize, height as usize),
+        )?;
+        Ok((
+            image.samples,
+            image.samples_per_pixel,
+            image.width,
+            image.height,
+        ))
+    }
+
+    /// Undo horizontal differencing, row by row.
+    fn undo_predictor(&self, samples: &mut [u8], width: u32) {
+        if self.predictor != PREDICTOR_HORIZONTAL {
+            return;
+        }
+        let samples_per_pixel = self.samples_per_pixel as usize;
+        let row_len = width as usize * samples_per_pixel;
+        for row in samples.chunks_mut(row_len) {
+            for index in samples_per_pixel..row.len() {
+                row[index] = row[index].wrapping_add(row[index - samples_per_pixel]);
+            }
+        }
+    }
+
+    /// Convert the samples of one pixel into a pre-multiplied ARGB word.
+    fn to_argb(&self, sample: &[u8]) -> u32 {
+        match sample.len() {
+            0 => 0,
+            1 | 2 => {
+                let gray = u32::from(sample[0]);
+                0xff00_0000 | gray << 16 | gray << 8 | gray
+            }
+            3 => {
+                0xff00_0000
+                    | u32::from(sample[0]) << 16
+                    | u32::from(sample[1]) << 8
+                    | u32::from(sample[2])
+            }
+            _ => {
+                let alpha = u32::from(sample[3]);
+                let scale = |value: u8| {
+                    if self.associated_alpha {
+                        u32::from(value)
+                    } else {
+                        (u32::from(value) * alpha + 127) / 255
+                    }
+                };
+                alpha << 24 | scale(sample[0]) << 16 | scale(sample[1]) << 8 | scale(sample[2])
+            }
+        }
+    }
+}
+
+fn unsupported(what: String) -> OpenSlideError {
+    OpenSlideError::Unsupported(format!("TIFF images with {} are not supported", what))
+}
+
+/// Number of pixels of a tile or strip, or an error if it has more than `MAX_PIXELS`.
+pub fn pixel_count(width: u64, height: u64) -> Result<usize, OpenSlideError> {
+    width
+        .checked_mul(height)
+        .filter(|&count| count <= MAX_PIXELS)
+        .map(|count| count as usize)
+        .ok_or_else(|| {
+            OpenSlideError::Library(format!(
+                "Corrupt TIFF file: Tile or strip of {} x {} pixels is too large",
+                width, height
+            ))
+        })
+}
@@ -0,0 +1,303 @@
+//! Parser for the image file directories (IFDs) of TIFF and BigTIFF files
+//!
+
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+use error::OpenSlideError;
+
+// Tags that are used by the backend
+pub const IMAGE_WIDTH: u16 = 256;
+pub const IMAGE_LENGTH: u16 = 257;
+pub const BITS_PER_SAMPLE: u16 = 258;
+pub const COMPRESSION: u16 = 259;
+pub const PHOTOMETRIC_INTERPRETATION: u16 = 262;
+pub const DOCUMENT_NAME: u16 = 269;
+pub const IMAGE_DESCRIPTION: u16 = 270;
+pub const MAKE: u16 = 271;
+pub const MODEL: u16 = 272;
+pub const STRIP_OFFSETS: u16 = 273;
+pub const SAMPLES_PER_PIXEL: u16 = 277;
+pub const ROWS_PER_STRIP: u16 = 278;
+pub const STRIP_BYTE_COUNTS: u16 = 279;
+pub const X_RESOLUTION: u16 = 282;
+pub const Y_RESOLUTION: u16 = 283;
+pub const PLANAR_CONFIGURATION: u16 = 284;
+pub const RESOLUTION_UNIT: u16 = 296;
+pub const SOFTWARE: u16 = 305;
+pub const DATE_TIME: u16 = 306;
+pub const ARTIST: u16 = 315;
+pub const HOST_COMPUTER: u16 = 316;
+pub const PREDICTOR: u16 = 317;
+pub const TILE_WIDTH: u16 = 322;
+pub const TILE_LENGTH: u16 = 323;
+pub const TILE_OFFSETS: u16 = 324;
+pub const TILE_BYTE_COUNTS: u16 = 325;
+pub const EXTRA_SAMPLES: u16 = 338;
+pub const JPEG_TABLES: u16 = 347;
+pub const COPYRIGHT: u16 = 33432;
+
+/// Maximum number of directories that are read, as a guard against cyclic directory chains.
+const MAX_DIRECTORIES: usize = 4096;
+
+/// The byte order of a TIFF file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+impl Endian {
+    fn u16(self, buf: &[u8]) -> u16 {
+        match self {
+            Endian::Little => LittleEndian::read_u16(buf),
+            Endian::Big => BigEndian::read_u16(buf),
+        }
+    }
+
+    fn u32(self, buf: &[u8]) -> u32 {
+        match self {
+            Endian::Little => LittleEndian::read_u32(buf),
+            Endian::Big => BigEndian::read_u32(buf),
+        }
+    }
+
+    fn u64(self, buf: &[u8]) -> u64 {
+        match self {
+            Endian::Little => LittleEndian::read_u64(buf),
+            Endian::Big => BigEndian::read_u64(buf),
+        }
+    }
+}
+
+/// The raw value of a directory entry.
+#[derive(Clone, Debug)]
+struct Entry {
+    field_type: u16,
+    count: u64,
+    data: Vec<u8>,
+}
+
+/// One image file directory, with all its entries.
+#[derive(Clone, Debug)]
+pub struct Directory {
+    endian: Endian,
+    entries: HashMap<u16, Entry>,
+}
+
+impl Directory {
+    /// Get an unsigned integer field (BYTE, SHORT, LONG, LONG8 or IFD) as a vector.
+    pub fn u64s(&self, tag: u16) -> Option<Vec<u64>> {
+        let entry = self.entries.get(&tag)?;
+        let size = match entry.field_type {
+            1 | 7 => 1,
+            3 => 2,
+            4 | 13 => 4,
+            16 | 18 => 8,
+            _ => return None,
+        };
+        let values = entry
+            .data
+            .chunks(size)
+            .take(entry.count as usize)
+            .map(|chunk| match size {
+                1 => u64::from(chunk[0]),
+                2 => u64::from(self.endian.u16(chunk)),
+                4 => u64::from(self.endian.u32(chunk)),
+                _ => self.endian.u64(chunk),
+            })
+            .collect();
+        Some(values)
+    }
+
+    /// Get the first value of an unsigned integer field.
+    pub fn u64(&self, tag: u16) -> Option<u64> {
+        self.u64s(tag).and_then(|values| values.first().cloned())
+    }
+
+    /// Get the first value of a RATIONAL, FLOAT or DOUBLE field.
+    pub fn f64(&self, tag: u16) -> Option<f64> {
+        let entry = self.entries.get(&tag)?;
+        match entry.field_type {
+            5 if entry.data.len() >= 8 => {
+                let numerator = self.endian.u32(&entry.data[0..4]);
+                let denominator = self.endian.u32(&entry.data[4..8]);
+                Some(f64::from(numerator) / f64::from(denominator))
+            }
+            11 if entry.data.len() >= 4 => Some(f64::from(f32::from_bits(
+                self.endian.u32(&entry.data[0..4]),
+            ))),
+            12 if entry.data.len() >= 8 => Some(f64::from_bits(self.endian.u64(&entry.data[0..8]))),
+            _ => self.u64(tag).map(|value| value as f64),
+        }
+    }
+
+    /// Get an ASCII field as a string, without the terminating NUL.
+    pub fn string(&self, tag: u16) -> Option<String> {
+        let entry = self.entries.get(&tag)?;
+        if entry.field_type != 2 {
+            return None;
+        }
+        let end = entry
+            .data
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(entry.data.len());
+        Some(String::from_utf8_lossy(&entry.data[..end]).into_owned())
+    }
+
+    /// Get the raw bytes of a field, e.g. the JPEG tables.
+    pub fn bytes(&self, tag: u16) -> Option<&[u8]> {
+        self.entries.get(&tag).map(|entry| &entry.data[..])
+    }
+
+    /// Whether the image in this directory is stored in tiles (as opposed to strips).
+    pub fn is_tiled(&self) -> bool {
+        self.entries.contains_key(&TILE_WIDTH) && self.entries.contains_key(&TILE_OFFSETS)
+    }
+}
+
+/// Read all directories of a TIFF or BigTIFF file.
+///
+/// Returns an `UnsupportedFormat` error if the file is not a TIFF file.
+pub fn read_directories<R: Read + Seek>(
+    reader: &mut R,
+    file_len: u64,
+) -> Result<Vec<Directory>, OpenSlideError> {
+    let mut header = [0u8; 16];
+    read_exact_at(reader, 0, &mut header[..8])?;
+    let endian = match &header[0..2] {
+        b"II" => Endian::Little,
+        b"MM" => Endian::Big,
+        _ => return Err(not_tiff()),
+    };
+    let big = match endian.u16(&header[2..4]) {
+        42 => false,
+        43 => true,
+        _ => return Err(not_tiff()),
+    };
+    let mut offset = if big {
+        read_exact_at(reader, 0, &mut header)?;
+        if endian.u16(&header[4..6]) != 8 {
+            return Err(not_tiff());
+        }
+        endian.u64(&header[8..16])
+    } else {
+        u64::from(endian.u32(&header[4..8]))
+    };
+
+    let (count_size, entry_size, offset_size) = if big { (8, 20, 8) } else { (2, 12, 4) };
+    let mut directories = Vec::new();
+    while offset != 0 {
+        if directories.len() >= MAX_DIRECTORIES {
+            return Err(corrupt("Too many directories"));
+        }
+
+        let mut count_buf = [0u8; 8];
+        read_exact_at(reader, offset, &mut count_buf[..count_size])?;
+        let count = if big {
+            endian.u64(&count_buf)
+        } else {
+            u64::from(endian.u16(&count_buf))
+        };
+        let end = count
+            .checked_mul(entry_size as u64)
+            .and_then(|len| len.checked_add(offset));
+        if end.is_none_or(|end| end > file_len) {
+            return Err(corrupt("Directory extends beyond the end of the file"));
+        }
+
+        let mut buf = vec![0u8; count as usize * entry_size + offset_size];
+        read_exact_at(reader, offset + count_size as u64, &mut buf)?;
+
+        let mut entries = HashMap::new();
+        for raw in buf.chunks(entry_size).take(count as usize) {
+            let tag = endian.u16(&raw[0..2]);
+            let field_type = endian.u16(&raw[2..4]);
+            let (value_count, value) = if big {
+                (endian.u64(&raw[4..12]), &raw[12..20])
+            } else {
+                (u64::from(endian.u32(&raw[4..8])), &raw[8..12])
+            };
+            let type_size = match type_size(field_type) {
+                Some(size) => size,
+                // Unknown field types are skipped, as the TIFF specification requires
+                None => continue,
+            };
+            let len = value_count
+                .checked_mul(type_size)
+                .filter(|&len| len <= file_len)
+                .ok_or_else(|| corrupt("Directory entry is larger than the file"))?;
+            let data = if len <= value.len() as u64 {
+                value[..len as usize].to_vec()
+            } else {
+                let value_offset = if big {
+                    endian.u64(value)
+                } else {
+                    u64::from(endian.u32(value))
+                };
+                let mut data = vec![0u8; len as usize];
+                read_exact_at(reader, value_offset, &mut data)?;
+                data
+            };
+            entries.insert(
+                tag,
+                Entry {
+                    field_type,
+                    count: value_count,
+                    data,
+                },
+            );
+        }
+        directories.push(Directory { endian, entries });
+
+        let next = &buf[count as usize * entry_size..];
+        offset = if big {
+            endian.u64(next)
+        } else {
+            u64::from(endian.u32(next))
+        };
+    }
+
+    Ok(directories)
+}
+
+/// Read exactly `buf.len()` bytes at the given offset.
+pub fn read_exact_at<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    buf: &mut [u8],
+) -> Result<(), OpenSlideError> {
+    reader
+        .seek(SeekFrom::Start(offset))
+        .and_then(|_| reader.read_exact(buf))
+        .map_err(|err| {
+            OpenSlideError::Library(format!(
+                "Could not read {} bytes at offset {}: {}",
+                buf.len(),
+                offset,
+                err
+            ))
+        })
+}
+
+/// Size in bytes of one value of the given field type.
+fn type_size(field_type: u16) -> Option<u64> {
+    match field_type {
+        1 | 2 | 6 | 7 => Some(1),
+        3 | 8 => Some(2),
+        4 | 9 | 11 | 13 => Some(4),
+        5 | 10 | 12 | 16 | 17 | 18 => Some(8),
+        _ => None,
+    }
+}
+
+fn not_tiff() -> OpenSlideError {
+    OpenSlideError::UnsupportedFormat(String::from("Not a TIFF file"))
+}
+
+fn corrupt(msg: &str) -> OpenSlideError {
+    OpenSlideError::Library(format!("Corrupt TIFF file: {}", msg))
+}
@@ -0,0 +1,858 @@
+//! Baseline JPEG decoder for TIFF tiles and strips
+//!
+//! The inverse DCT, the upsampling and the YCbCr conversion follow the default ("islow") code
+//! paths of libjpeg, which OpenSlide uses, such that the decoded pixels are identical to those of
+//! the OpenSlide C library. Only baseline and extended sequential Huffman coded JPEGs with 8 bit
+//! samples are supported, which is what TIFF files contain in practice.
+//!
+
+use error::OpenSlideError;
+
+/// Natural (row-major) index of the coefficients in zig-zag order.
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// Number of bits that are looked up at once when decoding Huffman codes.
+const LOOKAHEAD: u32 = 9;
+
+/// Color space of the decoded samples.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorSpace {
+    /// One gray sample per pixel
+    Gray,
+    /// Three samples per pixel, that are returned as they are
+    Rgb,
+    /// Three samples per pixel, that are converted from YCbCr to RGB
+    YCbCr,
+}
+
+/// A decoded image, with interleaved samples.
+pub struct DecodedImage {
+    pub width: u32,
+    pub height: u32,
+    pub samples_per_pixel: usize,
+    pub samples: Vec<u8>,
+}
+
+#[derive(Clone)]
+struct HuffmanTable {
+    /// (code length, value) of the codes that are at most `LOOKAHEAD` bits long, indexed by the
+    /// next `LOOKAHEAD` bits
+    lookup: Vec<(u8, u8)>,
+    /// Largest code of each length (1 to 16), or -1 if there is none
+    max_code: [i32; 17],
+    /// Offset from a code of each length to the index of its value
+    value_offset: [i32; 17],
+    values: Vec<u8>,
+}
+
+impl HuffmanTable {
+    fn new(counts: &[u8; 16], values: Vec<u8>) -> Result<HuffmanTable, OpenSlideError> {
+        let mut lookup = vec![(0u8, 0u8); 1 << LOOKAHEAD];
+        let mut max_code = [-1i32; 17];
+        let mut value_offset = [0i32; 17];
+        let mut code = 0i32;
+        let mut index = 0usize;
+        for length in 1..17 {
+            let count = counts[length - 1] as usize;
+            if index + count > values.len() {
+                return Err(corrupt("Huffman table has too few values"));
+            }
+            // An over-subscribed table has more codes than fit in the length, which would also
+            // index past the lookup table
+            if code + count as i32 > 1 << length {
+                return Err(corrupt("Huffman table has invalid code lengths"));
+            }
+            value_offset[length] = index as i32 - code;
+            for _ in 0..count {
+                if length as u32 <= LOOKAHEAD {
+                    let shift = LOOKAHEAD - length as u32;
+                    let first = (code as usize) << shift;
+                    for entry in &mut lookup[first..first + (1 << shift)] {
+                        *entry = (length as u8, values[index]);
+                    }
+                }
+                code += 1;
+                index += 1;
+            }
+            if count > 0 {
+                max_code[length] = code - 1;
+            }
+            code <<= 1;
+        }
+        Ok(HuffmanTable {
+            lookup,
+            max_code,
+            value_offset,
+            values,
+        })
+    }
+}
+
+/// Reader of the entropy coded data, which removes stuffed bytes and stops at markers.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    buffer: u64,
+    bits: u32,
+    /// A marker was found, and only zeros are returned until `reset()`
+    at_marker: bool,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8], position: usize) -> BitReader<'a> {
+        BitReader {
+            data,
+            position,
+            buffer: 0,
+            bits: 0,
+            at_marker: false,
+        }
+    }
+
+    fn fill(&mut self) {
+        while self.bits <= 56 {
+            let byte = if self.at_marker || self.position >= self.data.len() {
+                0
+            } else {
+                let byte = self.data[self.position];
+                if byte == 0xff {
+                    match self.data.get(self.position + 1) {
+                        Some(&0x00) => {
+                            self.position += 2;
+                            0xff
+                        }
+                        _ => {
+                            self.at_marker = true;
+                            0
+                        }
+                    }
+                } else {
+                    self.position += 1;
+                    byte
+                }
+            };
+            self.buffer |= u64::from(byte) << (56 - self.bits);
+            self.bits += 8;
+        }
+    }
+
+    fn peek(&mut self, count: u32) -> u32 {
+        if self.bits < count {
+            self.fill();
+        }
+        (self.buffer >> (64 - count)) as u32
+    }
+
+    fn consume(&mut self, count: u32) {
+        self.buffer <<= count;
+        self.bits -= count;
+    }
+
+    fn receive(&mut self, count: u32) -> i32 {
+        if count == 0 {
+            return 0;
+        }
+        let value = self.peek(count);
+        self.consume(count);
+        value as i32
+    }
+
+    /// Read `count` bits, and sign-extend them as in the JPEG specification (F.2.2.1).
+    fn receive_extend(&mut self, count: u32) -> i32 {
+        let value = self.receive(count);
+        if count > 0 && value < 1 << (count - 1) {
+            value - (1 << count) + 1
+        } else {
+            value
+        }
+    }
+
+    fn decode(&mut self, table: &HuffmanTable) -> Result<u8, OpenSlideError> {
+        let bits = self.peek(LOOKAHEAD);
+        let (length, value) = table.lookup[bits as usize];
+        if length > 0 {
+            self.consume(u32::from(length));
+            return Ok(value);
+        }
+        let code = self.peek(16);
+        for length in LOOKAHEAD as usize + 1..17 {
+            let prefix = (code >> (16 - length)) as i32;
+            if prefix <= table.max_code[length] {
+                self.consume(length as u32);
+                let index = (prefix + table.value_offset[length]) as usize;
+                return table
+                    .values
+                    .get(index)
+                    .cloned()
+                    .ok_or_else(|| corrupt("Bad Huffman code"));
+            }
+        }
+        Err(corrupt("Bad Huffman code"))
+    }
+
+    /// Skip to the restart marker, and start reading after it.
+    fn restart(&mut self) -> Result<(), OpenSlideError> {
+        self.buffer = 0;
+        self.bits = 0;
+        self.at_marker = false;
+        while self.position + 1 < self.data.len() {
+            if self.data[self.position] == 0xff {
+                let marker = self.data[self.position + 1];
+                if (0xd0..=0xd7).contains(&marker) {
+                    self.position += 2;
+                    return Ok(());
+                }
+                if marker != 0x00 && marker != 0xff {
+                    break;
+                }
+            }
+            self.position += 1;
+        }
+        Err(corrupt("Missing restart marker"))
+    }
+
+    /// Position of the next marker after the entropy coded data.
+    fn marker_position(&self) -> usize {
+        let mut position = self.position;
+        while position + 1 < self.data.len() {
+            if self.data[position] == 0xff && self.data[position + 1] != 0x00 {
+                return position;
+            }
+            position += 1;
+        }
+        self.data.len()
+    }
+}
+
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    quant_table: usize,
+    dc_table: usize,
+    ac_table: usize,
+    dc_pred: i32,
+    /// Width of the sample plane, which is padded to whole MCUs
+    plane_width: usize,
+    plane: Vec<u8>,
+}
+
+/// Decode a JPEG stream. `color_space` tells how three component images are to be interpreted,
+/// as TIFF files specify this in their photometric interpretation in stead of in the JPEG stream.
+///
+/// Images larger than `max_size`, the size of the tile or strip that holds the stream, are
+/// rejected, such that a corrupt frame header can not make the decoder allocate any amount of
+/// memory.
+pub fn decode(
+    data: &[u8],
+    color_space: ColorSpace,
+    max_size: (usize, usize),
+) -> Result<DecodedImage, OpenSlideError> {
+    if data.len() < 4 || data[0] != 0xff || data[1] != 0xd8 {
+        return Err(corrupt("Missing start of image marker"));
+    }
+
+    let mut quant_tables = [[0u16; 64]; 4];
+    let mut dc_tables: Vec<Option<HuffmanTable>> = vec![None; 4];
+    let mut ac_tables: Vec<Option<HuffmanTable>> = vec![None; 4];
+    let mut components: Vec<Component> = Vec::new();
+    let mut size = (0usize, 0usize);
+    let mut max_sampling = (1usize, 1usize);
+    let mut restart_interval = 0usize;
+    let mut position = 2;
+
+    loop {
+        // Find the next marker, skipping fill bytes
+        while position < data.len() && data[position] != 0xff {
+            position += 1;
+        }
+        while position < data.len() && data[position] == 0xff {
+            position += 1;
+        }
+        if position >= data.len() {
+            break;
+        }
+        let marker = data[position];
+        position += 1;
+        match marker {
+            0xd8 | 0x01 | 0xd0..=0xd7 => continue,
+            0xd9 => break,
+            _ => {}
+        }
+
+        if position + 2 > data.len() {
+            return Err(corrupt("Truncated marker segment"));
+        }
+        let length = (usize::from(data[position]) << 8 | usize::from(data[position + 1])).max(2);
+        let segment = data
+            .get(position + 2..position + length)
+            .ok_or_else(|| corrupt("Truncated marker segment"))?;
+        position += length;
+
+        match marker {
+            // DQT
+            0xdb => {
+                let mut rest = segment;
+                while !rest.is_empty() {
+                    let precision = rest[0] >> 4;
+                    let id = usize::from(rest[0] & 0x0f);
+                    let entry_size = if precision == 0 { 1 } else { 2 };
+                    if id > 3 || rest.len() < 1 + 64 * entry_size {
+                        return Err(corrupt("Invalid quantization table"));
+                    }
+                    for (index, &natural) in ZIGZAG.iter().enumerate() {
+                        quant_tables[id][natural] = if entry_size == 1 {
+                            u16::from(rest[1 + index])
+                        } else {
+                            u16::from(rest[1 + 2 * index]) << 8 | u16::from(rest[2 + 2 * index])
+                        };
+                    }
+                    rest = &rest[1 + 64 * entry_size..];
+                }
+            }
+            // DHT
+            0xc4 => {
+                let mut rest = segment;
+                while rest.len() >= 17 {
+                    let class = rest[0] >> 4;
+                    let id = usize::from(rest[0] & 0x0f);
+                    let mut counts = [0u8; 16];
+                    counts.copy_from_slice(&rest[1..17]);
+                    let total = counts.iter().map(|&count| count as usize).sum::<usize>();
+                    if id > 3 || class > 1 || rest.len() < 17 + total {
+                        return Err(corrupt("Invalid Huffman table"));
+                    }
+                    let table = HuffmanTable::new(&counts, rest[17..17 + total].to_vec())?;
+                    if class == 0 {
+                        dc_tables[id] = Some(table);
+                    } else {
+                        ac_tables[id] = Some(table);
+                    }
+                    rest = &rest[17 + total..];
+                }
+            }
+            // DRI
+            0xdd => {
+                if segment.len() < 2 {
+                    return Err(corrupt("Invalid restart interval"));
+                }
+                restart_interval = usize::from(segment[0]) << 8 | usize::from(segment[1]);
+            }
+            // SOF0 and SOF1 (baseline and extended sequential, Huffman coded)
+            0xc0 | 0xc1 => {
+                if !components.is_empty() {
+                    return Err(corrupt("More than one frame header"));
+                }
+                if segment.len() < 6 || segment[0] != 8 {
+                    return Err(unsupported("JPEG with other than 8 bit samples"));
+                }
+                let height = usize::from(segment[1]) << 8 | usize::from(segment[2]);
+                let width = usize::from(segment[3]) << 8 | usize::from(segment[4]);
+                let count = usize::from(segment[5]);
+                if width == 0 || height == 0 || (count != 1 && count != 3) {
+                    return Err(unsupported("JPEG with other than 1 or 3 components"));
+                }
+                if width > max_size.0 || height > max_size.1 {
+                    return Err(corrupt("Image is larger than its tile or strip"));
+                }
+                if segment.len() < 6 + 3 * count {
+                    return Err(corrupt("Truncated frame header"));
+                }
+                for index in 0..count {
+                    let raw = &segment[6 + 3 * index..9 + 3 * index];
+                    let (h, v) = (usize::from(raw[1] >> 4), usize::from(raw[1] & 0x0f));
+                    if h == 0 || v == 0 || h > 4 || v > 4 || raw[2] > 3 {
+                        return Err(corrupt("Invalid component in frame header"));
+                    }
+                    max_sampling = (max_sampling.0.max(h), max_sampling.1.max(v));
+                    components.push(Component {
+                        id: raw[0],
+                        h,
+                        v,
+                        quant_table: usize::from(raw[2]),
+                        dc_table: 0,
+                        ac_table: 0,
+                        dc_pred: 0,
+                        plane_width: 0,
+                        plane: Vec::new(),
+                    });
+                }
+                // Components are upsampled by whole factors, as in libjpeg
+                if components.iter().any(|component| {
+                    !max_sampling.0.is_multiple_of(component.h)
+                        || !max_sampling.1.is_multiple_of(component.v)
+                }) {
+                    return Err(unsupported("JPEG with fractional sampling"));
+                }
+                size = (width, height);
+                let mcus_x = width.div_ceil(8 * max_sampling.0);
+                let mcus_y = height.div_ceil(8 * max_sampling.1);
+                for component in &mut components {
+                    component.plane_width = mcus_x * component.h * 8;
+                    component.plane = vec![0; component.plane_width * mcus_y * component.v * 8];
+                }
+            }
+            0xc2 | 0xc3 | 0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf => {
+                return Err(unsupported(
+                    "progressive, lossless or arithmetic coded JPEG",
+                ));
+            }
+            // SOS
+            0xda => {
+                if components.is_empty() {
+                    return Err(corrupt("Scan before frame header"));
+                }
+                let count = usize::from(*segment.first().unwrap_or(&0));
+                if count == 0 || segment.len() < 1 + 2 * count {
+                    return Err(corrupt("Invalid scan header"));
+                }
+                let mut scan = Vec::with_capacity(count);
+                for index in 0..count {
+                    let id = segment[1 + 2 * index];
+                    let tables = segment[2 + 2 * index];
+                    let component = components
+                        .iter()
+                        .position(|component| component.id == id)
+                        .ok_or_else(|| corrupt("Scan refers to an unknown component"))?;
+                    components[component].dc_table = usize::from(tables >> 4) & 3;
+                    components[component].ac_table = usize::from(tables & 0x0f) & 3;
+                    scan.push(component);
+                }
+                let mut reader = BitReader::new(data, position);
+                decode_scan(
+                    &mut reader,
+                    &mut components,
+                    &scan,
+                    &quant_tables,
+                    &dc_tables,
+                    &ac_tables,
+                    size,
+                    max_sampling,
+                    restart_interval,
+                )?;
+                position = reader.marker_position();
+            }
+            // APPn, COM and other segments are skipped
+            _ => {}
+        }
+    }
+
+    if components.is_empty() {
+        return Err(corrupt("Missing frame header"));
+    }
+    Ok(to_image(&components, size, max_sampling, color_space))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn decode_scan(
+    reader: &mut BitReader,
+    components: &mut [Component],
+    scan: &[usize],
+    quant_tables: &[[u16; 64]; 4],
+    dc_tables: &[Option<HuffmanTable>],
+    ac_tables: &[Option<HuffmanTable>],
+    size: (usize, usize),
+    max_sampling: (usize, usize),
+    restart_interval: usize,
+) -> Result<(), OpenSlideError> {
+    for &index in scan {
+        components[index].dc_pred = 0;
+        if dc_tables[components[index].dc_table].is_none()
+            || ac_tables[components[index].ac_table].is_none()
+        {
+            return Err(corrupt("Scan uses an undefined Huffman table"));
+        }
+    }
+
+    // A scan with one component is not interleaved, and has one block per MCU, covering only the
+    // blocks with samples of the component (A.2.2)
+    let (mcus_x, mcus_y) = if scan.len() == 1 {
+        let component = &components[scan[0]];
+        (
+            (size.0 * component.h).div_ceil(max_sampling.0).div_ceil(8),
+            (size.1 * component.v).div_ceil(max_sampling.1).div_ceil(8),
+        )
+    } else {
+        (
+            size.0.div_ceil(8 * max_sampling.0),
+            size.1.div_ceil(8 * max_sampling.1),
+        )
+    };
+
+    let mut coefficients = [0i32; 64];
+    let mut mcu_count = 0;
+    for mcu_y in 0..mcus_y {
+        for mcu_x in 0..mcus_x {
+            if restart_interval > 0 && mcu_count > 0 && mcu_count % restart_interval == 0 {
+                reader.restart()?;
+                for &index in scan {
+                    components[index].dc_pred = 0;
+                }
+            }
+            mcu_count += 1;
+
+            for &index in scan {
+                let component = &mut components[index];
+                let (blocks_x, blocks_y) = if scan.len() == 1 {
+                    (1, 1)
+                } else {
+                    (component.h, component.v)
+                };
+                for block_y in 0..blocks_y {
+                    for block_x in 0..blocks_x {
+                        decode_block(
+                            reader,
+                            &mut coefficients,
+                            &mut component.dc_pred,
+                            dc_tables[component.dc_table].as_ref().unwrap(),
+                            ac_tables[component.ac_table].as_ref().unwrap(),
+                        )?;
+                        let row = (mcu_y * blocks_y + block_y) * 8;
+                        let col = (mcu_x * blocks_x + block_x) * 8;
+                        let offset = row * component.plane_width + col;
+                        idct_islow(
+                            &coefficients,
+                            &quant_tables[component.quant_table],
+                            &mut component.plane[offset..],
+                            component.plane_width,
+                        );
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn decode_block(
+    reader: &mut BitReader,
+    coefficients: &mut [i32; 64],
+    dc_pred: &mut i32,
+    dc_table: &HuffmanTable,
+    ac_table: &HuffmanTable,
+) -> Result<(), OpenSlideError> {
+    *coefficients = [0; 64];
+    let size = u32::from(reader.decode(dc_table)?);
+    if size > 11 {
+        return Err(corrupt("Bad DC coefficient"));
+    }
+    *dc_pred += reader.receive_extend(size);
+    coefficients[0] = *dc_pred;
+
+    let mut k = 1;
+    while k < 64 {
+        let symbol = reader.decode(ac_table)?;
+        let run = usize::from(symbol >> 4);
+        let size = u32::from(symbol & 0x0f);
+        if size == 0 {
+            if run != 15 {
+                break;
+            }
+            k += 16;
+            continue;
+        }
+        k += run;
+        if k > 63 {
+            return Err(corrupt("Bad AC coefficient"));
+        }
+        coefficients[ZIGZAG[k]] = reader.receive_extend(size);
+        k += 1;
+    }
+    Ok(())
+}
+
+// Constants of the accurate integer IDCT of libjpeg (jidctint.c)
+const CONST_BITS: u32 = 13;
+const PASS1_BITS: u32 = 2;
+const FIX_0_298631336: i64 = 2446;
+const FIX_0_390180644: i64 = 3196;
+const FIX_0_541196100: i64 = 4433;
+const FIX_0_765366865: i64 = 6270;
+const FIX_0_899976223: i64 = 7373;
+const FIX_1_175875602: i64 = 9633;
+const FIX_1_501321110: i64 = 12299;
+const FIX_1_847759065: i64 = 15137;
+const FIX_1_961570560: i64 = 16069;
+const FIX_2_053119869: i64 = 16819;
+const FIX_2_562915447: i64 = 20995;
+const FIX_3_072711026: i64 = 25172;
+
+fn descale(value: i64, bits: u32) -> i64 {
+    (value + (1 << (bits - 1))) >> bits
+}
+
+/// One dimensional IDCT of 8 values, returning the outputs before descaling.
+fn idct_1d(values: [i64; 8]) -> [i64; 8] {
+    // Even part
+    let z2 = values[2];
+    let z3 = values[6];
+    let z1 = (z2 + z3) * FIX_0_541196100;
+    let tmp2 = z1 + z3 * -FIX_1_847759065;
+    let tmp3 = z1 + z2 * FIX_0_765366865;
+    let tmp0 = (values[0] + values[4]) << CONST_BITS;
+    let tmp1 = (values[0] - values[4]) << CONST_BITS;
+    let tmp10 = tmp0 + tmp3;
+    let tmp13 = tmp0 - tmp3;
+    let tmp11 = tmp1 + tmp2;
+    let tmp12 = tmp1 - tmp2;
+
+    // Odd part
+    let (mut tmp0, mut tmp1, mut tmp2, mut tmp3) = (values[7], values[5], values[3], values[1]);
+    let z1 = tmp0 + tmp3;
+    let z2 = tmp1 + tmp2;
+    let z3 = tmp0 + tmp2;
+    let z4 = tmp1 + tmp3;
+    let z5 = (z3 + z4) * FIX_1_175875602;
+    tmp0 *= FIX_0_298631336;
+    tmp1 *= FIX_2_053119869;
+    tmp2 *= FIX_3_072711026;
+    tmp3 *= FIX_1_501321110;
+    let z1 = z1 * -FIX_0_899976223;
+    let z2 = z2 * -FIX_2_562915447;
+    let z3 = z3 * -FIX_1_961570560 + z5;
+    let z4 = z4 * -FIX_0_390180644 + z5;
+    tmp0 += z1 + z3;
+    tmp1 += z2 + z4;
+    tmp2 += z2 + z3;
+    tmp3 += z1 + z4;
+
+    [
+        tmp10 + tmp3,
+        tmp11 + tmp2,
+        tmp12 + tmp1,
+        tmp13 + tmp0,
+        tmp13 - tmp0,
+        tmp12 - tmp1,
+        tmp11 - tmp2,
+        tmp10 - tmp3,
+    ]
+}
+
+/// Dequantize and inverse transform a block, like `jpeg_idct_islow` of libjpeg.
+fn idct_islow(coefficients: &[i32; 64], quant_table: &[u16; 64], output: &mut [u8], stride: usize) {
+    let mut workspace = [0i64; 64];
+
+    // Pass 1: columns, with results scaled up by 2^PASS1_BITS
+    for col in 0..8 {
+        let value = |row: usize| {
+            i64::from(coefficients[row * 8 + col]) * i64::from(quant_table[row * 8 + col])
+        };
+        if (1..8).all(|row| coefficients[row * 8 + col] == 0) {
+            let dc = value(0) << PASS1_BITS;
+            for row in 0..8 {
+                workspace[row * 8 + col] = dc;
+            }
+            continue;
+        }
+        let result = idct_1d([
+            value(0),
+            value(1),
+            value(2),
+            value(3),
+            value(4),
+            value(5),
+            value(6),
+            value(7),
+        ]);
+        for row in 0..8 {
+            workspace[row * 8 + col] = descale(result[row], CONST_BITS - PASS1_BITS);
+        }
+    }
+
+    // Pass 2: rows, descaled by 8 and 2^PASS1_BITS, and shifted to unsigned samples
+    for row in 0..8 {
+        let values = &workspace[row * 8..row * 8 + 8];
+        let out = &mut output[row * stride..row * stride + 8];
+        if values[1..].iter().all(|&value| value == 0) {
+            let sample = range_limit(descale(values[0], PASS1_BITS + 3));
+            for pixel in out.iter_mut() {
+                *pixel = sample;
+            }
+            continue;
+        }
+        let mut input = [0i64; 8];
+        input.copy_from_slice(values);
+        let result = idct_1d(input);
+        for (pixel, &value) in out.iter_mut().zip(result.iter()) {
+            *pixel = range_limit(descale(value, CONST_BITS + PASS1_BITS + 3));
+        }
+    }
+}
+
+/// Shift an IDCT output to an unsigned sample, and clamp it to the sample range.
+fn range_limit(value: i64) -> u8 {
+    (value + 128).clamp(0, 255) as u8
+}
+
+/// Upsample the components to full resolution, and interleave them into pixels.
+fn to_image(
+    components: &[Component],
+    size: (usize, usize),
+    max_sampling: (usize, usize),
+    color_space: ColorSpace,
+) -> DecodedImage {
+    let (width, height) = size;
+    let planes = components
+        .iter()
+        .map(|component| upsample(component, size, max_sampling))
+        .collect::<Vec<Vec<u8>>>();
+
+    let samples = if planes.len() == 1 || color_space == ColorSpace::Gray {
+        planes[0].clone()
+    } else {
+        let mut samples = Vec::with_capacity(width * height * 3);
+        let pixels = planes[0].iter().zip(planes[1].iter()).zip(planes[2].iter());
+        for ((&first, &second), &third) in pixels {
+            if color_space == ColorSpace::YCbCr {
+                samples.extend_from_slice(&ycc_to_rgb(first, second, third));
+            } else {
+                samples.extend_from_slice(&[first, second, third]);
+            }
+        }
+        samples
+    };
+
+    DecodedImage {
+        width: width as u32,
+        height: height as u32,
+        samples_per_pixel: if planes.len() == 1 || color_space == ColorSpace::Gray {
+            1
+        } else {
+            3
+        },
+        samples,
+    }
+}
+
+/// Upsample a component plane to a `width * height` plane, with the "fancy" (triangle filter)
+/// upsampling of libjpeg for 2h1v and 2h2v sampling, and sample replication otherwise.
+fn upsample(component: &Component, size: (usize, usize), max_sampling: (usize, usize)) -> Vec<u8> {
+    let (width, height) = size;
+    let h_factor = max_sampling.0 / component.h;
+    let v_factor = max_sampling.1 / component.v;
+    // Dimensions of the component without the padding to whole blocks
+    let comp_width = (width * component.h).div_ceil(max_sampling.0);
+    let comp_height = (height * component.v).div_ceil(max_sampling.1);
+    let stride = component.plane_width;
+    let plane = &component.plane;
+
+    let mut output = vec![0u8; width * height];
+    match (h_factor, v_factor) {
+        (1, 1) => {
+            for row in 0..height {
+                output[row * width..(row + 1) * width]
+                    .copy_from_slice(&plane[row * stride..row * stride + width]);
+            }
+        }
+        (2, 1) => {
+            let mut out_row = vec![0u8; comp_width * 2];
+            for row in 0..height {
+                let input = &plane[row * stride..row * stride + comp_width];
+                h2v1_fancy(input, &mut out_row);
+                output[row * width..(row + 1) * width].copy_from_slice(&out_row[..width]);
+            }
+        }
+        (2, 2) => {
+            let mut out_row = vec![0u8; comp_width * 2];
+            for row in 0..height {
+                let in_row = (row / 2).min(comp_height - 1);
+                // The nearest other input row, with the edge rows replicated
+                let other_row = if row % 2 == 0 {
+                    in_row.saturating_sub(1)
+                } else {
+                    (in_row + 1).min(comp_height - 1)
+                };
+                let input = &plane[in_row * stride..in_row * stride + comp_width];
+                let other = &plane[other_row * stride..other_row * stride + comp_width];
+                h2v2_fancy(input, other, &mut out_row);
+                output[row * width..(row + 1) * width].copy_from_slice(&out_row[..width]);
+            }
+        }
+        _ => {
+            for row in 0..height {
+                let in_row = (row / v_factor).min(comp_height - 1);
+                for col in 0..width {
+                    let in_col = (col / h_factor).min(comp_width - 1);
+                    output[row * width + col] = plane[in_row * stride + in_col];
+                }
+            }
+        }
+    }
+    output
+}
+
+/// Horizontal triangle filter upsampling (`h2v1_fancy_upsample` of libjpeg).
+fn h2v1_fancy(input: &[u8], output: &mut [u8]) {
+    let len = input.len();
+    if len == 1 {
+        output[0] = input[0];
+        output[1] = input[0];
+        return;
+    }
+    for col in 0..len {
+        let this = u32::from(input[col]) * 3;
+        output[2 * col] = if col == 0 {
+            input[0]
+        } else {
+            ((this + u32::from(input[col - 1]) + 1) >> 2) as u8
+        };
+        output[2 * col + 1] = if col == len - 1 {
+            input[col]
+        } else {
+            ((this + u32::from(input[col + 1]) + 2) >> 2) as u8
+        };
+    }
+}
+
+/// Horizontal and vertical triangle filter upsampling (`h2v2_fancy_upsample` of libjpeg), for
+/// one output row from the nearest input row and the other input row that is closest to it.
+fn h2v2_fancy(input: &[u8], other: &[u8], output: &mut [u8]) {
+    let len = input.len();
+    let column_sum = |col: usize| u32::from(input[col]) * 3 + u32::from(other[col]);
+    if len == 1 {
+        let sum = column_sum(0);
+        output[0] = ((sum * 4 + 8) >> 4) as u8;
+        output[1] = ((sum * 4 + 7) >> 4) as u8;
+        return;
+    }
+    for col in 0..len {
+        let this = column_sum(col);
+        output[2 * col] = if col == 0 {
+            ((this * 4 + 8) >> 4) as u8
+        } else {
+            ((this * 3 + column_sum(col - 1) + 8) >> 4) as u8
+        };
+        output[2 * col + 1] = if col == len - 1 {
+            ((this * 4 + 7) >> 4) as u8
+        } else {
+            ((this * 3 + column_sum(col + 1) + 7) >> 4) as u8
+        };
+    }
+}
+
+/// Convert YCbCr to RGB with the fixed point arithmetic of libjpeg (`ycc_rgb_convert`).
+fn ycc_to_rgb(y: u8, cb: u8, cr: u8) -> [u8; 3] {
+    const SCALE_BITS: u32 = 16;
+    const ONE_HALF: i64 = 1 << (SCALE_BITS - 1);
+    let fix = |value: f64| (value * f64::from(1u32 << SCALE_BITS) + 0.5) as i64;
+    let y = i64::from(y);
+    let cb = i64::from(cb) - 128;
+    let cr = i64::from(cr) - 128;
+    let red = y + ((fix(1.40200) * cr + ONE_HALF) >> SCALE_BITS);
+    let green = y + ((-fix(0.34414) * cb + ONE_HALF - fix(0.71414) * cr) >> SCALE_BITS);
+    let blue = y + ((fix(1.77200) * cb + ONE_HALF) >> SCALE_BITS);
+    let clamp = |value: i64| value.clamp(0, 255) as u8;
+    [clamp(red), clamp(green), clamp(blue)]
+}
+
+fn corrupt(msg: &str) -> OpenSlideError {
+    OpenSlideError::Library(format!("Corrupt JPEG data: {}", msg))
+}
+
+fn unsupported(what: &str) -> OpenSlideError {
+    OpenSlideError::Unsupported(format!("{} is not supported", what))
+}
@@ -0,0 +1,540 @@
+//! A pure-Rust backend for Aperio SVS and generic tiled TIFF slides
+//!
+//! This reads the same slides as the `aperio` and `generic-tiff` formats of OpenSlide, without the
+//! OpenSlide C library. Tiles may be uncompressed, LZW or JPEG compressed. JPEG 2000 compressed
+//! slides are not supported.
+//!
+//! JPEG tiles are decoded with the same arithmetic as libjpeg, so the decoded tiles are identical
+//! to those of OpenSlide. Region reads only match OpenSlide on level 0 (see `TiffBackend`).
+//!
+
+mod decode;
+mod ifd;
+mod jpeg;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Mutex;
+
+use error::OpenSlideError;
+use self::decode::Encoding;
+use self::ifd::Directory;
+use super::SlideBackend;
+
+/// An image in the file, stored in tiles or strips (both called chunks here).
+struct Image {
+    tiled: bool,
+    width: u64,
+    height: u64,
+    chunk_width: u64,
+    chunk_height: u64,
+    offsets: Vec<u64>,
+    byte_counts: Vec<u64>,
+    encoding: Encoding,
+}
+
+impl Image {
+    fn from_directory(directory: &Directory) -> Result<Image, OpenSlideError> {
+        let width = required(directory, ifd::IMAGE_WIDTH)?;
+        let height = required(directory, ifd::IMAGE_LENGTH)?;
+        let (chunk_width, chunk_height, offsets, byte_counts) = if directory.is_tiled() {
+            (
+                required(directory, ifd::TILE_WIDTH)?,
+                required(directory, ifd::TILE_LENGTH)?,
+                directory.u64s(ifd::TILE_OFFSETS),
+                directory.u64s(ifd::TILE_BYTE_COUNTS),
+            )
+        } else {
+            (
+                width,
+                directory.u64(ifd::ROWS_PER_STRIP).unwrap_or(height).min(height),
+                directory.u64s(ifd::STRIP_OFFSETS),
+                directory.u64s(ifd::STRIP_BYTE_COUNTS),
+            )
+        };
+        if width == 0 || height == 0 || chunk_width == 0 || chunk_height == 0 {
+            return Err(OpenSlideError::Library(String::from(
+                "Corrupt TIFF file: Image or tile has no pixels",
+            )));
+        }
+
+        let image = Image {
+            tiled: directory.is_tiled(),
+            width,
+            height,
+            chunk_width,
+            chunk_height,
+            offsets: offsets.unwrap_or_default(),
+            byte_counts: byte_counts.unwrap_or_default(),
+            encoding: Encoding::from_directory(directory)?,
+        };
+        let chunk_count = image
+            .chunks_across()
+            .checked_mul(image.chunks_down())
+            .ok_or_else(|| {
+                OpenSlideError::Library(String::from("Corrupt TIFF file: Too many tiles or strips"))
+            })?;
+        if (image.offsets.len() as u64) < chunk_count
+            || (image.byte_counts.len() as u64) < chunk_count
+        {
+            return Err(OpenSlideError::Library(format!(
+                "Corrupt TIFF file: Expected {} tiles or strips, found {} offsets and {} byte counts",
+                chunk_count,
+                image.offsets.len(),
+                image.byte_counts.len()
+            )));
+        }
+        Ok(image)
+    }
+
+    fn chunks_across(&self) -> u64 {
+        self.width.div_ceil(self.chunk_width)
+    }
+
+    fn chunks_down(&self) -> u64 {
+        self.height.div_ceil(self.chunk_height)
+    }
+}
+
+/// One level of the pyramid.
+struct Level {
+    image: Image,
+    downsample: f64,
+}
+
+/// A slide read from a tiled TIFF or BigTIFF file, without the OpenSlide C library.
+///
+/// Aperio SVS files are recognized by their image description, and get the same levels,
+/// associated images (`thumbnail`, `label` and `macro`) and `openslide.*`, `tiff.*` and
+/// `aperio.*` properties as in OpenSlide. Other tiled TIFF files are read as a pyramid of all
+/// their tiled images, like the `generic-tiff` format of OpenSlide. The `openslide.quickhash-1`
+/// property is not computed.
+///
+/// Region reads are exact copies of the decoded pixels, from the level pixel at the level 0
+/// origin divided by the downsample and rounded down. This is what OpenSlide returns on level 0,
+/// and on other levels when the origin is a multiple of the downsample. Otherwise OpenSlide
+/// resamples the region by the fractional offset, which this backend does not do, so regions on
+/// other levels may be shifted by up to one pixel compared to OpenSlide.
+pub struct TiffBackend {
+    file: Mutex<File>,
+    file_len: u64,
+    levels: Vec<Level>,
+    associated_images: Vec<(String, Image)>,
+    properties: HashMap<String, String>,
+}
+
+impl TiffBackend {
+    /// Open the slide at the given filename location.
+    ///
+    /// Returns an `UnsupportedFormat` error if the file is not a tiled TIFF file, and an
+    /// `Unsupported` error if it uses an encoding that this backend can not decode.
+    pub fn open(filename: &Path) -> Result<TiffBackend, OpenSlideError> {
+        if !filename.exists() {
+            return Err(OpenSlideError::InvalidPath(format!(
+                "Nonexisting path: {}",
+                filename.display()
+            )));
+        }
+        let mut file = File::open(filename).map_err(|err| {
+            OpenSlideError::InvalidPath(format!("Could not open {}: {}", filename.display(), err))
+        })?;
+        let file_len = file
+            .metadata()
+            .map_err(|err| OpenSlideError::Library(format!("Could not read file size: {}", err)))?
+            .len();
+
+        let directories = ifd::read_directories(&mut file, file_len)?;
+        match directories.first() {
+            Some(directory) if directory.is_tiled() => {}
+            _ => {
+                return Err(OpenSlideError::UnsupportedFormat(format!(
+                    "Not a tiled TIFF file: {}",
+                    filename.display()
+                )))
+            }
+        }
+
+        let description = directories[0].string(ifd::IMAGE_DESCRIPTION);
+        let is_aperio = description
+            .as_ref()
+            .is_some_and(|description| description.starts_with("Aperio"));
+
+        let mut levels = Vec::new();
+        let mut associated_images = Vec::new();
+        for (index, directory) in directories.iter().enumerate() {
+            if directory.is_tiled() {
+                levels.push(Image::from_directory(directory)?);
+            } else if is_aperio {
+                if let Some(name) = aperio_associated_image_name(index, directory) {
+                    associated_images.push((name, Image::from_directory(directory)?));
+                }
+            }
+        }
+        if !is_aperio {
+            levels.sort_by_key(|image| ::std::cmp::Reverse(image.width));
+        }
+
+        let (base_width, base_height) = (levels[0].width as f64, levels[0].height as f64);
+        let levels = levels
+            .into_iter()
+            .map(|image| Level {
+                downsample: (base_width / image.width as f64 + base_height / image.height as f64)
+                    / 2.0,
+                image,
+            })
+            .collect::<Vec<Level>>();
+
+        let mut properties = HashMap::new();
+        add_tiff_properties(&mut properties, &directories[0]);
+        add_level_properties(&mut properties, &levels);
+        if is_aperio {
+            properties.insert(String::from("openslide.vendor"), String::from("aperio"));
+            if let Some(ref description) = description {
+                add_aperio_properties(&mut properties, description);
+            }
+        } else {
+            properties.insert(String::from("openslide.vendor"), String::from("generic-tiff"));
+            add_resolution_properties(&mut properties, &directories[0]);
+        }
+
+        Ok(TiffBackend {
+            file: Mutex::new(file),
+            file_len,
+            levels,
+            associated_images,
+            properties,
+        })
+    }
+
+    fn level(&self, level: u32) -> Result<&Level, OpenSlideError> {
+        self.levels
+            .get(level as usize)
+            .ok_or(OpenSlideError::InvalidLevel {
+                level: i64::from(level),
+                level_count: self.levels.len() as u32,
+            })
+    }
+
+    fn associated_image(&self, name: &str) -> Result<&Image, OpenSlideError> {
+        self.associated_images
+            .iter()
+            .find(|(image_name, _)| image_name == name)
+            .map(|(_, image)| image)
+            .ok_or_else(|| {
+                OpenSlideError::InvalidArgument(format!(
+                    "Slide has no associated image named {}",
+                    name
+                ))
+            })
+    }
+
+    /// Read and decode the tile or strip with the given index.
+    fn read_chunk(&self, image: &Image, index: u64) -> Result<Vec<u32>, OpenSlideError> {
+        // The last strip may be shorter, while tiles always have the full size
+        let chunk_height = if image.tiled {
+            image.chunk_height
+        } else {
+            image
+                .chunk_height
+                .min(image.height - index * image.chunk_height)
+        };
+
+        // Check the size before allocating, as the byte count below, which also keeps the width
+        // and height within `u32`
+        let pixel_count = decode::pixel_count(image.chunk_width, chunk_height)?;
+        let offset = image.offsets[index as usize];
+        let byte_count = image.byte_counts[index as usize];
+        if byte_count == 0 {
+            // Missing tiles are transparent
+            return Ok(vec![0; pixel_count]);
+        }
+        // Check the chunk against the file before allocating, as a corrupt byte count could
+        // otherwise allocate any amount of memory
+        if offset
+            .checked_add(byte_count)
+            .is_none_or(|end| end > self.file_len)
+        {
+            return Err(OpenSlideError::Library(format!(
+                "Corrupt TIFF file: Tile or strip of {} bytes at offset {} extends beyond the end \
+                 of the file",
+                byte_count, offset
+            )));
+        }
+        let mut data = vec![0u8; byte_count as usize];
+        {
+            let mut file = self
+                .file
+                .lock()
+                .map_err(|_| OpenSlideError::Library(String::from("Slide file lock is poisoned")))?;
+            ifd::read_exact_at(&mut *file, offset, &mut data)?;
+        }
+        image
+            .encoding
+            .decode(&data, image.chunk_width as u32, chunk_height as u32)
+    }
+
+    /// Copy the pixels of the given image inside the region into `dest`, which has `width`
+    /// pixels per row. Pixels outside the image are transparent.
+    fn read_image_region(
+        &self,
+        image: &Image,
+        dest: &mut [u32],
+        left: i64,
+        top: i64,
+        width: u64,
+        height: u64,
+    ) -> Result<(), OpenSlideError> {
+        for pixel in dest.iter_mut().take((width * height) as usize) {
+            *pixel = 0;
+        }
+
+        // The part of the region that is inside the image, in image coordinates
+        let x_start = left.max(0) as u64;
+        let y_start = top.max(0) as u64;
+        let x_end = (left + width as i64).min(image.width as i64);
+        let y_end = (top + height as i64).min(image.height as i64);
+        if x_end <= x_start as i64 || y_end <= y_start as i64 {
+            return Ok(());
+        }
+        let (x_end, y_end) = (x_end as u64, y_end as u64);
+
+        for chunk_row in y_start / image.chunk_height..=(y_end - 1) / image.chunk_height {
+            for chunk_col in x_start / image.chunk_width..=(x_end - 1) / image.chunk_width {
+                let index = chunk_row * image.chunks_across() + chunk_col;
+                let chunk = self.read_chunk(image, index)?;
+                let chunk_left = chunk_col * image.chunk_width;
+                let chunk_top = chunk_row * image.chunk_height;
+
+                let cols = chunk_left.max(x_start)..(chunk_left + image.chunk_width).min(x_end);
+                let rows = chunk_top.max(y_start)..(chunk_top + image.chunk_height).min(y_end);
+                for y in rows {
+                    let src_start = ((y - chunk_top) * image.chunk_width + cols.start - chunk_left)
+                        as usize;
+                    let dest_start =
+                        ((y as i64 - top) as u64 * width + (cols.start as i64 - left) as u64)
+                            as usize;
+                    let len = (cols.end - cols.start) as usize;
+                    if src_start + len <= chunk.len() {
+                        dest[dest_start..dest_start + len]
+                            .copy_from_slice(&chunk[src_start..src_start + len]);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SlideBackend for TiffBackend {
+    fn level_count(&self) -> Result<u32, OpenSlideError> {
+        Ok(self.levels.len() as u32)
+    }
+
+    fn level_dimensions(&self, level: u32) -> Result<(u64, u64), OpenSlideError> {
+        let level = self.level(level)?;
+        Ok((level.image.width, level.image.height))
+    }
+
+    fn level_downsample(&self, level: u32) -> Result<f64, OpenSlideError> {
+        Ok(self.level(level)?.downsample)
+    }
+
+    fn read_region(
+        &self,
+        dest: &mut [u32],
+        x: i64,
+        y: i64,
+        level: u32,
+        width: u64,
+        height: u64,
+    ) -> Result<(), OpenSlideError> {
+        let level = self.level(level)?;
+        check_buffer(dest, width, height)?;
+        let left = (x as f64 / level.downsample).floor() as i64;
+        let top = (y as f64 / level.downsample).floor() as i64;
+        self.read_image_region(&level.image, dest, left, top, width, height)
+    }
+
+    fn properties(&self) -> Result<HashMap<String, String>, OpenSlideError> {
+        Ok(self.properties.clone())
+    }
+
+    fn associated_image_names(&self) -> Result<Vec<String>, OpenSlideError> {
+        Ok(self
+            .associated_images
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect())
+    }
+
+    fn associated_image_dimensions(&self, name: &str) -> Result<(u64, u64), OpenSlideError> {
+        let image = self.associated_image(name)?;
+        Ok((image.width, image.height))
+    }
+
+    fn read_associated_image(&self, name: &str, dest: &mut [u32]) -> Result<(), OpenSlideError> {
+        let image = self.associated_image(name)?;
+        check_buffer(dest, image.width, image.height)?;
+        self.read_image_region(image, dest, 0, 0, image.width, image.height)
+    }
+}
+
+fn required(directory: &Directory, tag: u16) -> Result<u64, OpenSlideError> {
+    directory.u64(tag).ok_or_else(|| {
+        OpenSlideError::Library(format!("Corrupt TIFF file: Missing required tag {}", tag))
+    })
+}
+
+fn check_buffer(dest: &[u32], width: u64, height: u64) -> Result<(), OpenSlideError> {
+    let len = width
+        .checked_mul(height)
+        .ok_or_else(|| OpenSlideError::InvalidArgument(String::from("Region is too large")))?;
+    if (dest.len() as u64) < len {
+        return Err(OpenSlideError::InvalidArgument(format!(
+            "Buffer of length {} is too small for {} pixels",
+            dest.len(),
+            len
+        )));
+    }
+    Ok(())
+}
+
+/// The name of an Aperio associated image, as OpenSlide names it.
+///
+/// The second directory is the thumbnail. Other associated images are named by the first word on
+/// the second line of their image description, e.g. `label` or `macro`.
+fn aperio_associated_image_name(index: usize, directory: &Directory) -> Option<String> {
+    if index == 1 {
+        return Some(String::from("thumbnail"));
+    }
+    let description = directory.string(ifd::IMAGE_DESCRIPTION)?;
+    let line = description.lines().nth(1)?;
+    line.split_whitespace().next().map(String::from)
+}
+
+/// Add the `tiff.*` properties of the first directory, and `openslide.comment`.
+fn add_tiff_properties(properties: &mut HashMap<String, String>, directory: &Directory) {
+    let strings = [
+        (ifd::IMAGE_DESCRIPTION, "tiff.ImageDescription"),
+        (ifd::MAKE, "tiff.Make"),
+        (ifd::MODEL, "tiff.Model"),
+        (ifd::SOFTWARE, "tiff.Software"),
+        (ifd::DATE_TIME, "tiff.DateTime"),
+        (ifd::ARTIST, "tiff.Artist"),
+        (ifd::HOST_COMPUTER, "tiff.HostComputer"),
+        (ifd::COPYRIGHT, "tiff.Copyright"),
+        (ifd::DOCUMENT_NAME, "tiff.DocumentName"),
+    ];
+    for &(tag, name) in &strings {
+        if let Some(value) = directory.string(tag) {
+            properties.insert(String::from(name), value);
+        }
+    }
+    if let Some(description) = directory.string(ifd::IMAGE_DESCRIPTION) {
+        properties.insert(String::from("openslide.comment"), description);
+    }
+
+    for &(tag, name) in &[
+        (ifd::X_RESOLUTION, "tiff.XResolution"),
+        (ifd::Y_RESOLUTION, "tiff.YResolution"),
+    ] {
+        if let Some(value) = directory.f64(tag) {
+            properties.insert(String::from(name), format_double(value));
+        }
+    }
+    if directory.f64(ifd::X_RESOLUTION).is_some() || directory.f64(ifd::Y_RESOLUTION).is_some() {
+        let unit = match directory.u64(ifd::RESOLUTION_UNIT).unwrap_or(2) {
+            1 => "none",
+            2 => "inch",
+            3 => "centimeter",
+            _ => "unknown",
+        };
+        properties.insert(String::from("tiff.ResolutionUnit"), String::from(unit));
+    }
+}
+
+/// Add `openslide.level-count` and the `openslide.level[<level>].*` properties.
+fn add_level_properties(properties: &mut HashMap<String, String>, levels: &[Level]) {
+    properties.insert(String::from("openslide.level-count"), levels.len().to_string());
+    for (index, level) in levels.iter().enumerate() {
+        let prefix = format!("openslide.level[{}]", index);
+        let image = &level.image;
+        properties.insert(format!("{}.width", prefix), image.width.to_string());
+        properties.insert(format!("{}.height", prefix), image.height.to_string());
+        properties.insert(format!("{}.downsample", prefix), format_double(level.downsample));
+        properties.insert(format!("{}.tile-width", prefix), image.chunk_width.to_string());
+        properties.insert(format!("{}.tile-height", prefix), image.chunk_height.to_string());
+    }
+}
+
+/// Add the `aperio.*` properties from the image description, which is a header followed by
+/// `|`-separated `key = value` pairs, and the standard properties that are derived from them.
+fn add_aperio_properties(properties: &mut HashMap<String, String>, description: &str) {
+    for pair in description.split('|').skip(1) {
+        let mut parts = pair.splitn(2, '=');
+        let name = parts.next().unwrap_or("").trim();
+        if let Some(value) = parts.next() {
+            properties.insert(format!("aperio.{}", name), String::from(value.trim()));
+        }
+    }
+
+    let objective_power = properties
+        .get("aperio.AppMag")
+        .and_then(|value| value.parse::<i64>().ok());
+    if let Some(value) = objective_power {
+        properties.insert(String::from("openslide.objective-power"), value.to_string());
+    }
+    let mpp = properties
+        .get("aperio.MPP")
+        .and_then(|value| value.parse::<f64>().ok());
+    if let Some(value) = mpp {
+        properties.insert(String::from("openslide.mpp-x"), format_double(value));
+        properties.insert(String::from("openslide.mpp-y"), format_double(value));
+    }
+}
+
+/// Add `openslide.mpp-x` and `openslide.mpp-y` from the TIFF resolution, if it is given in
+/// pixels per centimeter.
+fn add_resolution_properties(properties: &mut HashMap<String, String>, directory: &Directory) {
+    if directory.u64(ifd::RESOLUTION_UNIT) != Some(3) {
+        return;
+    }
+    for &(tag, name) in &[
+        (ifd::X_RESOLUTION, "openslide.mpp-x"),
+        (ifd::Y_RESOLUTION, "openslide.mpp-y"),
+    ] {
+        if let Some(resolution) = directory.f64(tag).filter(|&value| value > 0.0) {
+            properties.insert(String::from(name), format_double(10000.0 / resolution));
+        }
+    }
+}
+
+/// Format a floating point property value like OpenSlide does (`%.17g` in C).
+fn format_double(value: f64) -> String {
+    if value == 0.0 || !value.is_finite() {
+        return value.to_string();
+    }
+    let scientific = format!("{:.16e}", value);
+    let exponent = scientific
+        .rsplit('e')
+        .next()
+        .and_then(|exponent| exponent.parse::<i32>().ok())
+        .unwrap_or(0);
+    if !(-4..17).contains(&exponent) {
+        let mut parts = scientific.splitn(2, 'e');
+        let mantissa = strip_fraction_zeros(parts.next().unwrap_or(""));
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+    } else {
+        strip_fraction_zeros(&format!("{:.*}", (16 - exponent) as usize, value))
+    }
+}
+
+fn strip_fraction_zeros(number: &str) -> String {
+    if number.contains('.') {
+        String::from(number.trim_end_matches('0').trim_end_matches('.'))
+    } else {
+        String::from(number)
+    }
+}
@@ -82,6 +82,7 @@ fn get_cli<'a>() -> ArgMatches<'a> {
 }
 
 
+#[allow(clippy::too_many_arguments)]
 fn write_region(
     fname: &str,
     os: &OpenSlide,
@@ -151,7 +152,7 @@ fn main() -> Result<(), Error> {
         Some(val) => {
             let dirpath = Path::new(val);
             if !dirpath.exists() {
-                fs::create_dir_all(&dirpath)?;
+                fs::create_dir_all(dirpath)?;
             }
             dirpath
         }
@@ -192,9 +193,9 @@ fn main() -> Result<(), Error> {
     }
 
     write_region(
-        &input_file.file_stem().unwrap().to_str().unwrap(),
+        input_file.file_stem().unwrap().to_str().unwrap(),
         &os,
-        &out_dir,
+        out_dir,
         source_row,
         source_column,
         target_height,
@@ -0,0 +1,122 @@
+//! Deep Zoom and IIIF tile server for a directory of slides
+//!
+//! Run with e.g. `cargo run --features server --bin serve -- /path/to/slides`, and open
+//! http://127.0.0.1:8000 in a browser.
+//!
+
+extern crate clap;
+extern crate openslide;
+
+use std::error::Error;
+use std::path::Path;
+
+use clap::{App, Arg, ArgMatches};
+use openslide::deepzoom::TileFormat;
+use openslide::server::SlideServer;
+
+fn get_cli<'a>() -> ArgMatches<'a> {
+    App::new("Serve slides")
+        .version("0.2.0")
+        .about(
+            "Serves the slides in a directory as Deep Zoom tiles and IIIF images, with an \
+             OpenSeadragon viewer.",
+        )
+        .arg(
+            Arg::with_name("directory")
+                .value_name("DIRECTORY")
+                .help("Directory with slides, which is searched recursively")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("listen")
+                .short("l")
+                .long("listen")
+                .value_name("ADDRESS")
+                .default_value("127.0.0.1:8000")
+                .help("Address to listen on"),
+        )
+        .arg(
+            Arg::with_name("base_url")
+                .short("u")
+                .long("base_url")
+                .value_name("URL")
+                .help("URL that the server is reached at, for the identifiers of IIIF images"),
+        )
+        .arg(
+            Arg::with_name("tile_size")
+                .short("s")
+                .long("tile_size")
+                .value_name("PIXELS")
+                .default_value("254")
+                .help("Tile size"),
+        )
+        .arg(
+            Arg::with_name("overlap")
+                .short("e")
+                .long("overlap")
+                .value_name("PIXELS")
+                .default_value("1")
+                .help("Overlap of neighbouring tiles"),
+        )
+        .arg(
+            Arg::with_name("quality")
+                .short("Q")
+                .long("quality")
+                .value_name("QUALITY")
+                .default_value("75")
+                .help("JPEG quality of the tiles (1 - 100)"),
+        )
+        .arg(
+            Arg::with_name("threads")
+                .short("t")
+                .long("threads")
+                .value_name("COUNT")
+                .default_value("4")
+                .help("Number of threads that answer requests"),
+        )
+        .arg(
+            Arg::with_name("open_slides")
+                .short("c")
+                .long("open_slides")
+                .value_name("COUNT")
+                .default_value("10")
+                .help("Maximum number of slides that are kept open between requests"),
+        )
+        .arg(
+            Arg::with_name("ignore_bounds")
+                .short("B")
+                .long("ignore_bounds")
+                .takes_value(false)
+                .help("Show the whole slide, also outside the bounds of the scanned area"),
+        )
+        .get_matches()
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let matches = get_cli();
+    let directory = Path::new(matches.value_of("directory").unwrap_or("."));
+    let tile_size = clap::value_t!(matches.value_of("tile_size"), u64)?;
+    let overlap = clap::value_t!(matches.value_of("overlap"), u64)?;
+    let quality = clap::value_t!(matches.value_of("quality"), u8)?;
+    let threads = clap::value_t!(matches.value_of("threads"), usize)?;
+    let open_slides = clap::value_t!(matches.value_of("open_slides"), usize)?;
+
+    let server = SlideServer::new(directory)?
+        .with_tile_size(tile_size, overlap)
+        .with_format(TileFormat::Jpeg(quality))
+        .with_limit_bounds(!matches.is_present("ignore_bounds"))
+        .with_max_open_slides(open_slides)
+        .with_base_url(matches.value_of("base_url").unwrap_or(""));
+    println!(
+        "Found {} slides in {}",
+        server.slides().len(),
+        directory.display()
+    );
+
+    let listener = server.bind(matches.value_of("listen").unwrap_or("127.0.0.1:8000"))?;
+    if let Some(address) = listener.local_addr() {
+        println!("Listening on http://{}", address);
+    }
+    listener.run(threads);
+    Ok(())
+}
@@ -5,133 +5,97 @@
 //!
 //! For a more rust convenient api, use the OpenSlide struct.
 //!
+//! # Safety
+//!
+//! The functions taking an `osr` argument are `unsafe`, as they require that `osr` is a handle
+//! returned by `open`, which is not yet closed. The wrappers take care of NULL pointers returned
+//! by OpenSlide, and of strings that are not valid UTF-8.
+//!
+
+#![allow(clippy::missing_safety_doc)]
 
-use failure::Error;
 use libc;
+use num::ToPrimitive;
 use std::{self, ffi, str};
 
-/// Dummy type for the openslide_t type in OpenSlide
-pub enum OpenSlideT {}
-
-#[link(name = "openslide")]
-extern "C" {
-
-    // ---------------
-    // Basic usage
-    // ---------------
-
-    fn openslide_detect_vendor(filename: *const libc::c_char) -> *const libc::c_char;
-
-    fn openslide_open(filename: *const libc::c_char) -> *const OpenSlideT;
-
-    fn openslide_close(osr: *const OpenSlideT) -> libc::c_void;
-
-    fn openslide_get_level_count(osr: *const OpenSlideT) -> libc::int32_t;
-
-    fn openslide_get_level0_dimensions(
-        osr: *const OpenSlideT,
-        w: *mut libc::int64_t,
-        h: *mut libc::int64_t,
-    ) -> libc::c_void;
+use error::OpenSlideError;
+use library::api;
+#[cfg(openslide4)]
+use library::optional;
 
-    fn openslide_get_level_dimensions(
-        osr: *const OpenSlideT,
-        level: libc::int32_t,
-        w: *mut libc::int64_t,
-        h: *mut libc::int64_t,
-    ) -> libc::c_void;
+#[cfg(feature = "dynamic")]
+pub use library::{load_library, LIBRARY_PATH_VARIABLE};
+pub use library::{optional_symbols, OptionalSymbols};
 
-    fn openslide_get_level_downsample(
-        osr: *const OpenSlideT,
-        level: libc::int32_t,
-    ) -> libc::c_double;
-
-    fn openslide_get_best_level_for_downsample(
-        slide: *const OpenSlideT,
-        downsample_factor: libc::c_double,
-    ) -> libc::int32_t;
-
-    fn openslide_read_region(
-        osr: *const OpenSlideT,
-        dest: *mut libc::uint32_t,
-        x: libc::int64_t,
-        y: libc::int64_t,
-        level: libc::int32_t,
-        w: libc::int64_t,
-        h: libc::int64_t,
-    ) -> libc::c_void;
-
-    // ---------------
-    // Error handling
-    // ---------------
-
-    // fn openslide_get_error(
-    //     osr: *const OpenSlideT
-    // ) -> *const libc::c_char;
-
-    // ---------------
-    // Properties
-    // ---------------
-
-    fn openslide_get_property_names(osr: *const OpenSlideT) -> *const *const libc::c_char;
+/// Dummy type for the openslide_t type in OpenSlide
+pub enum OpenSlideT {}
 
-    fn openslide_get_property_value(
-        osr: *const OpenSlideT,
-        name: *const libc::c_char,
-    ) -> *const libc::c_char;
-}
+/// Dummy type for the openslide_cache_t type in OpenSlide 4.x
+pub enum OpenSlideCacheT {}
 
 // ---------------
 // Basic usage
 // ---------------
 
 /// Quickly determine whether a whole slide image is recognized.
-pub fn detect_vendor(filename: &str) -> Result<String, Error> {
+///
+/// Returns `None` if the file is not recognized by OpenSlide.
+pub fn detect_vendor(filename: &str) -> Result<Option<String>, OpenSlideError> {
     let c_filename = ffi::CString::new(filename)?;
-    let vendor = unsafe {
-        let c_vendor = openslide_detect_vendor(c_filename.as_ptr());
-        ffi::CStr::from_ptr(c_vendor).to_string_lossy().into_owned()
-    };
+    let api = api()?;
+    let vendor = unsafe { c_str_to_string((api.openslide_detect_vendor)(c_filename.as_ptr()))? };
     Ok(vendor)
 }
 
 /// Open a whole slide image.
-pub fn open(filename: &str) -> Result<*const OpenSlideT, Error> {
+///
+/// Returns an error if the file is not recognized by OpenSlide. Note that OpenSlide may also
+/// return a handle that is in the error state, use `get_error` to check for that.
+pub fn open(filename: &str) -> Result<*const OpenSlideT, OpenSlideError> {
     let c_filename = ffi::CString::new(filename)?;
-    let slide = unsafe { openslide_open(c_filename.as_ptr()) };
+    let slide = unsafe { (api()?.openslide_open)(c_filename.as_ptr()) };
+    if slide.is_null() {
+        return Err(OpenSlideError::UnsupportedFormat(format!(
+            "OpenSlide could not open {}",
+            filename
+        )));
+    }
     Ok(slide)
 }
 
 /// Close an OpenSlide object.
 pub unsafe fn close(osr: *const OpenSlideT) {
-    openslide_close(osr); // This is unsafe
+    // A valid handle implies that the library is loaded
+    if let Ok(api) = api() {
+        (api.openslide_close)(osr); // This is unsafe
+    }
 }
 
 /// Get the number of levels in the whole slide image.
-pub unsafe fn get_level_count(osr: *const OpenSlideT) -> Result<i32, Error> {
-    let num_levels = openslide_get_level_count(osr); // This is unsafe
+pub unsafe fn get_level_count(osr: *const OpenSlideT) -> Result<i32, OpenSlideError> {
+    let num_levels = (api()?.openslide_get_level_count)(osr); // This is unsafe
     Ok(num_levels)
 }
 
 /// Get the dimensions of level 0 (the largest level).
-pub unsafe fn get_level0_dimensions(osr: *const OpenSlideT) -> Result<(i64, i64), Error> {
-    let mut width: libc::int64_t = 0;
-    let mut height: libc::int64_t = 0;
-    openslide_get_level0_dimensions(osr, &mut width, &mut height); // This is unsafe
+pub unsafe fn get_level0_dimensions(osr: *const OpenSlideT) -> Result<(i64, i64), OpenSlideError> {
+    let mut width: i64 = 0;
+    let mut height: i64 = 0;
+    (api()?.openslide_get_level0_dimensions)(osr, &mut width, &mut height); // This is unsafe
     Ok((width, height))
 }
 
 /// Get the dimensions of a level.
-pub unsafe fn get_level_dimensions(osr: *const OpenSlideT, level: i32) -> Result<(i64, i64), Error> {
-    let mut width: libc::int64_t = 0;
-    let mut height: libc::int64_t = 0;
-    openslide_get_level_dimensions(osr, level, &mut width, &mut height); // This is unsafe
+pub unsafe fn get_level_dimensions(osr: *const OpenSlideT, level: i32) -> Result<(i64, i64), OpenSlideError> {
+    let mut width: i64 = 0;
+    let mut height: i64 = 0;
+    (api()?.openslide_get_level_dimensions)(osr, level, &mut width, &mut height); // This is unsafe
     Ok((width, height))
 }
 
 /// Get the downsampling factor of a given level.
-pub unsafe fn get_level_downsample(osr: *const OpenSlideT, level: i32) -> Result<f64, Error> {
-    let downsampling_factor = openslide_get_level_downsample(osr, level); // This is unsafe
+pub unsafe fn get_level_downsample(osr: *const OpenSlideT, level: i32) -> Result<f64, OpenSlideError> {
+    let downsampling_factor = (api()?.openslide_get_level_downsample)(osr, level); // This is unsafe
     Ok(downsampling_factor)
 }
 
@@ -139,8 +103,8 @@ pub unsafe fn get_level_downsample(osr: *const OpenSlideT, level: i32) -> Result
 pub unsafe fn get_best_level_for_downsample(
     osr: *const OpenSlideT,
     downsample: f64,
-) -> Result<i32, Error> {
-    let level = openslide_get_best_level_for_downsample(osr, downsample); // This is unsafe
+) -> Result<i32, OpenSlideError> {
+    let level = (api()?.openslide_get_best_level_for_downsample)(osr, downsample); // This is unsafe
     Ok(level)
 }
 
@@ -152,64 +116,320 @@ pub unsafe fn read_region(
     level: i32,
     w: i64,
     h: i64,
-) -> Result<Vec<u32>, Error> {
-    let mut buffer: Vec<libc::uint32_t> = Vec::with_capacity((h * w) as usize);
-    let p_buffer = buffer.as_mut_ptr();
-    openslide_read_region(osr, p_buffer, x, y, level, w, h); // This is unsafe
-    buffer.set_len((h * w) as usize);
+) -> Result<Vec<u32>, OpenSlideError> {
+    let len = buffer_len(w, h)?;
+    let mut buffer = vec![0u32; len];
+    read_region_into(osr, &mut buffer, x, y, level, w, h)?;
     Ok(buffer)
 }
 
+/// Copy pre-multiplied ARGB data from a whole slide image into the given buffer.
+///
+/// The buffer must have room for at least `w * h` pixels.
+pub unsafe fn read_region_into(
+    osr: *const OpenSlideT,
+    dest: &mut [u32],
+    x: i64,
+    y: i64,
+    level: i32,
+    w: i64,
+    h: i64,
+) -> Result<(), OpenSlideError> {
+    let len = buffer_len(w, h)?;
+    if dest.len() < len {
+        return Err(OpenSlideError::InvalidArgument(format!(
+            "Buffer of length {} is too small for a region of {} pixels",
+            dest.len(),
+            len
+        )));
+    }
+    (api()?.openslide_read_region)(osr, dest.as_mut_ptr(), x, y, level, w, h); // This is unsafe
+    Ok(())
+}
+
 // ---------------
 // Error handling
 // ---------------
 
-/* FIXME Keep commented as long as it is not working. Gets segmentation fault core dumped
 /// Get the current error string.
-pub fn get_error(
-    osr: *const OpenSlideT
-) -> Result<String, Error> {
-    let msg = unsafe {
-        let c_msg = openslide_get_error(osr);
-        ffi::CStr::from_ptr(c_msg).to_string_lossy().into_owned()
-    };
-    Ok(msg)
+///
+/// Returns `None` if no error has occurred. Once an error has occurred, the OpenSlide object is
+/// in an error state, and the only useful operation on it is to close it.
+pub unsafe fn get_error(osr: *const OpenSlideT) -> Result<Option<String>, OpenSlideError> {
+    let c_msg = (api()?.openslide_get_error)(osr); // This is unsafe
+    // The error message is allowed to be lossy, such that it is never lost on invalid UTF-8.
+    if c_msg.is_null() {
+        return Ok(None);
+    }
+    let msg = ffi::CStr::from_ptr(c_msg).to_string_lossy().into_owned();
+    Ok(Some(msg))
 }
-*/
 
 // ---------------
 // Properties
 // ---------------
 
 /// Get the NULL-terminated array of property names.
-pub unsafe fn get_property_names(osr: *const OpenSlideT) -> Result<Vec<String>, Error> {
-    let string_values = {
-        let null_terminated_array_ptr = openslide_get_property_names(osr);
-        let mut counter = 0;
-        let mut loc = null_terminated_array_ptr;
-        while !(*loc).is_null() {
-            counter += 1;
-            loc = loc.offset(1);
-        }
-        //let c_array = ffi::CStr::from_ptr(null_terminated_array_ptr);
-        let values = std::slice::from_raw_parts(null_terminated_array_ptr, counter as usize);
-        values
-            .iter()
-            .map(|&p| ffi::CStr::from_ptr(p)) // iterator of &CStr
-            .map(|cs| cs.to_bytes()) // iterator of &[u8]
-            .map(|bs| str::from_utf8(bs).unwrap()) // iterator of &str
-            .map(|ss| ss.to_owned())
-            .collect()
-    };
-    Ok(string_values)
+pub unsafe fn get_property_names(osr: *const OpenSlideT) -> Result<Vec<String>, OpenSlideError> {
+    null_terminated_array_to_vec((api()?.openslide_get_property_names)(osr))
 }
 
 /// Get the value of a single property.
-pub unsafe fn get_property_value(osr: *const OpenSlideT, name: &str) -> Result<String, Error> {
+///
+/// Returns `None` if the slide has no property with the given name.
+pub unsafe fn get_property_value(
+    osr: *const OpenSlideT,
+    name: &str,
+) -> Result<Option<String>, OpenSlideError> {
+    let c_name = ffi::CString::new(name)?;
+    c_str_to_string((api()?.openslide_get_property_value)(osr, c_name.as_ptr()))
+}
+
+// ---------------
+// Associated images
+// ---------------
+
+/// Get the NULL-terminated array of associated image names.
+pub unsafe fn get_associated_image_names(
+    osr: *const OpenSlideT,
+) -> Result<Vec<String>, OpenSlideError> {
+    null_terminated_array_to_vec((api()?.openslide_get_associated_image_names)(osr))
+}
+
+/// Get the dimensions of an associated image.
+pub unsafe fn get_associated_image_dimensions(
+    osr: *const OpenSlideT,
+    name: &str,
+) -> Result<(i64, i64), OpenSlideError> {
+    let c_name = ffi::CString::new(name)?;
+    let mut width: i64 = 0;
+    let mut height: i64 = 0;
+    let get_dimensions = api()?.openslide_get_associated_image_dimensions;
+    get_dimensions(osr, c_name.as_ptr(), &mut width, &mut height); // This is unsafe
+    Ok((width, height))
+}
+
+/// Copy pre-multiplied ARGB data from an associated image.
+///
+/// The dimensions of the associated image are looked up first, such that the returned buffer has
+/// `width * height` elements.
+pub unsafe fn read_associated_image(
+    osr: *const OpenSlideT,
+    name: &str,
+) -> Result<Vec<u32>, OpenSlideError> {
+    let (w, h) = get_associated_image_dimensions(osr, name)?;
+    if w < 0 || h < 0 {
+        return Err(OpenSlideError::InvalidArgument(format!(
+            "Slide has no associated image named {}",
+            name
+        )));
+    }
+    let mut buffer = vec![0u32; buffer_len(w, h)?];
+    read_associated_image_into(osr, name, &mut buffer)?;
+    Ok(buffer)
+}
+
+/// Copy pre-multiplied ARGB data from an associated image into the given buffer.
+///
+/// The buffer must have room for at least `width * height` pixels of the associated image.
+pub unsafe fn read_associated_image_into(
+    osr: *const OpenSlideT,
+    name: &str,
+    dest: &mut [u32],
+) -> Result<(), OpenSlideError> {
+    let (w, h) = get_associated_image_dimensions(osr, name)?;
+    if w < 0 || h < 0 {
+        return Err(OpenSlideError::InvalidArgument(format!(
+            "Slide has no associated image named {}",
+            name
+        )));
+    }
+    let len = buffer_len(w, h)?;
+    if dest.len() < len {
+        return Err(OpenSlideError::InvalidArgument(format!(
+            "Buffer of length {} is too small for associated image {} of {} pixels",
+            dest.len(),
+            name,
+            len
+        )));
+    }
     let c_name = ffi::CString::new(name)?;
-    let value = {
-        let c_value = openslide_get_property_value(osr, c_name.as_ptr());
-        ffi::CStr::from_ptr(c_value).to_string_lossy().into_owned()
-    };
-    Ok(value)
+    (api()?.openslide_read_associated_image)(osr, c_name.as_ptr(), dest.as_mut_ptr()); // This is unsafe
+    Ok(())
+}
+
+// ---------------
+// Miscellaneous
+// ---------------
+
+/// Get the version of the OpenSlide library.
+pub fn get_version() -> Result<String, OpenSlideError> {
+    let version = unsafe { c_str_to_string((api()?.openslide_get_version)())? };
+    version.ok_or_else(|| OpenSlideError::Library(String::from("OpenSlide returned no version")))
+}
+
+// ---------------
+// ICC profiles (OpenSlide 4.x)
+// ---------------
+
+/// Get the size in bytes of the ICC color profile for the whole slide image.
+///
+/// Returns 0 if the slide has no profile, and -1 if an error occurred.
+#[cfg(openslide4)]
+pub unsafe fn get_icc_profile_size(osr: *const OpenSlideT) -> Result<i64, OpenSlideError> {
+    let get_size = optional(api()?.openslide_get_icc_profile_size, "openslide_get_icc_profile_size")?;
+    let size = get_size(osr); // This is unsafe
+    Ok(size)
+}
+
+/// Copy the ICC color profile from a whole slide image.
+///
+/// The size of the profile is looked up first, such that the returned buffer has the size of the
+/// profile.
+#[cfg(openslide4)]
+pub unsafe fn read_icc_profile(osr: *const OpenSlideT) -> Result<Vec<u8>, OpenSlideError> {
+    let size = get_icc_profile_size(osr)?;
+    if size < 0 {
+        return Err(OpenSlideError::Library(String::from("Could not get ICC profile size")));
+    }
+    let mut buffer: Vec<u8> = Vec::with_capacity(size as usize);
+    let p_buffer = buffer.as_mut_ptr() as *mut libc::c_void;
+    let read = optional(api()?.openslide_read_icc_profile, "openslide_read_icc_profile")?;
+    read(osr, p_buffer); // This is unsafe
+    buffer.set_len(size as usize);
+    Ok(buffer)
+}
+
+/// Get the size in bytes of the ICC color profile for an associated image.
+///
+/// Returns 0 if the image has no profile, and -1 if an error occurred.
+#[cfg(openslide4)]
+pub unsafe fn get_associated_image_icc_profile_size(
+    osr: *const OpenSlideT,
+    name: &str,
+) -> Result<i64, OpenSlideError> {
+    let c_name = ffi::CString::new(name)?;
+    let get_size = optional(
+        api()?.openslide_get_associated_image_icc_profile_size,
+        "openslide_get_associated_image_icc_profile_size",
+    )?;
+    let size = get_size(osr, c_name.as_ptr()); // This is unsafe
+    Ok(size)
+}
+
+/// Copy the ICC color profile from an associated image.
+#[cfg(openslide4)]
+pub unsafe fn read_associated_image_icc_profile(
+    osr: *const OpenSlideT,
+    name: &str,
+) -> Result<Vec<u8>, OpenSlideError> {
+    let size = get_associated_image_icc_profile_size(osr, name)?;
+    if size < 0 {
+        return Err(OpenSlideError::InvalidArgument(format!(
+            "Could not get ICC profile size of associated image {}",
+            name
+        )));
+    }
+    let c_name = ffi::CString::new(name)?;
+    let mut buffer: Vec<u8> = Vec::with_capacity(size as usize);
+    let p_buffer = buffer.as_mut_ptr() as *mut libc::c_void;
+    let read = optional(
+        api()?.openslide_read_associated_image_icc_profile,
+        "openslide_read_associated_image_icc_profile",
+    )?;
+    read(osr, c_name.as_ptr(), p_buffer); // This is unsafe
+    buffer.set_len(size as usize);
+    Ok(buffer)
+}
+
+// ---------------
+// Caching (OpenSlide 4.x)
+// ---------------
+
+/// Create a tile cache, with a capacity in bytes, that can be shared between slides.
+#[cfg(openslide4)]
+pub fn cache_create(capacity: usize) -> Result<*mut OpenSlideCacheT, OpenSlideError> {
+    let create = optional(api()?.openslide_cache_create, "openslide_cache_create")?;
+    let cache = unsafe { create(capacity) };
+    if cache.is_null() {
+        return Err(OpenSlideError::Library(String::from("Could not create tile cache")));
+    }
+    Ok(cache)
+}
+
+/// Use the given tile cache for the slide, in stead of the cache private to the slide.
+///
+/// The slide keeps its own reference to the cache, so the cache may be released afterwards.
+#[cfg(openslide4)]
+pub unsafe fn set_cache(
+    osr: *const OpenSlideT,
+    cache: *mut OpenSlideCacheT,
+) -> Result<(), OpenSlideError> {
+    let set = optional(api()?.openslide_set_cache, "openslide_set_cache")?;
+    set(osr, cache); // This is unsafe
+    Ok(())
+}
+
+/// Release the reference to a tile cache.
+#[cfg(openslide4)]
+pub unsafe fn cache_release(cache: *mut OpenSlideCacheT) {
+    // A valid cache implies that the library is loaded, and has the cache functions
+    if let Some(release) = api().ok().and_then(|api| api.openslide_cache_release) {
+        release(cache); // This is unsafe
+    }
+}
+
+// ---------------
+// Helpers
+// ---------------
+
+/// Convert a C string returned by OpenSlide into an owned string.
+///
+/// Returns `None` for a NULL pointer, and an error if the string is not valid UTF-8.
+unsafe fn c_str_to_string(c_str: *const libc::c_char) -> Result<Option<String>, OpenSlideError> {
+    if c_str.is_null() {
+        return Ok(None);
+    }
+    let value = ffi::CStr::from_ptr(c_str).to_str()?;
+    Ok(Some(value.to_owned()))
+}
+
+/// Collect a NULL-terminated array of C strings, as returned by OpenSlide, into a vector.
+///
+/// A NULL array is treated as an empty array.
+unsafe fn null_terminated_array_to_vec(
+    null_terminated_array_ptr: *const *const libc::c_char,
+) -> Result<Vec<String>, OpenSlideError> {
+    if null_terminated_array_ptr.is_null() {
+        return Ok(Vec::new());
+    }
+    let mut counter = 0;
+    let mut loc = null_terminated_array_ptr;
+    while !(*loc).is_null() {
+        counter += 1;
+        loc = loc.offset(1);
+    }
+    let values = std::slice::from_raw_parts(null_terminated_array_ptr, counter as usize);
+    values
+        .iter()
+        .map(|&p| ffi::CStr::from_ptr(p)) // iterator of &CStr
+        .map(|cs| cs.to_str().map(|ss| ss.to_owned())) // iterator of Result<String, _>
+        .collect::<Result<Vec<String>, _>>()
+        .map_err(OpenSlideError::from)
+}
+
+/// Number of pixels in a buffer of the given dimensions, or an error if they are negative or too
+/// large.
+fn buffer_len(w: i64, h: i64) -> Result<usize, OpenSlideError> {
+    if w < 0 || h < 0 {
+        return Err(OpenSlideError::InvalidArgument(format!(
+            "Region dimensions ({}, {}) are negative",
+            w, h
+        )));
+    }
+    w.checked_mul(h)
+        .and_then(|len| len.to_usize())
+        .ok_or_else(|| {
+            OpenSlideError::InvalidArgument(format!("Region dimensions ({}, {}) are too large", w, h))
+        })
 }
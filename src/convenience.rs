@@ -6,29 +6,57 @@ use std::cmp::PartialOrd;
 use std::collections::HashMap;
 use std::fmt::{Debug, Display};
 use std::path::Path;
+use std::sync::Arc;
 
-use failure::{format_err, Error};
-use image::RgbaImage;
+use image::{RgbImage, RgbaImage};
 use num::zero;
-use num::{Integer, Num, ToPrimitive, Unsigned};
-
+use num::{Integer, Num, ToPrimitive};
+
+#[cfg(openslide4)]
+use backend::TileCache;
+use backend::{LibOpenSlideBackend, SlideBackend};
+use error::{conversion_error, OpenSlideError};
+use geometry::{Anchor, Level, Level0, LevelN, Point, Rect, Size, Space};
+use info::SlideInfo;
+use resample::{self, Axis, Filter};
+use units::{self, Calibration, PointUm};
 use {bindings, properties, utils};
 
 /// A convenient OpenSlide object with the ordinary OpenSlide functions as methods
 ///
-/// This wraps the bindings found in the bindings module, but has a more (in my opinion) convenient
-/// API for rust. It also contains some other convenience methods.
+/// This is a front-end over a `SlideBackend`, with a more (in my opinion) convenient API for rust
+/// than the backend itself. It also contains some other convenience methods. By default, the slide
+/// is read with the OpenSlide C library (`LibOpenSlideBackend`), but any backend can be used with
+/// `OpenSlide::from_backend()`.
+///
+/// Cloning is cheap: clones share the same backend, which (for the default backend) closes the
+/// slide when the last clone is dropped. The object is `Send` and `Sync`, so it can also be shared
+/// between threads, e.g. in an `Arc<OpenSlide>`.
 #[derive(Clone)]
 pub struct OpenSlide {
-    osr: *const bindings::OpenSlideT,
+    backend: Arc<dyn SlideBackend>,
+    bounds_policy: BoundsPolicy,
+    info: Arc<SlideInfo>,
+    calibration: Option<Calibration>,
     pub properties: properties::Properties,
 }
 
-impl Drop for OpenSlide {
-    /// This method is called when the object in dropped, and tries to close the slide.
-    fn drop(&mut self) {
-        unsafe { bindings::close(self.osr) };
-    }
+/// How regions that are not entirely inside the level are read by `read_region`,
+/// `read_region_as` and `read_region_composited`.
+///
+/// A region may extend past any edge of the level, as the top left corner may also be negative.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BoundsPolicy {
+    /// Clip the region to the level, such that the returned image only covers the part of the
+    /// region inside the level, and may be smaller than requested. Returns an `OutOfBounds`
+    /// error if no part of the region is inside the level.
+    #[default]
+    Clamp,
+    /// Return an `OutOfBounds` error if any part of the region is outside the level.
+    Error,
+    /// Return an image of the requested size, where the pixels outside the level are transparent
+    /// (or have the background color, for `read_region_composited`).
+    Pad,
 }
 
 impl OpenSlide {
@@ -36,87 +64,96 @@ impl OpenSlide {
     ///
     /// This function can be expensive; avoid calling it unnecessarily. For example, a tile server
     /// should not create a new object on every tile request. Instead, it should maintain a cache
-    /// of OpenSlide objects and reuse them when possible.
-    pub fn new(filename: &Path) -> Result<OpenSlide, Error> {
-        if !filename.exists() {
-            return Err(format_err!("Error: Nonexisting path: {}", filename.display()));
-        }
-
-        let osr = bindings::open(filename.to_str().ok_or(format_err!("Error: Path to &str"))?)?;
-
-        let mut property_map = HashMap::<String, String>::new();
-        for name in unsafe { bindings::get_property_names(osr)? } {
-            property_map.insert(name.clone(), unsafe { bindings::get_property_value(osr, &name)? });
-        }
-        let properties = properties::Properties::new(&property_map);
+    /// of OpenSlide objects and reuse them when possible, e.g. a `pool::SlidePool`.
+    pub fn new(filename: &Path) -> Result<OpenSlide, OpenSlideError> {
+        OpenSlide::from_backend(LibOpenSlideBackend::open(filename)?)
+    }
 
+    /// Create an OpenSlide object that reads the slide with the given backend.
+    ///
+    /// Returns an error if the properties of the slide can not be read or parsed.
+    pub fn from_backend<B: SlideBackend + 'static>(backend: B) -> Result<OpenSlide, OpenSlideError> {
+        let property_map = backend.properties()?;
+        let properties = properties::Properties::new(&property_map)?;
+        let info = SlideInfo::new(&backend, &property_map)?;
         Ok(OpenSlide {
-            osr,
+            backend: Arc::new(backend),
+            bounds_policy: BoundsPolicy::default(),
+            info: Arc::new(info),
+            calibration: None,
             properties,
         })
     }
 
-    /// Get the number of levels in the whole slide image.
-    pub fn get_level_count(&self) -> Result<u32, Error> {
-        let num_levels = unsafe { bindings::get_level_count(self.osr)? };
-
-        if num_levels < -1 {
-            Err(format_err!(
-                "Error: Number of levels is {}, this is an unknown error from OpenSlide. \
-                 OpenSlide returns -1 if an error occured. \
-                 See OpenSlide C API documentation.",
-                num_levels
-            ))
-        } else if num_levels == -1 {
-            Err(format_err!(
-                "Error: Number of levels is -1, this is a known error from OpenSlide. \
-                 OpenSlide returns -1 if an error occured. \
-                 See OpenSlide C API documentation.",
-            ))
-        } else {
-            Ok(num_levels as u32)
+    /// Use the given policy for regions that are not entirely inside the level. The default is
+    /// `BoundsPolicy::Clamp`.
+    ///
+    /// Use e.g. `slide.clone().with_bounds_policy(BoundsPolicy::Pad)` to get a slide object with
+    /// another policy, that shares the backend with `slide`.
+    pub fn with_bounds_policy(mut self, bounds_policy: BoundsPolicy) -> OpenSlide {
+        self.bounds_policy = bounds_policy;
+        self
+    }
+
+    /// Get the policy for regions that are not entirely inside the level.
+    pub fn bounds_policy(&self) -> BoundsPolicy {
+        self.bounds_policy
+    }
+
+    /// Use the given calibration for conversions between pixels and physical units, in stead of
+    /// the one in the properties of the slide. This is needed for slides without microns per
+    /// pixel or objective power, or to correct them.
+    pub fn with_calibration(mut self, calibration: Calibration) -> OpenSlide {
+        self.calibration = Some(calibration);
+        self
+    }
+
+    /// Get the calibration for conversions between pixels and physical units: the one given with
+    /// `with_calibration`, or else the one in the properties of the slide.
+    ///
+    /// Returns a `MissingCalibration` error if the slide has no microns per pixel, and none were
+    /// given.
+    pub fn calibration(&self) -> Result<Calibration, OpenSlideError> {
+        match self.calibration {
+            Some(calibration) => Ok(calibration),
+            None => Calibration::from_info(&self.info),
         }
     }
 
+    /// Get the objective power (nominal magnification) of level 0: the one in the calibration
+    /// given with `with_calibration`, or else `openslide.objective-power`. Unlike `calibration`,
+    /// this does not need the microns per pixel.
+    ///
+    /// Returns a `MissingCalibration` error if the objective power is unknown.
+    pub fn objective_power(&self) -> Result<f64, OpenSlideError> {
+        self.calibration
+            .and_then(|calibration| calibration.objective_power)
+            .or_else(|| self.info.objective_power())
+            .ok_or_else(units::missing_objective_power)
+    }
+
+    /// Get the levels and geometry of the slide, which are read once when the slide is opened.
+    pub fn info(&self) -> &SlideInfo {
+        &self.info
+    }
+
+    /// Get the backend that this slide is read with.
+    pub fn backend(&self) -> &dyn SlideBackend {
+        &*self.backend
+    }
+
+    /// Get the number of levels in the whole slide image.
+    pub fn get_level_count(&self) -> Result<u32, OpenSlideError> {
+        Ok(self.info.level_count())
+    }
+
     /// Get the dimensions of level 0 (the largest level).
     ///
     /// This method returns the (width, height) number of pixels of the level 0 whole slide image.
     ///
     /// This is the same as calling get_level_dimensions(level) with level=0.
-    pub fn get_level0_dimensions(&self) -> Result<(u64, u64), Error> {
-        let (width, height) = unsafe { bindings::get_level0_dimensions(self.osr)? };
-
-        if width < -1 {
-            return Err(format_err!(
-                "Error: Width is {}, this is an unknown error from OpenSlide. \
-                 OpenSlide returns -1 if an error occured. \
-                 See OpenSlide C API documentation.",
-                width
-            ));
-        } else if width == -1 {
-            return Err(format_err!(
-                "Error: Width is -1, this is a known error from OpenSlide. \
-                 OpenSlide returns -1 if an error occured. \
-                 See OpenSlide C API documentation.",
-            ));
-        }
-
-        if height < -1 {
-            return Err(format_err!(
-                "Error: Height is {}, this is an unknown error from OpenSlide. \
-                 OpenSlide returns -1 if an error occured. \
-                 See OpenSlide C API documentation.",
-                width
-            ));
-        } else if height == -1 {
-            return Err(format_err!(
-                "Error: Height is -1, this is a known error from OpenSlide. \
-                 OpenSlide returns -1 if an error occured. \
-                 See OpenSlide C API documentation.",
-            ));
-        }
-
-        Ok((width as u64, height as u64))
+    pub fn get_level0_dimensions(&self) -> Result<(u64, u64), OpenSlideError> {
+        Ok(self.info.dimensions())
     }
 
     /// Get the dimensions of level 0 (the largest level).
@@ -126,43 +163,15 @@ impl OpenSlide {
     pub fn get_level_dimensions<T: Integer + ToPrimitive + Debug + Display + Clone + Copy>(
         &self,
         level: T,
-    ) -> Result<(u64, u64), Error> {
-        self.assert_level_validity(level)?;
-        let level = level.to_i32().ok_or(format_err!("Conversion to primitive error"))?;
-
-        let (width, height) = unsafe { bindings::get_level_dimensions(self.osr, level)?};
-
-        if width < -1 {
-            return Err(format_err!(
-                "Error: Width is {}, this is an unknown error from OpenSlide. \
-                 OpenSlide returns -1 if an error occured. \
-                 See OpenSlide C API documentation.",
-                width
-            ));
-        } else if width == -1 {
-            return Err(format_err!(
-                "Error: Width is -1, this is a known error from openslide. \
-                 OpenSlide returns -1 if an error occured. \
-                 See OpenSlide C API documentation.",
-            ));
-        }
-
-        if height < -1 {
-            return Err(format_err!(
-                "Error: Height is {}, this is an unknown error from OpenSlide. \
-                 OpenSlide returns -1 if an error occured. \
-                 See OpenSlide C API documentation.",
-                width
-            ));
-        } else if height == -1 {
-            return Err(format_err!(
-                "Error: Height is -1, this is a known error from openslide. \
-                 OpenSlide returns -1 if an error occured. \
-                 See OpenSlide C API documentation.",
-            ));
-        }
+    ) -> Result<(u64, u64), OpenSlideError> {
+        let level = self.info.level(self.assert_level_validity(level)?)?;
+        Ok((level.width, level.height))
+    }
 
-        Ok((width as u64, height as u64))
+    /// Get the size of a given level. Returns an error if the level is invalid.
+    pub fn level_size(&self, level: Level) -> Result<Size<LevelN>, OpenSlideError> {
+        let (width, height) = self.get_level_dimensions(level.0)?;
+        Ok(Size::new(width, height))
     }
 
     /// Get the downsampling factor of a given level.
@@ -171,22 +180,8 @@ impl OpenSlide {
     >(
         &self,
         level: T,
-    ) -> Result<f64, Error> {
-        self.assert_level_validity(level)?;
-        let level = level.to_i32().ok_or(format_err!("Conversion to primitive error"))?;
-        let downsample_factor = unsafe { bindings::get_level_downsample(self.osr, level)? };
-
-        if downsample_factor < 0.0 {
-            return Err(format_err!(
-                "Error: When trying to get a downsample factor for level {},\
-                 OpenSlide returned a downsample factor {}, this is an error from \
-                 OpenSlide. OpenSlide returns -1.0 if an error occured. \
-                 See OpenSlide C API documentation.",
-                level, downsample_factor
-            ));
-        }
-
-        Ok(downsample_factor)
+    ) -> Result<f64, OpenSlideError> {
+        Ok(self.info.level(self.assert_level_validity(level)?)?.downsample)
     }
 
     /// Get the best level to use for displaying the given downsample factor.
@@ -195,113 +190,334 @@ impl OpenSlide {
     >(
         &self,
         downsample_factor: T,
-    ) -> Result<u32, Error> {
+    ) -> Result<u32, OpenSlideError> {
         if downsample_factor < zero() {
-            return Err(format_err!(
-                "Error: Only non-negative downsample factor is allowed. \
-                 You specified {}. ",
+            return Err(OpenSlideError::InvalidArgument(format!(
+                "Only non-negative downsample factor is allowed. You specified {}",
                 downsample_factor
-            ));
+            )));
         }
 
-        let level = unsafe {
-            bindings::get_best_level_for_downsample(
-                self.osr,
-                downsample_factor
-                    .to_f64()
-                    .ok_or(format_err!("Conversion to primitive error"))?,
-            )?
+        self.backend
+            .best_level_for_downsample(downsample_factor.to_f64().ok_or_else(conversion_error)?)
+    }
+
+    /// Copy pre-multiplied ARGB data from a whole slide image.
+    ///
+    /// This function reads and decompresses a region of a whole slide image into an RGBA image.
+    /// Regions that are not entirely inside the level are handled according to the bounds policy
+    /// of the slide (see `with_bounds_policy`).
+    ///
+    /// Args:
+    ///     top_left_lvl0_row: Row coordinate (increasing downwards) of top left pixel position
+    ///     top_left_lvl0_col: Column coordinate (increasing to the right) of top left pixel
+    ///                        position
+    ///     level: At which level to grab the region from
+    ///     height: Height in pixels of the outputted region
+    ///     width: Width in pixels of the outputted region
+    pub fn read_region<T: Integer + ToPrimitive + Debug + Display + Clone + Copy>(
+        &self,
+        top_left_lvl0_row: T,
+        top_left_lvl0_col: T,
+        level: T,
+        height: T,
+        width: T,
+    ) -> Result<RgbaImage, OpenSlideError> {
+        let (buffer, height, width) =
+            self.read_bounded_region(top_left_lvl0_row, top_left_lvl0_col, level, height, width)?;
+        let word_repr = utils::WordRepresentation::BigEndian;
+        utils::decode_buffer(&buffer, height, width, word_repr)
+    }
+
+    /// Read a rectangle of a whole slide image into an RGBA image.
+    ///
+    /// This is `read_region` with the types of the `geometry` module. The rectangle is given in
+    /// the level 0 reference frame (`Rect<Level0>`), and read from `level` at the corresponding
+    /// size, or in the reference frame of `level` itself (`Rect<LevelN>`). The bounds policy
+    /// applies in the same way.
+    pub fn read_rect<S: Space>(
+        &self,
+        level: Level,
+        rect: Rect<S>,
+    ) -> Result<RgbaImage, OpenSlideError> {
+        let level = self.assert_level_validity(level.0)?;
+        let (origin, size) = rect.region(self, Level(level))?;
+        let (buffer, height, width) =
+            self.read_level_region(origin.y, origin.x, level, size.height, size.width)?;
+        let word_repr = utils::WordRepresentation::BigEndian;
+        utils::decode_buffer(&buffer, height, width, word_repr)
+    }
+
+    /// Read a region of a whole slide image at an arbitrary downsample factor.
+    ///
+    /// The region is read from the best level for the downsample factor (see
+    /// `get_best_level_for_downsample`), and resampled with `filter`. When downsampling, the
+    /// filters other than `Filter::Nearest` are antialiased. The output always has the requested
+    /// size, and pixels outside the slide are transparent, as with `BoundsPolicy::Pad`.
+    ///
+    /// Args:
+    ///     anchor: Top left corner or center of the region, in the level 0 reference frame
+    ///     downsample: Downsample factor relative to level 0, e.g. 2.0 for half the resolution of
+    ///                 level 0, or 0.5 for twice the resolution
+    ///     size: Size in pixels of the outputted region
+    ///     filter: Resampling filter
+    ///
+    /// Returns an `InvalidArgument` error if the downsample factor is not positive, or if the
+    /// size is zero.
+    pub fn read_region_scaled(
+        &self,
+        anchor: Anchor,
+        downsample: f64,
+        size: Size<LevelN>,
+        filter: Filter,
+    ) -> Result<RgbaImage, OpenSlideError> {
+        let (left, top) = match anchor {
+            Anchor::TopLeft(point) => (point.x as f64, point.y as f64),
+            Anchor::Center(point) => (
+                point.x as f64 - size.width as f64 * downsample / 2.0,
+                point.y as f64 - size.height as f64 * downsample / 2.0,
+            ),
         };
+        self.read_resampled_image(left, top, (downsample, downsample), size, filter)
+    }
 
-        if level < -1 {
-            Err(format_err!(
-                "Error: Returned level is {}, this is an unknown error from OpenSlide. \
-                 OpenSlide returns -1 if an error occured. \
-                 See OpenSlide C API documentation.",
-                level
-            ))
-        } else if level == -1 {
-            Err(format_err!(
-                "Error: Returned level is -1, this is a known error from openslide. \
-                 OpenSlide returns -1 if an error occured. \
-                 See OpenSlide C API documentation.",
-            ))
-        } else {
-            Ok(level as u32)
+    /// `read_resampled`, decoded into an RGBA image.
+    pub(crate) fn read_resampled_image(
+        &self,
+        left: f64,
+        top: f64,
+        downsample: (f64, f64),
+        size: Size<LevelN>,
+        filter: Filter,
+    ) -> Result<RgbaImage, OpenSlideError> {
+        let buffer = self.read_resampled(left, top, downsample, size, filter)?;
+        let word_repr = utils::WordRepresentation::BigEndian;
+        utils::decode_buffer(&buffer, size.height, size.width, word_repr)
+    }
+
+    /// Read a region with its top left corner at (`left`, `top`) in the level 0 reference frame,
+    /// resampled at the (x, y) downsample factors relative to level 0 from the best level for
+    /// the smaller factor, into pre-multiplied ARGB words.
+    pub(crate) fn read_resampled(
+        &self,
+        left: f64,
+        top: f64,
+        downsample: (f64, f64),
+        size: Size<LevelN>,
+        filter: Filter,
+    ) -> Result<Vec<u32>, OpenSlideError> {
+        for &factor in &[downsample.0, downsample.1] {
+            if !(factor.is_finite() && factor > 0.0) {
+                return Err(OpenSlideError::InvalidArgument(format!(
+                    "Downsample factor must be positive, not {}",
+                    factor
+                )));
+            }
+        }
+        if size.width == 0 || size.height == 0 {
+            return Err(OpenSlideError::InvalidArgument(format!(
+                "Region dimensions ({}, {}) must not be zero",
+                size.width, size.height
+            )));
         }
+        let level = self.get_best_level_for_downsample(downsample.0.min(downsample.1))?;
+        let level_downsample = self.info.level(level)?.downsample;
+
+        // Check that the output can be allocated, before it is indexed with usize
+        pixel_count(size.width, size.height)?;
+        // The source pixels that the filter needs, in the reference frame of the level
+        let columns = Axis::new(
+            left / level_downsample,
+            downsample.0 / level_downsample,
+            size.width as usize,
+            filter,
+        );
+        let rows = Axis::new(
+            top / level_downsample,
+            downsample.1 / level_downsample,
+            size.height as usize,
+            filter,
+        );
+        let origin = Point::<LevelN>::new(columns.first, rows.first).to_level0(self, Level(level))?;
+        let (source_width, source_height) = (columns.len as u64, rows.len as u64);
+
+        let mut buffer = vec![0u32; pixel_count(source_width, source_height)?];
+        self.backend.read_region(
+            &mut buffer,
+            origin.x,
+            origin.y,
+            level,
+            source_width,
+            source_height,
+        )?;
+        Ok(resample::resample(&buffer, &columns, &rows))
     }
 
-    /// Return (new_height, new_width) where
+    /// Read a region of a whole slide image with pixels of a given physical size.
     ///
-    /// new_height = min(height, max_height)
-    /// new_width = min(width, max_width)
+    /// This is `read_region_scaled` at the downsample factors that give square pixels of
+    /// `target_mpp` microns, according to the calibration of the slide (see `calibration`). On
+    /// slides with non-square pixels, the factors differ between the directions.
     ///
-    /// and max_{height, width} are computed based on the top left corner coordinates and the
-    /// dimensions of the image.
-    fn get_feasible_dimensions<
-        T: Integer + Unsigned + ToPrimitive + Debug + Display + Clone + Copy,
-    >(
+    /// Args:
+    ///     origin: Top left corner of the region, in microns from the top left corner of level 0
+    ///     size: Size in pixels of the outputted region
+    ///     target_mpp: Microns per pixel of the outputted region
+    ///     filter: Resampling filter
+    pub fn read_region_at_mpp(
+        &self,
+        origin: PointUm,
+        size: Size<LevelN>,
+        target_mpp: f64,
+        filter: Filter,
+    ) -> Result<RgbaImage, OpenSlideError> {
+        let calibration = self.calibration()?;
+        let downsample = calibration.downsample_for_mpp(target_mpp)?;
+        let origin = calibration.to_pixels(origin)?;
+        self.read_resampled_image(origin.x as f64, origin.y as f64, downsample, size, filter)
+    }
+
+    /// Read a region of a whole slide image at a nominal magnification, e.g. 20.0 for 20x.
+    ///
+    /// This is `read_region_scaled` at the downsample factor that gives `magnification`,
+    /// according to the objective power of the slide (see `objective_power`). The slide does not
+    /// need microns per pixel.
+    ///
+    /// Args:
+    ///     origin: Top left corner of the region, in the level 0 reference frame
+    ///     size: Size in pixels of the outputted region
+    ///     magnification: Nominal magnification of the outputted region
+    ///     filter: Resampling filter
+    pub fn read_region_at_magnification(
+        &self,
+        origin: Point<Level0>,
+        size: Size<LevelN>,
+        magnification: f64,
+        filter: Filter,
+    ) -> Result<RgbaImage, OpenSlideError> {
+        let objective_power = self.objective_power()?;
+        let downsample = units::downsample_for_magnification(objective_power, magnification)?;
+        self.read_region_scaled(Anchor::TopLeft(origin), downsample, size, filter)
+    }
+
+    /// Read a region of a whole slide image into the given pixel format.
+    ///
+    /// This is `read_region` for other layouts than RGBA, e.g. RGB composited onto a background
+    /// color, or normalised floats in planes, which can be fed to machine learning frameworks
+    /// without another pass over the data. The bounds policy applies in the same way.
+    ///
+    /// Args:
+    ///     top_left_lvl0_row: Row coordinate (increasing downwards) of top left pixel position
+    ///     top_left_lvl0_col: Column coordinate (increasing to the right) of top left pixel
+    ///                        position
+    ///     level: At which level to grab the region from
+    ///     height: Height in pixels of the outputted region
+    ///     width: Width in pixels of the outputted region
+    ///     format: Pixel layout of the outputted region
+    pub fn read_region_as<T: Integer + ToPrimitive + Debug + Display + Clone + Copy>(
         &self,
         top_left_lvl0_row: T,
         top_left_lvl0_col: T,
         level: T,
         height: T,
         width: T,
-    ) -> Result<(u64, u64), Error> {
-        let (max_width, max_height) = self.get_level_dimensions(level)?;
-        let downsample_factor = self.get_level_downsample(level)?;
-
-        let tl_row_this_lvl = top_left_lvl0_row
-            .to_f64()
-            .ok_or(format_err!("Conversion to primitive error"))?
-            / downsample_factor;
-        let tl_col_this_lvl = top_left_lvl0_col
-            .to_f64()
-            .ok_or(format_err!("Conversion to primitive error"))?
-            / downsample_factor;
-
-        let new_height = height
-            .to_u64()
-            .ok_or(format_err!("Conversion to primitive error"))?
-            .min(max_height - tl_row_this_lvl.round() as u64);
-        let new_width = width
-            .to_u64()
-            .ok_or(format_err!("Conversion to primitive error"))?
-            .min(max_width - tl_col_this_lvl.round() as u64);
-
-        if new_height < height
-            .to_u64()
-            .ok_or(format_err!("Conversion to primitive error"))?
-        {
-            println!(
-                "WARNING: Requested region height is changed from {} to {} in order to fit",
-                height, new_height
-            );
-        }
-        if new_width < width
-            .to_u64()
-            .ok_or(format_err!("conversion to primitive error"))?
-        {
-            println!(
-                "WARNING: Requested region width is changed from {} to {} in order to fit",
-                width, new_width
-            );
-        }
+        format: utils::PixelFormat,
+    ) -> Result<utils::DecodedRegion, OpenSlideError> {
+        let (buffer, height, width) =
+            self.read_bounded_region(top_left_lvl0_row, top_left_lvl0_col, level, height, width)?;
+        let word_repr = utils::WordRepresentation::BigEndian;
+        utils::decode_buffer_as(&buffer, height, width, word_repr, format)
+    }
 
-        if new_height > max_height {
-            return Err(format_err!("Requested height {} exceeds maximum {}", height, max_height));
-        }
+    /// Copy pre-multiplied ARGB data from a whole slide image into a buffer of the caller.
+    ///
+    /// This is the allocation-free variant of `read_region`, for reading many regions in a row:
+    /// the region is read straight into `dest`, which must have room for at least
+    /// `height * width` pixels. Pixels are `0xAARRGGBB` words in row-major order, as in the
+    /// OpenSlide C library; use `utils::unpremultiply_in_place` to un-premultiply them.
+    ///
+    /// Regardless of the bounds policy, the region is not clipped to the level: pixels outside of
+    /// the slide are transparent (0), so the buffer is always filled in the same way.
+    ///
+    /// Args:
+    ///     dest: Buffer to copy the pixels into
+    ///     top_left_lvl0_row: Row coordinate (increasing downwards) of top left pixel position
+    ///     top_left_lvl0_col: Column coordinate (increasing to the right) of top left pixel
+    ///                        position
+    ///     level: At which level to grab the region from
+    ///     height: Height in pixels of the region
+    ///     width: Width in pixels of the region
+    pub fn read_region_into<T: Integer + ToPrimitive + Debug + Display + Clone + Copy>(
+        &self,
+        dest: &mut [u32],
+        top_left_lvl0_row: T,
+        top_left_lvl0_col: T,
+        level: T,
+        height: T,
+        width: T,
+    ) -> Result<(), OpenSlideError> {
+        self.read_region_into_buffer(
+            dest,
+            top_left_lvl0_row.to_i64().ok_or_else(conversion_error)?,
+            top_left_lvl0_col.to_i64().ok_or_else(conversion_error)?,
+            self.assert_level_validity(level)?,
+            region_size("height", height)?,
+            region_size("width", width)?,
+        )
+    }
 
-        if new_width > max_width {
-            return Err(format_err!("Requested width {} exceeds maximum {}", width, max_width));
+    /// Read a region of a whole slide image into an existing RGBA image.
+    ///
+    /// The region has the dimensions of `image`, and is read straight into its memory whenever
+    /// possible, so the image can be reused between reads without any allocation. As with
+    /// `read_region_into`, the region is never clipped, and pixels outside of the slide are
+    /// transparent.
+    ///
+    /// Args:
+    ///     image: Image to copy the un-premultiplied pixels into
+    ///     top_left_lvl0_row: Row coordinate (increasing downwards) of top left pixel position
+    ///     top_left_lvl0_col: Column coordinate (increasing to the right) of top left pixel
+    ///                        position
+    ///     level: At which level to grab the region from
+    pub fn read_region_into_image<T: Integer + ToPrimitive + Debug + Display + Clone + Copy>(
+        &self,
+        image: &mut RgbaImage,
+        top_left_lvl0_row: T,
+        top_left_lvl0_col: T,
+        level: T,
+    ) -> Result<(), OpenSlideError> {
+        let row = top_left_lvl0_row.to_i64().ok_or_else(conversion_error)?;
+        let col = top_left_lvl0_col.to_i64().ok_or_else(conversion_error)?;
+        let level = self.assert_level_validity(level)?;
+        let (width, height) = (u64::from(image.width()), u64::from(image.height()));
+        let len = pixel_count(width, height)?;
+
+        // Any bit pattern is a valid u8 and u32, so the aligned part of the image data can be
+        // used as a buffer of words
+        let data: &mut [u8] = &mut *image;
+        let (prefix, words, _) = unsafe { data.align_to_mut::<u32>() };
+        if prefix.is_empty() && words.len() == len {
+            self.read_region_into_buffer(words, row, col, level, height, width)?;
+            utils::argb_to_rgba_in_place(words);
+        } else {
+            let mut buffer = vec![0u32; len];
+            self.read_region_into_buffer(&mut buffer, row, col, level, height, width)?;
+            utils::argb_to_rgba_in_place(&mut buffer);
+            for (pixel, word) in data.chunks_mut(4).zip(buffer) {
+                pixel.copy_from_slice(&word.to_ne_bytes());
+            }
         }
-
-        Ok((new_height, new_width))
+        Ok(())
     }
 
-    /// Copy pre-multiplied ARGB data from a whole slide image.
+    /// Read a region of a whole slide image into an opaque RGB image, with transparent and
+    /// semi-transparent pixels alpha-composited onto a background color.
     ///
-    /// This function reads and decompresses a region of a whole slide image into an RGBA image
+    /// Regions that were not scanned, e.g. in Mirax and Leica slides, are transparent, and would
+    /// be black if the alpha channel was dropped. With `background` set to `None`, they get the
+    /// background color of the slide (the `openslide.background-color` property), or white if the
+    /// slide has none. The bounds policy applies in the same way as in `read_region`, and with
+    /// `BoundsPolicy::Pad`, the pixels outside the level get the background color.
     ///
     /// Args:
     ///     top_left_lvl0_row: Row coordinate (increasing downwards) of top left pixel position
@@ -310,44 +526,37 @@ impl OpenSlide {
     ///     level: At which level to grab the region from
     ///     height: Height in pixels of the outputted region
     ///     width: Width in pixels of the outputted region
-    pub fn read_region<T: Integer + Unsigned + ToPrimitive + Debug + Display + Clone + Copy>(
+    ///     background: `[red, green, blue]` color to composite onto, in stead of the background
+    ///                 color of the slide
+    pub fn read_region_composited<
+        T: Integer + ToPrimitive + Debug + Display + Clone + Copy,
+    >(
         &self,
         top_left_lvl0_row: T,
         top_left_lvl0_col: T,
         level: T,
         height: T,
         width: T,
-    ) -> Result<RgbaImage, Error> {
-        let (height, width) = self.get_feasible_dimensions(
+        background: Option<[u8; 3]>,
+    ) -> Result<RgbImage, OpenSlideError> {
+        let background = background
+            .or_else(|| self.properties.background_color())
+            .unwrap_or([255, 255, 255]);
+        let region = self.read_region_as(
             top_left_lvl0_row,
             top_left_lvl0_col,
             level,
             height,
             width,
+            utils::PixelFormat::Composited(background),
         )?;
-
-        let buffer = unsafe {
-            bindings::read_region(
-                self.osr,
-                top_left_lvl0_col
-                    .to_i64()
-                    .ok_or(format_err!("Conversion to primitive error"))?,
-                top_left_lvl0_row
-                    .to_i64()
-                    .ok_or(format_err!("Conversion to primitive error"))?,
-                level
-                    .to_i32()
-                    .ok_or(format_err!("Conversion to primitive error"))?,
-                width
-                    .to_i64()
-                    .ok_or(format_err!("Conversion to primitive error"))?,
-                height
-                    .to_i64()
-                    .ok_or(format_err!("Conversion to primitive error"))?,
-            )?
-        };
-        let word_repr = utils::WordRepresentation::BigEndian;
-        utils::decode_buffer(&buffer, height, width, word_repr)
+        match region.samples {
+            utils::Samples::U8(samples) => {
+                RgbImage::from_raw(region.width, region.height, samples)
+                    .ok_or_else(conversion_error)
+            }
+            utils::Samples::F32(_) => Err(conversion_error()),
+        }
     }
 
     /// Get a dictionary of properties associated with the current slide
@@ -355,25 +564,208 @@ impl OpenSlide {
     /// There are some standard properties to every slide, but also a lot of vendor-specific
     /// properties. This method returns a HashMap with all key-value pairs of the properties
     /// associated with the slide.
-    pub fn get_properties(&self) -> Result<HashMap<String, String>, Error> {
-        let mut properties = HashMap::<String, String>::new();
-        for name in unsafe { bindings::get_property_names(self.osr)? } {
-            properties.insert(name.clone(), unsafe { bindings::get_property_value(self.osr, &name)? });
+    pub fn get_properties(&self) -> Result<HashMap<String, String>, OpenSlideError> {
+        self.backend.properties()
+    }
+
+    /// Get the version of the OpenSlide library.
+    pub fn version() -> Result<String, OpenSlideError> {
+        bindings::get_version()
+    }
+
+    /// Get the ICC color profile of the whole slide image (requires OpenSlide 4.x).
+    ///
+    /// Returns `None` if the slide has no color profile.
+    #[cfg(openslide4)]
+    pub fn icc_profile(&self) -> Result<Option<Vec<u8>>, OpenSlideError> {
+        self.backend.icc_profile()
+    }
+
+    /// Get the ICC color profile of the associated image with the given name (requires
+    /// OpenSlide 4.x).
+    ///
+    /// Returns `None` if the associated image has no color profile.
+    #[cfg(openslide4)]
+    pub fn associated_image_icc_profile(
+        &self,
+        name: &str,
+    ) -> Result<Option<Vec<u8>>, OpenSlideError> {
+        self.backend.associated_image_icc_profile(name)
+    }
+
+    /// Use the given tile cache for this slide (and its clones), in stead of its private cache
+    /// (requires OpenSlide 4.x).
+    #[cfg(openslide4)]
+    pub fn set_cache(&self, cache: &TileCache) -> Result<(), OpenSlideError> {
+        self.backend.set_cache(cache)
+    }
+
+    /// Get the names of the associated images of the current slide.
+    ///
+    /// Associated images are small images that are stored alongside the whole slide image, e.g.
+    /// the slide `label`, the `macro` image, or a `thumbnail`. Which images that are available
+    /// depends on the format.
+    pub fn associated_image_names(&self) -> Result<Vec<String>, OpenSlideError> {
+        self.backend.associated_image_names()
+    }
+
+    /// Get the (width, height) dimensions of the associated image with the given name.
+    pub fn associated_image_dimensions(&self, name: &str) -> Result<(u64, u64), OpenSlideError> {
+        self.backend.associated_image_dimensions(name)
+    }
+
+    /// Read the associated image with the given name into an RGBA image.
+    pub fn read_associated_image(&self, name: &str) -> Result<RgbaImage, OpenSlideError> {
+        let (width, height) = self.associated_image_dimensions(name)?;
+        let mut buffer = vec![0u32; pixel_count(width, height)?];
+        self.backend.read_associated_image(name, &mut buffer)?;
+        let word_repr = utils::WordRepresentation::BigEndian;
+        utils::decode_buffer(&buffer, height, width, word_repr)
+    }
+
+    /// Read pre-multiplied ARGB data from a region, after applying the bounds policy. Returns the
+    /// buffer, the height and the width.
+    fn read_bounded_region<T: Integer + ToPrimitive + Debug + Display + Clone + Copy>(
+        &self,
+        top_left_lvl0_row: T,
+        top_left_lvl0_col: T,
+        level: T,
+        height: T,
+        width: T,
+    ) -> Result<(Vec<u32>, u64, u64), OpenSlideError> {
+        self.read_level_region(
+            top_left_lvl0_row.to_i64().ok_or_else(conversion_error)?,
+            top_left_lvl0_col.to_i64().ok_or_else(conversion_error)?,
+            self.assert_level_validity(level)?,
+            region_size("height", height)?,
+            region_size("width", width)?,
+        )
+    }
+
+    /// Read pre-multiplied ARGB data from a region of a valid level, after applying the bounds
+    /// policy. Returns the buffer, the height and the width.
+    fn read_level_region(
+        &self,
+        row: i64,
+        col: i64,
+        level: u32,
+        height: u64,
+        width: u64,
+    ) -> Result<(Vec<u32>, u64, u64), OpenSlideError> {
+        let (row, col, height, width) = self.bounded_region(row, col, level, height, width)?;
+        let mut buffer = vec![0u32; pixel_count(width, height)?];
+        self.backend
+            .read_region(&mut buffer, col, row, level, width, height)?;
+        Ok((buffer, height, width))
+    }
+
+    /// Apply the bounds policy to a region of a valid level. Returns the (row, column) of the top
+    /// left corner in the level 0 reference frame, the height and the width of the region to read.
+    fn bounded_region(
+        &self,
+        row: i64,
+        col: i64,
+        level: u32,
+        height: u64,
+        width: u64,
+    ) -> Result<(i64, i64, u64, u64), OpenSlideError> {
+        if self.bounds_policy == BoundsPolicy::Pad {
+            return Ok((row, col, height, width));
+        }
+
+        let level_info = self.info.level(level)?;
+        let downsample = level_info.downsample;
+        let to_i64 = |value: u64| value.to_i64().ok_or_else(conversion_error);
+        let (level_width, level_height) = (to_i64(level_info.width)?, to_i64(level_info.height)?);
+        // The edges of the region in the reference frame of the level, where the backends round
+        // the top left corner down
+        let top = (row as f64 / downsample).floor() as i64;
+        let left = (col as f64 / downsample).floor() as i64;
+        let bottom = top.saturating_add(to_i64(height)?);
+        let right = left.saturating_add(to_i64(width)?);
+
+        if top >= 0 && left >= 0 && bottom <= level_height && right <= level_width {
+            return Ok((row, col, height, width));
         }
-        Ok(properties)
+        let describe = || {
+            format!(
+                "Region of {} x {} pixels at ({}, {}) in the level 0 reference frame is outside \
+                 level {} of {} x {} pixels",
+                width, height, col, row, level, level_width, level_height
+            )
+        };
+        if self.bounds_policy == BoundsPolicy::Error {
+            return Err(OpenSlideError::OutOfBounds(describe()));
+        }
+
+        let (clipped_top, clipped_left) = (top.max(0), left.max(0));
+        let (clipped_bottom, clipped_right) = (bottom.min(level_height), right.min(level_width));
+        if clipped_bottom <= clipped_top || clipped_right <= clipped_left {
+            return Err(OpenSlideError::OutOfBounds(describe()));
+        }
+        // A corner that is clipped moves to the edge of the level, which is at 0 in the level 0
+        // reference frame too
+        Ok((
+            if top < 0 { 0 } else { row },
+            if left < 0 { 0 } else { col },
+            (clipped_bottom - clipped_top) as u64,
+            (clipped_right - clipped_left) as u64,
+        ))
     }
 
+    /// Check the size of `dest`, and read a region of a valid level into it.
+    fn read_region_into_buffer(
+        &self,
+        dest: &mut [u32],
+        row: i64,
+        col: i64,
+        level: u32,
+        height: u64,
+        width: u64,
+    ) -> Result<(), OpenSlideError> {
+        let len = pixel_count(width, height)?;
+        if dest.len() < len {
+            return Err(OpenSlideError::InvalidArgument(format!(
+                "Buffer of {} pixels is too small for a region of {} x {} pixels",
+                dest.len(),
+                width,
+                height
+            )));
+        }
+        self.backend
+            .read_region(&mut dest[..len], col, row, level, width, height)
+    }
 
-    /// Check if the given level is valid
-    fn assert_level_validity<T: Integer + ToPrimitive>(&self, level: T) -> Result<(), Error> {
-        let max_num_levels = self.get_level_count()?;
-        let level = level.to_u32().ok_or(format_err!("Conversion to primitive error"))?;
-        if level >= max_num_levels {
-            return Err(format_err!(
-                "Error: Specified level {} is larger than the max slide level {}",
-                level, max_num_levels - 1,
-            ));
+    /// Check if the given level is valid, and return it as the level type of the backend.
+    fn assert_level_validity<T: Integer + ToPrimitive>(&self, level: T) -> Result<u32, OpenSlideError> {
+        let level_count = self.info.level_count();
+        let level = level.to_i64().ok_or_else(conversion_error)?;
+        if level < 0 || level >= i64::from(level_count) {
+            return Err(OpenSlideError::InvalidLevel { level, level_count });
         }
-        Ok(())
+        Ok(level as u32)
     }
 }
+
+/// Convert the height or width of a region, which must not be negative.
+fn region_size<T: ToPrimitive + Display>(name: &str, value: T) -> Result<u64, OpenSlideError> {
+    value.to_u64().ok_or_else(|| {
+        OpenSlideError::InvalidArgument(format!(
+            "Region {} must be a non-negative integer, not {}",
+            name, value
+        ))
+    })
+}
+
+/// Number of pixels in a buffer of the given dimensions.
+fn pixel_count(width: u64, height: u64) -> Result<usize, OpenSlideError> {
+    width
+        .checked_mul(height)
+        .and_then(|count| count.to_usize())
+        .ok_or_else(|| {
+            OpenSlideError::InvalidArgument(format!(
+                "Region dimensions ({}, {}) are too large",
+                width, height
+            ))
+        })
+}
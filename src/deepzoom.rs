@@ -0,0 +1,380 @@
+//! Deep Zoom tiles for viewers such as OpenSeadragon
+//!
+//! `DeepZoomGenerator` is a port of the generator in openslide-python (`openslide.deepzoom`), and
+//! computes the same Deep Zoom levels, tile counts and tile coordinates, such that it can replace
+//! a Python tile server without changing the tiles that the viewer requests. As in Python, the
+//! tiles are composited onto the background color of the slide, and scaled down with a Lanczos
+//! filter when the slide level does not have the exact resolution of the Deep Zoom level.
+//!
+//! ```no_run
+//! # fn main() -> Result<(), openslide::OpenSlideError> {
+//! use openslide::deepzoom::{DeepZoomGenerator, TileFormat};
+//! use openslide::OpenSlide;
+//! use std::path::Path;
+//!
+//! let slide = OpenSlide::new(Path::new("assets/CMU-1-Small-Region.svs"))?;
+//! let generator = DeepZoomGenerator::new(&slide, 254, 1, false)?;
+//! let descriptor = generator.dzi(TileFormat::Jpeg(75));
+//! let level = generator.level_count() - 1;
+//! let tile = generator.tile_bytes(level, 0, 0, TileFormat::Jpeg(75))?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+
+use image::jpeg::JPEGEncoder;
+use image::png::PNGEncoder;
+use image::{imageops, ColorType, FilterType, RgbImage};
+
+use convenience::{BoundsPolicy, OpenSlide};
+use error::OpenSlideError;
+use geometry::{Level, Level0, LevelN, Point, Size};
+
+/// The image format of Deep Zoom tiles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TileFormat {
+    /// JPEG with the given quality (1 - 100)
+    Jpeg(u8),
+    /// PNG
+    Png,
+}
+
+impl TileFormat {
+    /// The name of the format in the `.dzi` descriptor, which is also the file extension of the
+    /// tiles: `jpeg` or `png`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            TileFormat::Jpeg(_) => "jpeg",
+            TileFormat::Png => "png",
+        }
+    }
+
+    /// The MIME type of the tiles.
+    pub fn mime_type(&self) -> &'static str {
+        match *self {
+            TileFormat::Jpeg(_) => "image/jpeg",
+            TileFormat::Png => "image/png",
+        }
+    }
+}
+
+/// The region of the slide that a Deep Zoom tile is read from, as in `read_region`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TileCoordinates {
+    /// Top left corner in the level 0 reference frame
+    pub origin: Point<Level0>,
+    /// The slide level that the tile is read from
+    pub level: Level,
+    /// Size of the region in pixels of `level`
+    pub size: Size<LevelN>,
+}
+
+/// Generates Deep Zoom tiles and the `.dzi` descriptor of a slide.
+#[derive(Clone)]
+pub struct DeepZoomGenerator {
+    slide: OpenSlide,
+    tile_size: u64,
+    overlap: u64,
+    /// Offset of the bounds of the slide, in the level 0 reference frame
+    l0_offset: (i64, i64),
+    /// Dimensions of the (bounded) slide levels
+    l_dimensions: Vec<(u64, u64)>,
+    /// Dimensions of the Deep Zoom levels
+    z_dimensions: Vec<(u64, u64)>,
+    /// Number of tiles of the Deep Zoom levels
+    t_dimensions: Vec<(u64, u64)>,
+    /// The slide level of every Deep Zoom level
+    slide_from_dz_level: Vec<u32>,
+    /// Downsample factor of every Deep Zoom level relative to its slide level
+    l_z_downsamples: Vec<f64>,
+}
+
+impl DeepZoomGenerator {
+    /// Create a generator with tiles of `tile_size` pixels, which overlap their neighbours with
+    /// `overlap` pixels. If `limit_bounds` is set, only the part of the slide in its bounds
+    /// (`SlideInfo::bounds`) is rendered, for slide formats that report them.
+    ///
+    /// OpenSeadragon works best with a tile size of 254 and an overlap of 1, which are the
+    /// defaults in Python.
+    pub fn new(
+        slide: &OpenSlide,
+        tile_size: u64,
+        overlap: u64,
+        limit_bounds: bool,
+    ) -> Result<DeepZoomGenerator, OpenSlideError> {
+        if tile_size == 0 {
+            return Err(OpenSlideError::InvalidArgument(String::from(
+                "Deep Zoom tile size must be positive",
+            )));
+        }
+        let info = slide.info();
+        let level_dimensions = info
+            .levels()
+            .iter()
+            .map(|level| (level.width, level.height))
+            .collect::<Vec<(u64, u64)>>();
+        let (l0_width, l0_height) = info.dimensions();
+
+        let (l0_offset, l_dimensions) = match info.bounds().filter(|_| limit_bounds) {
+            Some(bounds) => {
+                let l0_offset = (bounds.origin.x, bounds.origin.y);
+                // Scale factor of the level dimensions in each axis
+                let scale_x = bounds.size.width as f64 / l0_width as f64;
+                let scale_y = bounds.size.height as f64 / l0_height as f64;
+                let l_dimensions = level_dimensions
+                    .iter()
+                    .map(|&(width, height)| {
+                        (
+                            (width as f64 * scale_x).ceil() as u64,
+                            (height as f64 * scale_y).ceil() as u64,
+                        )
+                    })
+                    .collect();
+                (l0_offset, l_dimensions)
+            }
+            None => ((0, 0), level_dimensions),
+        };
+
+        // Halve the level 0 dimensions, rounding up, until the level is a single pixel
+        let mut z_size = l_dimensions[0];
+        let mut z_dimensions = vec![z_size];
+        while z_size.0 > 1 || z_size.1 > 1 {
+            z_size = (z_size.0.div_ceil(2).max(1), z_size.1.div_ceil(2).max(1));
+            z_dimensions.push(z_size);
+        }
+        z_dimensions.reverse();
+
+        let t_dimensions = z_dimensions
+            .iter()
+            .map(|&(width, height)| (width.div_ceil(tile_size), height.div_ceil(tile_size)))
+            .collect();
+
+        let dz_levels = z_dimensions.len();
+        let mut slide_from_dz_level = Vec::with_capacity(dz_levels);
+        let mut l_z_downsamples = Vec::with_capacity(dz_levels);
+        for dz_level in 0..dz_levels {
+            let l0_z_downsample = 2f64.powi((dz_levels - dz_level - 1) as i32);
+            let slide_level = slide.get_best_level_for_downsample(l0_z_downsample)?;
+            slide_from_dz_level.push(slide_level);
+            l_z_downsamples.push(l0_z_downsample / info.level(slide_level)?.downsample);
+        }
+
+        Ok(DeepZoomGenerator {
+            // The regions are read as in the C API, without clipping
+            slide: slide.clone().with_bounds_policy(BoundsPolicy::Pad),
+            tile_size,
+            overlap,
+            l0_offset,
+            l_dimensions,
+            z_dimensions,
+            t_dimensions,
+            slide_from_dz_level,
+            l_z_downsamples,
+        })
+    }
+
+    /// Number of Deep Zoom levels, where level 0 is a single pixel, and the last level has the
+    /// resolution of level 0 of the slide.
+    pub fn level_count(&self) -> usize {
+        self.z_dimensions.len()
+    }
+
+    /// Number of (columns, rows) of tiles of every Deep Zoom level.
+    pub fn level_tiles(&self) -> &[(u64, u64)] {
+        &self.t_dimensions
+    }
+
+    /// (width, height) of every Deep Zoom level.
+    pub fn level_dimensions(&self) -> &[(u64, u64)] {
+        &self.z_dimensions
+    }
+
+    /// Total number of tiles of all Deep Zoom levels.
+    pub fn tile_count(&self) -> u64 {
+        self.t_dimensions
+            .iter()
+            .map(|&(columns, rows)| columns * rows)
+            .sum()
+    }
+
+    /// Get the region of the slide that a tile is read from.
+    ///
+    /// Returns an `InvalidArgument` error if the level or the tile address is invalid.
+    pub fn tile_coordinates(
+        &self,
+        level: usize,
+        column: u64,
+        row: u64,
+    ) -> Result<TileCoordinates, OpenSlideError> {
+        Ok(self.tile_info(level, column, row)?.0)
+    }
+
+    /// Get the (width, height) of a tile, including the overlap.
+    ///
+    /// Returns an `InvalidArgument` error if the level or the tile address is invalid.
+    pub fn tile_dimensions(
+        &self,
+        level: usize,
+        column: u64,
+        row: u64,
+    ) -> Result<(u64, u64), OpenSlideError> {
+        Ok(self.tile_info(level, column, row)?.1)
+    }
+
+    /// Render a tile, composited onto the background color of the slide.
+    ///
+    /// Returns an `InvalidArgument` error if the level or the tile address is invalid.
+    pub fn tile(&self, level: usize, column: u64, row: u64) -> Result<RgbImage, OpenSlideError> {
+        let (coordinates, (z_width, z_height)) = self.tile_info(level, column, row)?;
+        let tile = self.slide.read_region_composited(
+            coordinates.origin.y,
+            coordinates.origin.x,
+            i64::from(coordinates.level.0),
+            coordinates.size.height as i64,
+            coordinates.size.width as i64,
+            None,
+        )?;
+        match thumbnail_size(tile.dimensions(), (z_width as u32, z_height as u32)) {
+            Some((width, height)) if (width, height) != tile.dimensions() => {
+                Ok(imageops::resize(&tile, width, height, FilterType::Lanczos3))
+            }
+            _ => Ok(tile),
+        }
+    }
+
+    /// Render a tile, and encode it in the given format.
+    ///
+    /// Returns an `InvalidArgument` error if the level or the tile address is invalid.
+    pub fn tile_bytes(
+        &self,
+        level: usize,
+        column: u64,
+        row: u64,
+        format: TileFormat,
+    ) -> Result<Vec<u8>, OpenSlideError> {
+        let tile = self.tile(level, column, row)?;
+        let (width, height) = tile.dimensions();
+        encode(&tile, width, height, ColorType::RGB(8), format)
+    }
+
+    /// Get the `.dzi` XML descriptor of the slide, for tiles in the given format.
+    pub fn dzi(&self, format: TileFormat) -> String {
+        let (width, height) = self.l_dimensions[0];
+        format!(
+            "<Image TileSize=\"{}\" Overlap=\"{}\" Format=\"{}\" \
+             xmlns=\"http://schemas.microsoft.com/deepzoom/2008\">\
+             <Size Width=\"{}\" Height=\"{}\" /></Image>",
+            self.tile_size,
+            self.overlap,
+            format.name(),
+            width,
+            height
+        )
+    }
+
+    /// Get the region that a tile is read from, and the (width, height) of the tile.
+    fn tile_info(
+        &self,
+        dz_level: usize,
+        column: u64,
+        row: u64,
+    ) -> Result<(TileCoordinates, (u64, u64)), OpenSlideError> {
+        if dz_level >= self.level_count() {
+            return Err(OpenSlideError::InvalidArgument(format!(
+                "Invalid Deep Zoom level {}",
+                dz_level
+            )));
+        }
+        let (columns, rows) = self.t_dimensions[dz_level];
+        if column >= columns || row >= rows {
+            return Err(OpenSlideError::InvalidArgument(format!(
+                "Invalid address ({}, {}) of a tile in Deep Zoom level {}",
+                column, row, dz_level
+            )));
+        }
+
+        let slide_level = self.slide_from_dz_level[dz_level];
+        let l0_l_downsample = self.slide.info().level(slide_level)?.downsample;
+        let l_z_downsample = self.l_z_downsamples[dz_level];
+        let (z_width, z_height) = self.z_dimensions[dz_level];
+        let (l_width, l_height) = self.l_dimensions[slide_level as usize];
+
+        // Returns the level 0 location and the slide level size of the region, and the size of
+        // the tile, along one axis
+        let axis = |t: u64, t_lim: u64, z_lim: u64, l_lim: u64, l0_offset: i64| {
+            // Overlap at the top or left, and at the bottom or right
+            let z_tl = if t != 0 { self.overlap } else { 0 };
+            let z_br = if t != t_lim - 1 { self.overlap } else { 0 };
+            let z_size = self.tile_size.min(z_lim - self.tile_size * t) + z_tl + z_br;
+
+            // Round the location down and the size up, and add the offset of the bounds
+            let z = self.tile_size * t;
+            let l = l_z_downsample * (z as f64 - z_tl as f64);
+            let l0 = (l0_l_downsample * l + l0_offset as f64).trunc() as i64;
+            let l_size = ((l_z_downsample * z_size as f64).ceil() as i64)
+                .min(l_lim as i64 - l.ceil() as i64);
+            (l0, l_size.max(0) as u64, z_size)
+        };
+        let (l0_x, l_width, z_width) = axis(column, columns, z_width, l_width, self.l0_offset.0);
+        let (l0_y, l_height, z_height) = axis(row, rows, z_height, l_height, self.l0_offset.1);
+
+        Ok((
+            TileCoordinates {
+                origin: Point::new(l0_x, l0_y),
+                level: Level(slide_level),
+                size: Size::new(l_width, l_height),
+            },
+            (z_width, z_height),
+        ))
+    }
+}
+
+/// Encode 8 bit samples of the given color type in the given format.
+pub(crate) fn encode(
+    samples: &[u8],
+    width: u32,
+    height: u32,
+    color: ColorType,
+    format: TileFormat,
+) -> Result<Vec<u8>, OpenSlideError> {
+    let mut bytes = Vec::new();
+    match format {
+        TileFormat::Jpeg(quality) => {
+            JPEGEncoder::new_with_quality(&mut bytes, quality).encode(samples, width, height, color)
+        }
+        TileFormat::Png => PNGEncoder::new(&mut bytes).encode(samples, width, height, color),
+    }
+    .map_err(|err| OpenSlideError::Encoding(format!("Could not encode image: {}", err)))?;
+    Ok(bytes)
+}
+
+/// The size that Pillow's `Image.thumbnail` scales an image of `size` to, to fit into `bounds`
+/// while keeping its aspect ratio, or `None` if it already fits.
+fn thumbnail_size(size: (u32, u32), bounds: (u32, u32)) -> Option<(u32, u32)> {
+    let (width, height) = size;
+    let (x, y) = bounds;
+    if x >= width && y >= height {
+        return None;
+    }
+    let aspect = f64::from(width) / f64::from(height);
+    // The rounding (down or up) that keeps the aspect ratio best, preferring down on ties
+    let round_aspect = |number: f64, error: &dyn Fn(f64) -> f64| {
+        let (down, up) = (number.floor(), number.ceil());
+        let rounded = if error(up) < error(down) { up } else { down };
+        rounded.max(1.0) as u32
+    };
+    let (x, y) = (f64::from(x), f64::from(y));
+    if x / y >= aspect {
+        let width = round_aspect(y * aspect, &|n| (aspect - n / y).abs());
+        Some((width, y as u32))
+    } else {
+        let height = round_aspect(x / aspect, &|n| {
+            if n == 0.0 {
+                0.0
+            } else {
+                (aspect - x / n).abs()
+            }
+        });
+        Some((x as u32, height))
+    }
+}
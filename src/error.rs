@@ -0,0 +1,99 @@
+//! The error type of this crate
+//!
+
+use std::{error, ffi, fmt, str};
+
+/// Errors that can occur when working with a slide.
+///
+/// All public functions in this crate return this error, such that callers can tell the different
+/// failures apart without inspecting the error message.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OpenSlideError {
+    /// The path does not exist, or it can not be passed on to OpenSlide.
+    InvalidPath(String),
+    /// The file exists, but its format is not recognized by OpenSlide.
+    UnsupportedFormat(String),
+    /// The requested level is not in the slide.
+    InvalidLevel {
+        /// The requested level, which may be negative
+        level: i64,
+        /// The number of levels in the slide
+        level_count: u32,
+    },
+    /// The requested region is (partially) outside the slide.
+    OutOfBounds(String),
+    /// OpenSlide has entered its error state. Contains the error message from OpenSlide.
+    Library(String),
+    /// The OpenSlide library could not be loaded at runtime, or it lacks a required function.
+    LibraryNotLoaded(String),
+    /// A slide property could not be parsed into its expected type.
+    PropertyParse {
+        /// The property name, e.g. `openslide.mpp-x`
+        name: String,
+        /// The property value that could not be parsed
+        value: String,
+    },
+    /// An argument has an invalid value, e.g. a negative downsample factor, or a value that does
+    /// not fit into the corresponding C type.
+    InvalidArgument(String),
+    /// The operation is not supported by the slide backend.
+    Unsupported(String),
+    /// The slide lacks the microns per pixel or the objective power that a conversion between
+    /// pixels and physical units needs.
+    MissingCalibration(String),
+    /// A region could not be encoded into an image format, e.g. JPEG or PNG.
+    Encoding(String),
+}
+
+impl fmt::Display for OpenSlideError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OpenSlideError::InvalidPath(ref msg) => write!(f, "Invalid path: {}", msg),
+            OpenSlideError::UnsupportedFormat(ref msg) => write!(f, "Unsupported format: {}", msg),
+            OpenSlideError::InvalidLevel { level, .. } if level < 0 => {
+                write!(f, "Invalid level: Specified level {} is negative", level)
+            }
+            OpenSlideError::InvalidLevel { level, level_count } => write!(
+                f,
+                "Invalid level: Specified level {} is larger than the max slide level {}",
+                level,
+                level_count.saturating_sub(1)
+            ),
+            OpenSlideError::OutOfBounds(ref msg) => write!(f, "Out of bounds: {}", msg),
+            OpenSlideError::Library(ref msg) => write!(f, "OpenSlide error: {}", msg),
+            OpenSlideError::LibraryNotLoaded(ref msg) => {
+                write!(f, "OpenSlide library not loaded: {}", msg)
+            }
+            OpenSlideError::PropertyParse { ref name, ref value } => write!(
+                f,
+                "Could not parse property with name {} and value {}",
+                name, value
+            ),
+            OpenSlideError::InvalidArgument(ref msg) => write!(f, "Invalid argument: {}", msg),
+            OpenSlideError::Unsupported(ref msg) => write!(f, "Unsupported operation: {}", msg),
+            OpenSlideError::MissingCalibration(ref msg) => {
+                write!(f, "Missing calibration: {}", msg)
+            }
+            OpenSlideError::Encoding(ref msg) => write!(f, "Encoding error: {}", msg),
+        }
+    }
+}
+
+impl error::Error for OpenSlideError {}
+
+impl From<ffi::NulError> for OpenSlideError {
+    fn from(err: ffi::NulError) -> Self {
+        OpenSlideError::InvalidArgument(format!("String contains a nul byte: {}", err))
+    }
+}
+
+impl From<str::Utf8Error> for OpenSlideError {
+    fn from(err: str::Utf8Error) -> Self {
+        OpenSlideError::Library(format!("OpenSlide returned a string that is not UTF-8: {}", err))
+    }
+}
+
+/// The error returned when a number does not fit into the requested primitive type.
+pub(crate) fn conversion_error() -> OpenSlideError {
+    OpenSlideError::InvalidArgument(String::from("Conversion to primitive error"))
+}
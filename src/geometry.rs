@@ -0,0 +1,299 @@
+//! Levels, points, sizes and rectangles with explicit coordinate spaces
+//!
+//! OpenSlide mixes two coordinate spaces: the top left corner of a region is given in the level 0
+//! reference frame, while its size is given in pixels of the level that is read. The types in this
+//! module are tagged with their space (`Level0` or `LevelN`), such that the compiler catches a
+//! size in the wrong space, and they always use x/y and width/height, as the C API does, in stead
+//! of row/column.
+//!
+//! ```no_run
+//! # fn main() -> Result<(), openslide::OpenSlideError> {
+//! use openslide::geometry::{Level, Level0, Rect};
+//! use openslide::OpenSlide;
+//! use std::path::Path;
+//!
+//! let slide = OpenSlide::new(Path::new("assets/CMU-1-Small-Region.svs"))?;
+//! // 512 x 256 pixels at level 0, read from level 1
+//! let rect: Rect<Level0> = Rect::new(1000, 500, 512, 256);
+//! let image = slide.read_rect(Level(1), rect)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+
+use std::fmt;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+use convenience::OpenSlide;
+use error::{conversion_error, OpenSlideError};
+
+/// A level of a whole slide image, where level 0 is the largest.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Level(pub u32);
+
+impl From<u32> for Level {
+    fn from(level: u32) -> Level {
+        Level(level)
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "level {}", self.0)
+    }
+}
+
+/// A coordinate space of points, sizes and rectangles.
+pub trait Space: Copy + Debug + Default + PartialEq + Eq + Hash {
+    /// Get the top left corner in the level 0 reference frame, and the size in pixels of `level`,
+    /// of a rectangle in this space. This is how OpenSlide reads regions.
+    fn region(
+        slide: &OpenSlide,
+        level: Level,
+        rect: Rect<Self>,
+    ) -> Result<(Point<Level0>, Size<LevelN>), OpenSlideError>;
+}
+
+/// The reference frame of level 0.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Level0;
+
+/// The reference frame of some level, which is given separately.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct LevelN;
+
+impl Space for Level0 {
+    fn region(
+        slide: &OpenSlide,
+        level: Level,
+        rect: Rect<Level0>,
+    ) -> Result<(Point<Level0>, Size<LevelN>), OpenSlideError> {
+        Ok((rect.origin, rect.to_level(slide, level)?.size))
+    }
+}
+
+impl Space for LevelN {
+    fn region(
+        slide: &OpenSlide,
+        level: Level,
+        rect: Rect<LevelN>,
+    ) -> Result<(Point<Level0>, Size<LevelN>), OpenSlideError> {
+        Ok((rect.origin.to_level0(slide, level)?, rect.size))
+    }
+}
+
+/// A pixel position. The coordinates may be negative, or outside the level.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Point<S: Space> {
+    /// Column, increasing to the right
+    pub x: i64,
+    /// Row, increasing downwards
+    pub y: i64,
+    space: PhantomData<S>,
+}
+
+impl<S: Space> Point<S> {
+    /// Create a point from its column and row.
+    pub fn new(x: i64, y: i64) -> Point<S> {
+        Point {
+            x,
+            y,
+            space: PhantomData,
+        }
+    }
+}
+
+impl Point<Level0> {
+    /// Convert this point to the reference frame of the given level, rounding down to the pixel
+    /// that contains it, as OpenSlide does.
+    pub fn to_level(
+        self,
+        slide: &OpenSlide,
+        level: Level,
+    ) -> Result<Point<LevelN>, OpenSlideError> {
+        let downsample = slide.get_level_downsample(level.0)?;
+        Ok(Point::new(
+            to_i64((self.x as f64 / downsample).floor())?,
+            to_i64((self.y as f64 / downsample).floor())?,
+        ))
+    }
+}
+
+impl Point<LevelN> {
+    /// Convert this point from the reference frame of the given level to the level 0 reference
+    /// frame. The result is rounded up, such that converting it back gives this point again.
+    pub fn to_level0(
+        self,
+        slide: &OpenSlide,
+        level: Level,
+    ) -> Result<Point<Level0>, OpenSlideError> {
+        let downsample = slide.get_level_downsample(level.0)?;
+        Ok(Point::new(
+            to_i64((self.x as f64 * downsample).ceil())?,
+            to_i64((self.y as f64 * downsample).ceil())?,
+        ))
+    }
+}
+
+/// A point that a region is placed at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Anchor {
+    /// The top left corner of the region is at the point.
+    TopLeft(Point<Level0>),
+    /// The center of the region is at the point.
+    Center(Point<Level0>),
+}
+
+/// A size in pixels.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Size<S: Space> {
+    pub width: u64,
+    pub height: u64,
+    space: PhantomData<S>,
+}
+
+impl<S: Space> Size<S> {
+    /// Create a size from its width and height.
+    pub fn new(width: u64, height: u64) -> Size<S> {
+        Size {
+            width,
+            height,
+            space: PhantomData,
+        }
+    }
+
+    /// Number of pixels, as a `u128` such that it does not overflow for any width and height
+    pub fn area(&self) -> u128 {
+        u128::from(self.width) * u128::from(self.height)
+    }
+}
+
+impl Size<Level0> {
+    /// Convert this size to the reference frame of the given level. The result is rounded up, such
+    /// that it covers at least the same area.
+    pub fn to_level(self, slide: &OpenSlide, level: Level) -> Result<Size<LevelN>, OpenSlideError> {
+        let downsample = slide.get_level_downsample(level.0)?;
+        Ok(Size::new(
+            to_u64((self.width as f64 / downsample).ceil())?,
+            to_u64((self.height as f64 / downsample).ceil())?,
+        ))
+    }
+}
+
+impl Size<LevelN> {
+    /// Convert this size from the reference frame of the given level to the level 0 reference
+    /// frame. The result is rounded up, such that it covers at least the same area.
+    pub fn to_level0(
+        self,
+        slide: &OpenSlide,
+        level: Level,
+    ) -> Result<Size<Level0>, OpenSlideError> {
+        let downsample = slide.get_level_downsample(level.0)?;
+        Ok(Size::new(
+            to_u64((self.width as f64 * downsample).ceil())?,
+            to_u64((self.height as f64 * downsample).ceil())?,
+        ))
+    }
+}
+
+/// A rectangle, given by its top left corner and its size.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Rect<S: Space> {
+    pub origin: Point<S>,
+    pub size: Size<S>,
+}
+
+impl<S: Space> Rect<S> {
+    /// Create a rectangle from the column and row of its top left corner, its width and its
+    /// height.
+    pub fn new(x: i64, y: i64, width: u64, height: u64) -> Rect<S> {
+        Rect {
+            origin: Point::new(x, y),
+            size: Size::new(width, height),
+        }
+    }
+
+    /// Create a rectangle from its top left corner and its size.
+    pub fn from_parts(origin: Point<S>, size: Size<S>) -> Rect<S> {
+        Rect { origin, size }
+    }
+
+    /// The x coordinate just right of the rectangle, as an `i128` such that it does not overflow
+    /// for any origin and width.
+    pub fn right(&self) -> i128 {
+        i128::from(self.origin.x) + i128::from(self.size.width)
+    }
+
+    /// The y coordinate just below the rectangle, as an `i128` such that it does not overflow for
+    /// any origin and height.
+    pub fn bottom(&self) -> i128 {
+        i128::from(self.origin.y) + i128::from(self.size.height)
+    }
+
+    /// Get the top left corner in the level 0 reference frame, and the size in pixels of `level`,
+    /// which are the arguments of `OpenSlide::read_region`.
+    pub fn region(
+        self,
+        slide: &OpenSlide,
+        level: Level,
+    ) -> Result<(Point<Level0>, Size<LevelN>), OpenSlideError> {
+        S::region(slide, level, self)
+    }
+}
+
+impl Rect<Level0> {
+    /// Convert this rectangle to the reference frame of the given level. The result covers at
+    /// least the same area: the top left corner is rounded down, and the bottom right corner up.
+    pub fn to_level(self, slide: &OpenSlide, level: Level) -> Result<Rect<LevelN>, OpenSlideError> {
+        let downsample = slide.get_level_downsample(level.0)?;
+        let origin = self.origin.to_level(slide, level)?;
+        let right = to_i64((self.right() as f64 / downsample).ceil())?;
+        let bottom = to_i64((self.bottom() as f64 / downsample).ceil())?;
+        Ok(Rect::new(
+            origin.x,
+            origin.y,
+            (right - origin.x) as u64,
+            (bottom - origin.y) as u64,
+        ))
+    }
+}
+
+impl Rect<LevelN> {
+    /// Convert this rectangle from the reference frame of the given level to the level 0
+    /// reference frame. Both corners are rounded up, such that the top left corner converts back
+    /// to the same point.
+    pub fn to_level0(
+        self,
+        slide: &OpenSlide,
+        level: Level,
+    ) -> Result<Rect<Level0>, OpenSlideError> {
+        let downsample = slide.get_level_downsample(level.0)?;
+        let origin = self.origin.to_level0(slide, level)?;
+        let right = to_i64((self.right() as f64 * downsample).ceil())?;
+        let bottom = to_i64((self.bottom() as f64 * downsample).ceil())?;
+        Ok(Rect::new(
+            origin.x,
+            origin.y,
+            (right - origin.x) as u64,
+            (bottom - origin.y) as u64,
+        ))
+    }
+}
+
+fn to_i64(value: f64) -> Result<i64, OpenSlideError> {
+    if value.is_finite() && value >= i64::MIN as f64 && value <= i64::MAX as f64 {
+        Ok(value as i64)
+    } else {
+        Err(conversion_error())
+    }
+}
+
+fn to_u64(value: f64) -> Result<u64, OpenSlideError> {
+    if value.is_finite() && value >= 0.0 && value <= u64::MAX as f64 {
+        Ok(value as u64)
+    } else {
+        Err(conversion_error())
+    }
+}
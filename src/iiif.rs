@@ -0,0 +1,576 @@
+//! IIIF Image API 3.0 requests and image information
+//!
+//! An `ImageService` answers requests of the [IIIF Image API](https://iiif.io/api/image/3.0/)
+//! for a slide, where the full image is level 0. A request
+//! `{region}/{size}/{rotation}/{quality}.{format}` is parsed into an `ImageRequest`, and rendered
+//! from the best level for its scale, composited onto the background color of the slide. The
+//! `info.json` document advertises tiles at scale factors from the level downsamples, such that
+//! viewers request regions that are read straight from a level.
+//!
+//! ```no_run
+//! # fn main() -> Result<(), openslide::OpenSlideError> {
+//! use openslide::iiif::{ImageRequest, ImageService};
+//! use openslide::OpenSlide;
+//! use std::path::Path;
+//!
+//! let slide = OpenSlide::new(Path::new("assets/CMU-1-Small-Region.svs"))?;
+//! let service = ImageService::new(&slide);
+//! let info = service.info_json("https://example.org/iiif/CMU-1-Small-Region");
+//! let request: ImageRequest = "pct:25,25,50,50/!512,512/90/gray.png".parse()?;
+//! let image = service.render(&request)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+
+use std::str::FromStr;
+
+use image::{imageops, ColorType, ImageBuffer, Luma, Pixel, RgbImage};
+
+use convenience::OpenSlide;
+use deepzoom::{self, TileFormat};
+use error::OpenSlideError;
+use geometry::{Level0, LevelN, Rect, Size};
+use resample::Filter;
+use utils::{self, json_string};
+
+/// The region of the full image that is requested.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Region {
+    /// `full`: the whole image
+    Full,
+    /// `square`: the largest square in the center of the image
+    Square,
+    /// `x,y,w,h`: a region in pixels of the full image
+    Pixels {
+        x: u64,
+        y: u64,
+        width: u64,
+        height: u64,
+    },
+    /// `pct:x,y,w,h`: a region in percent of the width and height of the full image
+    Percent {
+        x: f64,
+        y: f64,
+        width: f64,
+        height: f64,
+    },
+}
+
+/// The size of the returned image, before rotation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageSize {
+    /// `max`: the region, scaled down to the maximum size of the service if it is larger
+    Max,
+    /// `w,`: the given width, and the height that keeps the aspect ratio
+    Width(u64),
+    /// `,h`: the given height, and the width that keeps the aspect ratio
+    Height(u64),
+    /// `pct:n`: the given percentage of the size of the region
+    Percent(f64),
+    /// `w,h`: exactly the given width and height, which may distort the image
+    Exact(u64, u64),
+    /// `!w,h`: the largest size within the given width and height that keeps the aspect ratio
+    Confined(u64, u64),
+}
+
+/// The requested size, and whether it may be larger than the region (a size prefixed with `^`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SizeRequest {
+    /// The size
+    pub size: ImageSize,
+    /// Whether the size may be larger than the region
+    pub upscale: bool,
+}
+
+/// Clockwise rotation in multiples of 90 degrees, after mirroring if `mirror` is set
+/// (`!` prefix).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Rotation {
+    /// 0, 90, 180 or 270
+    pub degrees: u32,
+    /// Whether the image is mirrored horizontally before it is rotated
+    pub mirror: bool,
+}
+
+/// The colors of the returned image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Quality {
+    /// `default`, which is `color` for slides
+    Default,
+    /// `color`
+    Color,
+    /// `gray`: grayscale
+    Gray,
+    /// `bitonal`: black and white
+    Bitonal,
+}
+
+/// The image format of the returned image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Format {
+    /// `jpg`
+    Jpg,
+    /// `png`
+    Png,
+}
+
+impl Format {
+    /// The MIME type of the format.
+    pub fn mime_type(&self) -> &'static str {
+        match *self {
+            Format::Jpg => "image/jpeg",
+            Format::Png => "image/png",
+        }
+    }
+}
+
+/// A parsed image request: `{region}/{size}/{rotation}/{quality}.{format}`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImageRequest {
+    pub region: Region,
+    pub size: SizeRequest,
+    pub rotation: Rotation,
+    pub quality: Quality,
+    pub format: Format,
+}
+
+fn invalid(kind: &str, value: &str) -> OpenSlideError {
+    OpenSlideError::InvalidArgument(format!("Invalid IIIF {}: {}", kind, value))
+}
+
+/// Parse comma separated numbers, which must have `count` elements.
+fn parse_list<T: FromStr>(value: &str, count: usize) -> Option<Vec<T>> {
+    let numbers = value
+        .split(',')
+        .map(|number| number.parse().ok())
+        .collect::<Option<Vec<T>>>()?;
+    if numbers.len() == count {
+        Some(numbers)
+    } else {
+        None
+    }
+}
+
+impl FromStr for Region {
+    type Err = OpenSlideError;
+
+    fn from_str(value: &str) -> Result<Region, OpenSlideError> {
+        let region = match value {
+            "full" => Some(Region::Full),
+            "square" => Some(Region::Square),
+            _ => match value.strip_prefix("pct:") {
+                Some(percent) => parse_list::<f64>(percent, 4)
+                    .filter(|numbers| {
+                        numbers
+                            .iter()
+                            .all(|number| number.is_finite() && *number >= 0.0)
+                    })
+                    .map(|numbers| Region::Percent {
+                        x: numbers[0],
+                        y: numbers[1],
+                        width: numbers[2],
+                        height: numbers[3],
+                    }),
+                None => parse_list::<u64>(value, 4).map(|numbers| Region::Pixels {
+                    x: numbers[0],
+                    y: numbers[1],
+                    width: numbers[2],
+                    height: numbers[3],
+                }),
+            },
+        };
+        region.ok_or_else(|| invalid("region", value))
+    }
+}
+
+impl FromStr for SizeRequest {
+    type Err = OpenSlideError;
+
+    fn from_str(value: &str) -> Result<SizeRequest, OpenSlideError> {
+        let (upscale, size) = match value.strip_prefix('^') {
+            Some(size) => (true, size),
+            None => (false, value),
+        };
+        let confined = size.strip_prefix('!');
+        let size = if size == "max" {
+            Some(ImageSize::Max)
+        } else if let Some(percent) = size.strip_prefix("pct:") {
+            percent
+                .parse::<f64>()
+                .ok()
+                .filter(|percent| percent.is_finite() && *percent >= 0.0)
+                .map(ImageSize::Percent)
+        } else if let Some(width) = size.strip_suffix(',') {
+            width.parse().ok().map(ImageSize::Width)
+        } else if let Some(height) = size.strip_prefix(',') {
+            height.parse().ok().map(ImageSize::Height)
+        } else if let Some(confined) = confined {
+            parse_list(confined, 2).map(|numbers| ImageSize::Confined(numbers[0], numbers[1]))
+        } else {
+            parse_list(size, 2).map(|numbers| ImageSize::Exact(numbers[0], numbers[1]))
+        };
+        size.map(|size| SizeRequest { size, upscale })
+            .ok_or_else(|| invalid("size", value))
+    }
+}
+
+impl FromStr for Rotation {
+    type Err = OpenSlideError;
+
+    fn from_str(value: &str) -> Result<Rotation, OpenSlideError> {
+        let (mirror, degrees) = match value.strip_prefix('!') {
+            Some(degrees) => (true, degrees),
+            None => (false, value),
+        };
+        // Only multiples of 90 degrees are supported
+        match degrees.parse::<f64>() {
+            Ok(degrees) if (0.0..=360.0).contains(&degrees) && degrees % 90.0 == 0.0 => {
+                Ok(Rotation {
+                    degrees: degrees as u32 % 360,
+                    mirror,
+                })
+            }
+            _ => Err(invalid("rotation", value)),
+        }
+    }
+}
+
+impl FromStr for Quality {
+    type Err = OpenSlideError;
+
+    fn from_str(value: &str) -> Result<Quality, OpenSlideError> {
+        match value {
+            "default" => Ok(Quality::Default),
+            "color" => Ok(Quality::Color),
+            "gray" => Ok(Quality::Gray),
+            "bitonal" => Ok(Quality::Bitonal),
+            _ => Err(invalid("quality", value)),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = OpenSlideError;
+
+    fn from_str(value: &str) -> Result<Format, OpenSlideError> {
+        match value {
+            "jpg" => Ok(Format::Jpg),
+            "png" => Ok(Format::Png),
+            _ => Err(invalid("format", value)),
+        }
+    }
+}
+
+impl FromStr for ImageRequest {
+    type Err = OpenSlideError;
+
+    /// Parse `{region}/{size}/{rotation}/{quality}.{format}`.
+    fn from_str(value: &str) -> Result<ImageRequest, OpenSlideError> {
+        let parts = value.split('/').collect::<Vec<&str>>();
+        if parts.len() != 4 {
+            return Err(invalid("image request", value));
+        }
+        let (quality, format) = match parts[3].rfind('.') {
+            Some(index) => (&parts[3][..index], &parts[3][index + 1..]),
+            None => return Err(invalid("image request", value)),
+        };
+        Ok(ImageRequest {
+            region: parts[0].parse()?,
+            size: parts[1].parse()?,
+            rotation: parts[2].parse()?,
+            quality: quality.parse()?,
+            format: format.parse()?,
+        })
+    }
+}
+
+/// Renders IIIF image requests for a slide.
+#[derive(Clone)]
+pub struct ImageService {
+    slide: OpenSlide,
+    tile_size: (u64, u64),
+    max_size: (u64, u64),
+    jpeg_quality: u8,
+    filter: Filter,
+}
+
+impl ImageService {
+    /// Create a service for the slide. The tiles in `info.json` have the native tile size of
+    /// level 0 if the slide reports it, or else 512 x 512 pixels. Images are at most
+    /// 4096 x 4096 pixels, JPEG images have quality 75, and regions are resampled with
+    /// `Filter::Lanczos3`.
+    pub fn new(slide: &OpenSlide) -> ImageService {
+        let tile_size = slide
+            .info()
+            .levels()
+            .first()
+            .and_then(|level| match (level.tile_width, level.tile_height) {
+                (Some(width), Some(height)) if width > 0 && height > 0 => Some((width, height)),
+                _ => None,
+            })
+            .unwrap_or((512, 512));
+        ImageService {
+            slide: slide.clone(),
+            tile_size,
+            max_size: (4096, 4096),
+            jpeg_quality: 75,
+            filter: Filter::Lanczos3,
+        }
+    }
+
+    /// Set the size (width, height) of the tiles in `info.json`.
+    pub fn with_tile_size(mut self, tile_width: u64, tile_height: u64) -> ImageService {
+        self.tile_size = (tile_width.max(1), tile_height.max(1));
+        self
+    }
+
+    /// Set the maximum size (width, height) of the returned images.
+    pub fn with_max_size(mut self, max_width: u64, max_height: u64) -> ImageService {
+        self.max_size = (max_width.max(1), max_height.max(1));
+        self
+    }
+
+    /// Set the quality (1 - 100) of JPEG images.
+    pub fn with_jpeg_quality(mut self, jpeg_quality: u8) -> ImageService {
+        self.jpeg_quality = jpeg_quality;
+        self
+    }
+
+    /// Set the filter that regions are resampled with.
+    pub fn with_filter(mut self, filter: Filter) -> ImageService {
+        self.filter = filter;
+        self
+    }
+
+    /// The scale factors of the tiles: the level downsamples, rounded to integers.
+    pub fn scale_factors(&self) -> Vec<u64> {
+        let mut scale_factors = self
+            .slide
+            .info()
+            .levels()
+            .iter()
+            .map(|level| (level.downsample.round() as u64).max(1))
+            .collect::<Vec<u64>>();
+        scale_factors.dedup();
+        scale_factors
+    }
+
+    /// The `info.json` document of the image, where `id` is the URL of the service, i.e. the
+    /// URL of the requests without `/{region}/{size}/{rotation}/{quality}.{format}`.
+    pub fn info_json(&self, id: &str) -> String {
+        let (width, height) = self.slide.info().dimensions();
+        let scale_factors = self.scale_factors();
+        // The full image at every scale factor, from small to large
+        let sizes = scale_factors
+            .iter()
+            .rev()
+            .map(|&scale_factor| (width.div_ceil(scale_factor), height.div_ceil(scale_factor)))
+            .filter(|&(width, height)| width <= self.max_size.0 && height <= self.max_size.1)
+            .map(|(width, height)| format!("{{\"width\":{},\"height\":{}}}", width, height))
+            .collect::<Vec<String>>();
+        let scale_factors = scale_factors
+            .iter()
+            .map(|scale_factor| scale_factor.to_string())
+            .collect::<Vec<String>>();
+        format!(
+            "{{\"@context\":\"http://iiif.io/api/image/3/context.json\",\"id\":{},\
+             \"type\":\"ImageService3\",\"protocol\":\"http://iiif.io/api/image\",\
+             \"profile\":\"level2\",\"width\":{},\"height\":{},\"maxWidth\":{},\"maxHeight\":{},\
+             \"tiles\":[{{\"width\":{},\"height\":{},\"scaleFactors\":[{}]}}],\"sizes\":[{}],\
+             \"extraQualities\":[\"color\",\"gray\",\"bitonal\"],\
+             \"extraFeatures\":[\"mirroring\",\"sizeUpscaling\"]}}",
+            json_string(id),
+            width,
+            height,
+            self.max_size.0,
+            self.max_size.1,
+            self.tile_size.0,
+            self.tile_size.1,
+            scale_factors.join(","),
+            sizes.join(",")
+        )
+    }
+
+    /// Get the region in the level 0 reference frame, and the size of the image before
+    /// rotation.
+    ///
+    /// Returns an `InvalidArgument` error if the region is outside the image, or if the size is
+    /// empty, larger than the maximum size, or larger than the region without upscaling.
+    pub fn resolve(
+        &self,
+        request: &ImageRequest,
+    ) -> Result<(Rect<Level0>, Size<LevelN>), OpenSlideError> {
+        let (width, height) = self.slide.info().dimensions();
+        let (x, y, region_width, region_height) = match request.region {
+            Region::Full => (0, 0, width, height),
+            Region::Square if width > height => ((width - height) / 2, 0, height, height),
+            Region::Square => (0, (height - width) / 2, width, width),
+            Region::Pixels {
+                x,
+                y,
+                width,
+                height,
+            } => (x, y, width, height),
+            Region::Percent {
+                x,
+                y,
+                width: percent_width,
+                height: percent_height,
+            } => {
+                let scale =
+                    |percent: f64, length: u64| (percent / 100.0 * length as f64).round() as u64;
+                (
+                    scale(x, width),
+                    scale(y, height),
+                    scale(percent_width, width),
+                    scale(percent_height, height),
+                )
+            }
+        };
+        if x >= width || y >= height || region_width == 0 || region_height == 0 {
+            return Err(OpenSlideError::InvalidArgument(format!(
+                "The region {:?} is outside the image",
+                request.region
+            )));
+        }
+        // Crop the region to the image
+        let region_width = region_width.min(width - x);
+        let region_height = region_height.min(height - y);
+
+        let (rw, rh) = (region_width as f64, region_height as f64);
+        let upscale_limit = if request.size.upscale {
+            f64::INFINITY
+        } else {
+            1.0
+        };
+        let scaled = |scale: f64| ((rw * scale).round() as u64, (rh * scale).round() as u64);
+        let (max_width, max_height) = self.max_size;
+        let size = match request.size.size {
+            ImageSize::Max => scaled(
+                (max_width as f64 / rw)
+                    .min(max_height as f64 / rh)
+                    .min(upscale_limit),
+            ),
+            ImageSize::Width(width) => (width, (rh * width as f64 / rw).round() as u64),
+            ImageSize::Height(height) => ((rw * height as f64 / rh).round() as u64, height),
+            ImageSize::Percent(percent) => scaled(percent / 100.0),
+            ImageSize::Exact(width, height) => (width, height),
+            ImageSize::Confined(width, height) => {
+                let (width, height) = (width as f64, height as f64);
+                let (width, height) = scaled((width / rw).min(height / rh).min(upscale_limit));
+                (width.max(1), height.max(1))
+            }
+        };
+
+        let (output_width, output_height) = size;
+        if output_width == 0 || output_height == 0 {
+            return Err(OpenSlideError::InvalidArgument(format!(
+                "The size {:?} of the region {:?} is empty",
+                request.size, request.region
+            )));
+        }
+        if output_width > max_width || output_height > max_height {
+            return Err(OpenSlideError::InvalidArgument(format!(
+                "The size {} x {} is larger than the maximum size {} x {}",
+                output_width, output_height, max_width, max_height
+            )));
+        }
+        if !request.size.upscale && (output_width > region_width || output_height > region_height) {
+            return Err(OpenSlideError::InvalidArgument(format!(
+                "The size {} x {} is larger than the region, which needs a size with ^",
+                output_width, output_height
+            )));
+        }
+        Ok((
+            Rect::new(x as i64, y as i64, region_width, region_height),
+            Size::new(output_width, output_height),
+        ))
+    }
+
+    /// Render an image request into the bytes of an image in the requested format.
+    ///
+    /// Returns an `InvalidArgument` error if the request does not fit the image (see
+    /// `resolve`), or an `Encoding` error if the region can not be made into an image of the
+    /// requested format.
+    pub fn render(&self, request: &ImageRequest) -> Result<Vec<u8>, OpenSlideError> {
+        let (region, size) = self.resolve(request)?;
+        let downsample = (
+            region.size.width as f64 / size.width as f64,
+            region.size.height as f64 / size.height as f64,
+        );
+        let buffer = self.slide.read_resampled(
+            region.origin.x as f64,
+            region.origin.y as f64,
+            downsample,
+            size,
+            self.filter,
+        )?;
+        let background = self
+            .slide
+            .properties
+            .background_color()
+            .unwrap_or([255, 255, 255]);
+        let region = utils::decode_buffer_as(
+            &buffer,
+            size.height,
+            size.width,
+            utils::WordRepresentation::BigEndian,
+            utils::PixelFormat::Composited(background),
+        )?;
+        let image = match region.samples {
+            utils::Samples::U8(samples) => RgbImage::from_raw(region.width, region.height, samples),
+            utils::Samples::F32(_) => None,
+        };
+        let image = image.ok_or_else(|| {
+            OpenSlideError::Encoding(String::from(
+                "The region could not be composited into an image",
+            ))
+        })?;
+
+        let format = match request.format {
+            Format::Jpg => TileFormat::Jpeg(self.jpeg_quality),
+            Format::Png => TileFormat::Png,
+        };
+        match request.quality {
+            Quality::Default | Quality::Color => {
+                let image = transform(image, request.rotation);
+                let (width, height) = image.dimensions();
+                deepzoom::encode(&image, width, height, ColorType::RGB(8), format)
+            }
+            Quality::Gray | Quality::Bitonal => {
+                let mut image = imageops::grayscale(&image);
+                if request.quality == Quality::Bitonal {
+                    for pixel in image.pixels_mut() {
+                        *pixel = Luma([if pixel.data[0] < 128 { 0 } else { 255 }]);
+                    }
+                }
+                let image = transform(image, request.rotation);
+                let (width, height) = image.dimensions();
+                deepzoom::encode(&image, width, height, ColorType::Gray(8), format)
+            }
+        }
+    }
+}
+
+/// Mirror and rotate an image.
+fn transform<P>(
+    image: ImageBuffer<P, Vec<P::Subpixel>>,
+    rotation: Rotation,
+) -> ImageBuffer<P, Vec<P::Subpixel>>
+where
+    P: Pixel + 'static,
+    P::Subpixel: 'static,
+{
+    let image = if rotation.mirror {
+        imageops::flip_horizontal(&image)
+    } else {
+        image
+    };
+    match rotation.degrees {
+        90 => imageops::rotate90(&image),
+        180 => imageops::rotate180(&image),
+        270 => imageops::rotate270(&image),
+        _ => image,
+    }
+}
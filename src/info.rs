@@ -0,0 +1,156 @@
+//! Information about the levels and geometry of a slide, which is read once
+//!
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use backend::SlideBackend;
+use error::OpenSlideError;
+use geometry::{Level0, Rect};
+use properties;
+
+/// Dimensions, downsample and tile size of one level.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LevelInfo {
+    /// Width in pixels
+    pub width: u64,
+    /// Height in pixels
+    pub height: u64,
+    /// Downsampling factor relative to level 0
+    pub downsample: f64,
+    /// Tile width in pixels, if the slide format reports it
+    pub tile_width: Option<u64>,
+    /// Tile height in pixels, if the slide format reports it
+    pub tile_height: Option<u64>,
+}
+
+/// An immutable snapshot of the levels and geometry of a slide.
+///
+/// This is read from the backend when the slide is opened, such that validating levels and
+/// converting coordinates does not call into the backend (and through FFI, for the OpenSlide C
+/// library) again. Get it with `OpenSlide::info()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SlideInfo {
+    levels: Vec<LevelInfo>,
+    mpp_x: Option<f64>,
+    mpp_y: Option<f64>,
+    objective_power: Option<f64>,
+    bounds: Option<Rect<Level0>>,
+}
+
+impl SlideInfo {
+    /// Read the information from the backend, and from its property map.
+    ///
+    /// Returns an error if the backend fails, or if a property has a value that can not be parsed.
+    pub fn new(
+        backend: &dyn SlideBackend,
+        property_map: &HashMap<String, String>,
+    ) -> Result<SlideInfo, OpenSlideError> {
+        let mut levels = Vec::new();
+        for level in 0..backend.level_count()? {
+            let (width, height) = backend.level_dimensions(level)?;
+            let tile_size = |dimension| {
+                let name = format!("openslide.level[{}].tile-{}", level, dimension);
+                parse_property(property_map, &name)
+            };
+            levels.push(LevelInfo {
+                width,
+                height,
+                downsample: backend.level_downsample(level)?,
+                tile_width: tile_size("width")?,
+                tile_height: tile_size("height")?,
+            });
+        }
+
+        let bounds = match (
+            parse_property(property_map, "openslide.bounds-x")?,
+            parse_property(property_map, "openslide.bounds-y")?,
+            parse_property(property_map, "openslide.bounds-width")?,
+            parse_property(property_map, "openslide.bounds-height")?,
+        ) {
+            (None, None, None, None) => None,
+            // As in openslide-python, missing bounds default to the whole of level 0
+            (x, y, width, height) => {
+                let (l0_width, l0_height) = levels
+                    .first()
+                    .map_or((0, 0), |level| (level.width, level.height));
+                Some(Rect::new(
+                    x.unwrap_or(0),
+                    y.unwrap_or(0),
+                    width.unwrap_or(l0_width),
+                    height.unwrap_or(l0_height),
+                ))
+            }
+        };
+
+        Ok(SlideInfo {
+            levels,
+            mpp_x: parse_property(property_map, "openslide.mpp-x")?,
+            mpp_y: parse_property(property_map, "openslide.mpp-y")?,
+            objective_power: parse_property(property_map, "openslide.objective-power")?,
+            bounds,
+        })
+    }
+
+    /// Number of levels
+    pub fn level_count(&self) -> u32 {
+        self.levels.len() as u32
+    }
+
+    /// Information about all levels, where the position corresponds to the level.
+    pub fn levels(&self) -> &[LevelInfo] {
+        &self.levels
+    }
+
+    /// Information about the given level, or an `InvalidLevel` error if the slide does not have
+    /// the level.
+    pub fn level(&self, level: u32) -> Result<&LevelInfo, OpenSlideError> {
+        self.levels
+            .get(level as usize)
+            .ok_or(OpenSlideError::InvalidLevel {
+                level: i64::from(level),
+                level_count: self.level_count(),
+            })
+    }
+
+    /// (width, height) of level 0
+    pub fn dimensions(&self) -> (u64, u64) {
+        self.levels
+            .first()
+            .map_or((0, 0), |level| (level.width, level.height))
+    }
+
+    /// Micrometer (microns) per pixel of level 0 in the x direction.
+    pub fn mpp_x(&self) -> Option<f64> {
+        self.mpp_x
+    }
+
+    /// Micrometer (microns) per pixel of level 0 in the y direction.
+    pub fn mpp_y(&self) -> Option<f64> {
+        self.mpp_y
+    }
+
+    /// Objective power (nominal magnification) of level 0.
+    pub fn objective_power(&self) -> Option<f64> {
+        self.objective_power
+    }
+
+    /// The part of level 0 that was scanned, for slide formats that report it
+    /// (`openslide.bounds-*`). Pixels outside of it are transparent. If the slide has only some
+    /// of the properties, the origin defaults to 0 and the size to the level 0 dimensions, as in
+    /// openslide-python.
+    pub fn bounds(&self) -> Option<Rect<Level0>> {
+        self.bounds
+    }
+}
+
+/// Parse a property, if the slide has it.
+fn parse_property<T: FromStr>(
+    property_map: &HashMap<String, String>,
+    name: &str,
+) -> Result<Option<T>, OpenSlideError> {
+    property_map
+        .get(name)
+        .map(|value| properties::parse(name, value))
+        .transpose()
+}
@@ -13,6 +13,10 @@
 //!
 //! I cannot guarantee that it works for other versions.
 //!
+//! Bindings that are only available in OpenSlide 4.x (ICC profiles and shared tile caches) are
+//! compiled when the build script detects OpenSlide 4.x. If the version can not be detected, they are
+//! enabled with the `openslide4` feature.
+//!
 //! ## Building OpenSlide
 //!
 //! Download the OpenSlide `3.4.1` from
@@ -37,8 +41,17 @@
 //!    /usr/local/lib
 //! ```
 //!
-//! To make the library discoverable, we append it (call it `LIB_DIR`) to the `LD_LIBRARY_PATH`
-//! environment variable
+//! The build script finds the library with `pkg-config`. If it is installed somewhere pkg-config
+//! does not look (call it `LIB_DIR`), point the build script to it with the `OPENSLIDE_LIB_DIR`
+//! environment variable. The version is then read from `LIB_DIR/pkgconfig/openslide.pc`, or it can be
+//! given explicitly with the `OPENSLIDE_VERSION` environment variable
+//!
+//! ```shell,no_run
+//! export OPENSLIDE_LIB_DIR=LIB_DIR
+//! ```
+//!
+//! If `LIB_DIR` is not in the default search path of the dynamic linker, the library must also be
+//! discoverable at runtime, e.g. by appending it to the `LD_LIBRARY_PATH` environment variable
 //!
 //! ```shell,no_run
 //! export LD_LIBRARY_PATH=$LD_LIBRARY_PATH:LIB_DIR
@@ -47,6 +60,15 @@
 //! You should now be able to compile, and run a project using this OpenSlide binding. See the
 //! `examples` folder for examples.
 //!
+//! ### Loading OpenSlide at runtime
+//!
+//! With the `dynamic` feature, OpenSlide is not linked at build time. In stead, it is loaded the
+//! first time it is needed, from the path in the `OPENSLIDE_LIBRARY_PATH` environment variable, or
+//! else from the default library search path. A path can also be given explicitly with
+//! `bindings::load_library()`. If the library can not be loaded, `OpenSlide::new` returns an
+//! `OpenSlideError::LibraryNotLoaded` error. Use `bindings::optional_symbols()` to find out which
+//! of the OpenSlide 4.x functions the loaded library provides.
+//!
 //! ## Interface
 //!
 //! This library provides both a *native* interface, and a more *convenient* interface.
@@ -64,18 +86,18 @@
 //! ```rust,no_run
 //! //! Example using the raw binding api
 //!
-//! extern crate failure;
 //! extern crate openslide;
 //!
-//! use failure::Error;
-//! use openslide::bindings;
+//! use openslide::{bindings, OpenSlideError};
 //!
-//! fn main() -> Result<(), Error> {
+//! fn main() -> Result<(), OpenSlideError> {
 //!     let filename = "assets/CMU-1-Small-Region.svs";
 //!     let osr = bindings::open(filename)?;
-//!     let num_levels = bindings::get_level_count(osr)?;
-//!     println!("Slide has {} levels", num_levels);
-//!     bindings::close(osr);
+//!     unsafe {
+//!         let num_levels = bindings::get_level_count(osr)?;
+//!         println!("Slide has {} levels", num_levels);
+//!         bindings::close(osr);
+//!     }
 //!
 //!     Ok(())
 //! }
@@ -94,14 +116,12 @@
 //! ```rust,no_run
 //! //! Example using the convenience binding api
 //!
-//! extern crate failure;
 //! extern crate openslide;
 //!
 //! use std::path::Path;
-//! use failure::Error;
-//! use openslide::OpenSlide;
+//! use openslide::{OpenSlide, OpenSlideError};
 //!
-//! fn main() -> Result<(), Error> {
+//! fn main() -> Result<(), OpenSlideError> {
 //!     let filename = Path::new("assets/CMU-1-Small-Region.svs");
 //!     let os = openslide::OpenSlide::new(&filename)?;
 //!     let num_levels = os.get_level_count()?;
@@ -111,12 +131,61 @@
 //! }
 //! ```
 //!
+//! `read_region` takes the row and column of the top left corner in the level 0 reference frame,
+//! and the height and width in pixels of the level that is read. To avoid transposing or mixing up
+//! these coordinates, `read_rect` takes a `geometry::Rect` in stead, which uses x/y and
+//! width/height, and is tagged with the reference frame (level 0, or the level itself) it is
+//! given in.
+//!
+//! ### Backends
+//!
+//! The `OpenSlide` struct is a front-end over a `SlideBackend`, which does the actual reading. By
+//! default, this is the OpenSlide C library (`backend::LibOpenSlideBackend`), but other readers,
+//! or test doubles, can implement `SlideBackend` and be used with `OpenSlide::from_backend()`
+//! without changing the calling code.
+//!
+//! For tests, `backend::MemoryBackend` builds a slide from an image in memory, with a configurable
+//! set of properties, such that code using `OpenSlide` can be tested without the OpenSlide C
+//! library or slide files.
+//!
+//! With the `tiff` feature, `backend::TiffBackend` reads Aperio SVS and generic tiled TIFF slides
+//! without the OpenSlide C library, e.g. in containers where it can not be installed:
+//!
+//! ```no_run
+//! # #[cfg(feature = "tiff")]
+//! # fn main() -> Result<(), openslide::OpenSlideError> {
+//! use openslide::backend::TiffBackend;
+//! use openslide::OpenSlide;
+//! use std::path::Path;
+//!
+//! let backend = TiffBackend::open(Path::new("assets/CMU-1-Small-Region.svs"))?;
+//! let slide = OpenSlide::from_backend(backend)?;
+//! let tile = slide.read_region(0u32, 0, 0, 256, 256)?;
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "tiff"))]
+//! # fn main() {}
+//! ```
+//!
+//! ## Errors
+//!
+//! All functions return an `OpenSlideError` on failure. This is an enum, so that you can tell
+//! e.g. an invalid path from an unsupported format or an OpenSlide internal error. It implements
+//! `std::error::Error`, so it also works well with other error handling crates.
+//!
 
 extern crate byteorder;
-extern crate failure;
 extern crate image;
 extern crate libc;
+#[cfg(feature = "dynamic")]
+extern crate libloading;
 extern crate num;
+#[cfg(feature = "parallel")]
+extern crate rayon;
+#[cfg(feature = "server")]
+extern crate tiny_http;
+#[cfg(feature = "tiff")]
+extern crate weezl;
 
 /*
 pub use bindings::{OpenSlideT,
@@ -135,10 +204,27 @@ pub use bindings::{OpenSlideT,
 };
 */
 
-pub use convenience::{OpenSlide,
-};
+pub use backend::SlideBackend;
+#[cfg(openslide4)]
+pub use backend::TileCache;
+pub use convenience::{BoundsPolicy, OpenSlide};
+pub use error::OpenSlideError;
+pub use info::{LevelInfo, SlideInfo};
 
+pub mod backend;
 pub mod bindings;
+pub mod deepzoom;
+pub mod geometry;
+pub mod iiif;
 pub mod utils;
 mod convenience;
+mod error;
+mod info;
+mod library;
+pub mod pool;
 pub mod properties;
+pub mod resample;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod tiles;
+pub mod units;
@@ -0,0 +1,403 @@
+//! Access to the functions of the OpenSlide C library.
+//!
+//! By default, the library is linked at build time. With the `dynamic` feature, it is in stead
+//! loaded at runtime, the first time it is needed, such that crates depending on this crate can
+//! be built (and run, as long as no slide is opened) on machines without OpenSlide.
+//!
+//! Either way, the wrappers in the `bindings` module call OpenSlide through the function table
+//! returned by `api()`.
+//!
+
+use libc::{c_char, c_void, size_t};
+#[cfg(feature = "dynamic")]
+use std::path::Path;
+#[cfg(feature = "dynamic")]
+use std::{env, sync::OnceLock};
+
+use bindings::{OpenSlideCacheT, OpenSlideT};
+use error::OpenSlideError;
+
+/// Environment variable that overrides where the `dynamic` feature looks for the library.
+#[cfg(feature = "dynamic")]
+pub const LIBRARY_PATH_VARIABLE: &str = "OPENSLIDE_LIBRARY_PATH";
+
+/// Library names that are tried, in order, when no library path is given.
+#[cfg(feature = "dynamic")]
+const DEFAULT_LIBRARY_NAMES: &[&str] = &[
+    "libopenslide.so.1",
+    "libopenslide.so.0",
+    "libopenslide.so",
+    "libopenslide.1.dylib",
+    "libopenslide.0.dylib",
+    "libopenslide.dylib",
+    "libopenslide-1.dll",
+    "libopenslide-0.dll",
+];
+
+/// Table of the OpenSlide functions.
+///
+/// The OpenSlide 4.x functions are optional, as they are missing from older libraries.
+pub struct Api {
+    pub openslide_detect_vendor: unsafe extern "C" fn(*const c_char) -> *const c_char,
+    pub openslide_open: unsafe extern "C" fn(*const c_char) -> *const OpenSlideT,
+    pub openslide_close: unsafe extern "C" fn(*const OpenSlideT),
+    pub openslide_get_level_count: unsafe extern "C" fn(*const OpenSlideT) -> i32,
+    pub openslide_get_level0_dimensions: unsafe extern "C" fn(*const OpenSlideT, *mut i64, *mut i64),
+    pub openslide_get_level_dimensions:
+        unsafe extern "C" fn(*const OpenSlideT, i32, *mut i64, *mut i64),
+    pub openslide_get_level_downsample: unsafe extern "C" fn(*const OpenSlideT, i32) -> f64,
+    pub openslide_get_best_level_for_downsample: unsafe extern "C" fn(*const OpenSlideT, f64) -> i32,
+    pub openslide_read_region:
+        unsafe extern "C" fn(*const OpenSlideT, *mut u32, i64, i64, i32, i64, i64),
+    pub openslide_get_error: unsafe extern "C" fn(*const OpenSlideT) -> *const c_char,
+    pub openslide_get_property_names: unsafe extern "C" fn(*const OpenSlideT) -> *const *const c_char,
+    pub openslide_get_property_value:
+        unsafe extern "C" fn(*const OpenSlideT, *const c_char) -> *const c_char,
+    pub openslide_get_associated_image_names:
+        unsafe extern "C" fn(*const OpenSlideT) -> *const *const c_char,
+    pub openslide_get_associated_image_dimensions:
+        unsafe extern "C" fn(*const OpenSlideT, *const c_char, *mut i64, *mut i64),
+    pub openslide_read_associated_image:
+        unsafe extern "C" fn(*const OpenSlideT, *const c_char, *mut u32),
+    pub openslide_get_version: unsafe extern "C" fn() -> *const c_char,
+
+    // OpenSlide 4.x
+    pub openslide_get_icc_profile_size: Option<unsafe extern "C" fn(*const OpenSlideT) -> i64>,
+    pub openslide_read_icc_profile: Option<unsafe extern "C" fn(*const OpenSlideT, *mut c_void)>,
+    pub openslide_get_associated_image_icc_profile_size:
+        Option<unsafe extern "C" fn(*const OpenSlideT, *const c_char) -> i64>,
+    pub openslide_read_associated_image_icc_profile:
+        Option<unsafe extern "C" fn(*const OpenSlideT, *const c_char, *mut c_void)>,
+    pub openslide_cache_create: Option<unsafe extern "C" fn(size_t) -> *mut OpenSlideCacheT>,
+    pub openslide_set_cache: Option<unsafe extern "C" fn(*const OpenSlideT, *mut OpenSlideCacheT)>,
+    pub openslide_cache_release: Option<unsafe extern "C" fn(*mut OpenSlideCacheT)>,
+
+    /// The loaded library, which must outlive the function pointers above.
+    #[cfg(feature = "dynamic")]
+    _library: libloading::Library,
+}
+
+/// Which of the optional OpenSlide 4.x functions the OpenSlide library provides.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OptionalSymbols {
+    /// `openslide_get_icc_profile_size` and `openslide_read_icc_profile`
+    pub icc_profile: bool,
+    /// `openslide_get_associated_image_icc_profile_size` and
+    /// `openslide_read_associated_image_icc_profile`
+    pub associated_image_icc_profile: bool,
+    /// `openslide_cache_create`, `openslide_set_cache` and `openslide_cache_release`
+    pub cache: bool,
+}
+
+impl Api {
+    fn optional_symbols(&self) -> OptionalSymbols {
+        OptionalSymbols {
+            icc_profile: self.openslide_get_icc_profile_size.is_some()
+                && self.openslide_read_icc_profile.is_some(),
+            associated_image_icc_profile: self
+                .openslide_get_associated_image_icc_profile_size
+                .is_some()
+                && self.openslide_read_associated_image_icc_profile.is_some(),
+            cache: self.openslide_cache_create.is_some()
+                && self.openslide_set_cache.is_some()
+                && self.openslide_cache_release.is_some(),
+        }
+    }
+}
+
+/// Unwrap an optional OpenSlide 4.x function, or return an error naming the missing symbol.
+#[cfg(openslide4)]
+pub fn optional<T>(function: Option<T>, name: &str) -> Result<T, OpenSlideError> {
+    function.ok_or_else(|| {
+        OpenSlideError::LibraryNotLoaded(format!(
+            "The OpenSlide library has no symbol {}, it requires OpenSlide 4.x",
+            name
+        ))
+    })
+}
+
+/// Report which optional OpenSlide 4.x functions are available.
+///
+/// With the `dynamic` feature, this loads the library if it is not already loaded.
+pub fn optional_symbols() -> Result<OptionalSymbols, OpenSlideError> {
+    Ok(api()?.optional_symbols())
+}
+
+// ---------------
+// Linked at build time
+// ---------------
+
+#[cfg(not(feature = "dynamic"))]
+extern "C" {
+    fn openslide_detect_vendor(filename: *const c_char) -> *const c_char;
+
+    fn openslide_open(filename: *const c_char) -> *const OpenSlideT;
+
+    fn openslide_close(osr: *const OpenSlideT);
+
+    fn openslide_get_level_count(osr: *const OpenSlideT) -> i32;
+
+    fn openslide_get_level0_dimensions(osr: *const OpenSlideT, w: *mut i64, h: *mut i64);
+
+    fn openslide_get_level_dimensions(osr: *const OpenSlideT, level: i32, w: *mut i64, h: *mut i64);
+
+    fn openslide_get_level_downsample(osr: *const OpenSlideT, level: i32) -> f64;
+
+    fn openslide_get_best_level_for_downsample(osr: *const OpenSlideT, downsample: f64) -> i32;
+
+    fn openslide_read_region(
+        osr: *const OpenSlideT,
+        dest: *mut u32,
+        x: i64,
+        y: i64,
+        level: i32,
+        w: i64,
+        h: i64,
+    );
+
+    fn openslide_get_error(osr: *const OpenSlideT) -> *const c_char;
+
+    fn openslide_get_property_names(osr: *const OpenSlideT) -> *const *const c_char;
+
+    fn openslide_get_property_value(osr: *const OpenSlideT, name: *const c_char) -> *const c_char;
+
+    fn openslide_get_associated_image_names(osr: *const OpenSlideT) -> *const *const c_char;
+
+    fn openslide_get_associated_image_dimensions(
+        osr: *const OpenSlideT,
+        name: *const c_char,
+        w: *mut i64,
+        h: *mut i64,
+    );
+
+    fn openslide_read_associated_image(osr: *const OpenSlideT, name: *const c_char, dest: *mut u32);
+
+    fn openslide_get_version() -> *const c_char;
+}
+
+#[cfg(all(not(feature = "dynamic"), openslide4))]
+extern "C" {
+    fn openslide_get_icc_profile_size(osr: *const OpenSlideT) -> i64;
+
+    fn openslide_read_icc_profile(osr: *const OpenSlideT, dest: *mut c_void);
+
+    fn openslide_get_associated_image_icc_profile_size(
+        osr: *const OpenSlideT,
+        name: *const c_char,
+    ) -> i64;
+
+    fn openslide_read_associated_image_icc_profile(
+        osr: *const OpenSlideT,
+        name: *const c_char,
+        dest: *mut c_void,
+    );
+
+    fn openslide_cache_create(capacity: size_t) -> *mut OpenSlideCacheT;
+
+    fn openslide_set_cache(osr: *const OpenSlideT, cache: *mut OpenSlideCacheT);
+
+    fn openslide_cache_release(cache: *mut OpenSlideCacheT);
+}
+
+#[cfg(not(feature = "dynamic"))]
+static LINKED: Api = Api {
+    openslide_detect_vendor,
+    openslide_open,
+    openslide_close,
+    openslide_get_level_count,
+    openslide_get_level0_dimensions,
+    openslide_get_level_dimensions,
+    openslide_get_level_downsample,
+    openslide_get_best_level_for_downsample,
+    openslide_read_region,
+    openslide_get_error,
+    openslide_get_property_names,
+    openslide_get_property_value,
+    openslide_get_associated_image_names,
+    openslide_get_associated_image_dimensions,
+    openslide_read_associated_image,
+    openslide_get_version,
+
+    #[cfg(openslide4)]
+    openslide_get_icc_profile_size: Some(openslide_get_icc_profile_size),
+    #[cfg(openslide4)]
+    openslide_read_icc_profile: Some(openslide_read_icc_profile),
+    #[cfg(openslide4)]
+    openslide_get_associated_image_icc_profile_size: Some(
+        openslide_get_associated_image_icc_profile_size,
+    ),
+    #[cfg(openslide4)]
+    openslide_read_associated_image_icc_profile: Some(openslide_read_associated_image_icc_profile),
+    #[cfg(openslide4)]
+    openslide_cache_create: Some(openslide_cache_create),
+    #[cfg(openslide4)]
+    openslide_set_cache: Some(openslide_set_cache),
+    #[cfg(openslide4)]
+    openslide_cache_release: Some(openslide_cache_release),
+
+    #[cfg(not(openslide4))]
+    openslide_get_icc_profile_size: None,
+    #[cfg(not(openslide4))]
+    openslide_read_icc_profile: None,
+    #[cfg(not(openslide4))]
+    openslide_get_associated_image_icc_profile_size: None,
+    #[cfg(not(openslide4))]
+    openslide_read_associated_image_icc_profile: None,
+    #[cfg(not(openslide4))]
+    openslide_cache_create: None,
+    #[cfg(not(openslide4))]
+    openslide_set_cache: None,
+    #[cfg(not(openslide4))]
+    openslide_cache_release: None,
+};
+
+/// Get the table of OpenSlide functions.
+#[cfg(not(feature = "dynamic"))]
+pub fn api() -> Result<&'static Api, OpenSlideError> {
+    Ok(&LINKED)
+}
+
+// ---------------
+// Loaded at runtime
+// ---------------
+
+#[cfg(feature = "dynamic")]
+static LOADED: OnceLock<Api> = OnceLock::new();
+
+/// Get the table of OpenSlide functions.
+///
+/// The library is loaded the first time this is called, unless it is already loaded with
+/// `load_library`. It is loaded from the path in the `OPENSLIDE_LIBRARY_PATH` environment
+/// variable if it is set, or else from the default library search path of the system.
+#[cfg(feature = "dynamic")]
+pub fn api() -> Result<&'static Api, OpenSlideError> {
+    if let Some(api) = LOADED.get() {
+        return Ok(api);
+    }
+    let api = match env::var_os(LIBRARY_PATH_VARIABLE) {
+        Some(path) => load(Path::new(&path))?,
+        None => {
+            let mut errors = Vec::new();
+            let mut loaded = None;
+            for name in DEFAULT_LIBRARY_NAMES {
+                match load(Path::new(name)) {
+                    Ok(api) => {
+                        loaded = Some(api);
+                        break;
+                    }
+                    Err(OpenSlideError::LibraryNotLoaded(msg)) => errors.push(msg),
+                    Err(err) => errors.push(err.to_string()),
+                }
+            }
+            loaded.ok_or_else(|| OpenSlideError::LibraryNotLoaded(errors.join("; ")))?
+        }
+    };
+    // If another thread won the race, its library is used, and ours is unloaded again.
+    let _ = LOADED.set(api);
+    LOADED
+        .get()
+        .ok_or_else(|| OpenSlideError::LibraryNotLoaded(String::from("Library was not stored")))
+}
+
+/// Load the OpenSlide library from the given path, in stead of from the default location.
+///
+/// This must be called before any other function in this crate is used. Returns an error if the
+/// library can not be loaded, if it lacks one of the required (OpenSlide 3.4) symbols, or if a
+/// library is already loaded.
+#[cfg(feature = "dynamic")]
+pub fn load_library(path: &Path) -> Result<OptionalSymbols, OpenSlideError> {
+    if LOADED.get().is_some() {
+        return Err(OpenSlideError::LibraryNotLoaded(String::from(
+            "An OpenSlide library is already loaded",
+        )));
+    }
+    let api = load(path)?;
+    let optional_symbols = api.optional_symbols();
+    LOADED.set(api).map_err(|_| {
+        OpenSlideError::LibraryNotLoaded(String::from("An OpenSlide library is already loaded"))
+    })?;
+    Ok(optional_symbols)
+}
+
+/// Look up a symbol in the library, as a function pointer of the inferred type.
+#[cfg(feature = "dynamic")]
+macro_rules! symbol {
+    ($library:expr, $path:expr, $name:ident) => {
+        *$library
+            .get(concat!(stringify!($name), "\0").as_bytes())
+            .map_err(|err| {
+                OpenSlideError::LibraryNotLoaded(format!(
+                    "{} has no symbol {}: {}",
+                    $path.display(),
+                    stringify!($name),
+                    err
+                ))
+            })?
+    };
+}
+
+/// Look up an optional symbol in the library, as a function pointer of the inferred type.
+#[cfg(feature = "dynamic")]
+macro_rules! optional_symbol {
+    ($library:expr, $name:ident) => {
+        $library
+            .get(concat!(stringify!($name), "\0").as_bytes())
+            .ok()
+            .map(|symbol: libloading::Symbol<_>| *symbol)
+    };
+}
+
+#[cfg(feature = "dynamic")]
+fn load(path: &Path) -> Result<Api, OpenSlideError> {
+    unsafe {
+        let library = libloading::Library::new(path).map_err(|err| {
+            OpenSlideError::LibraryNotLoaded(format!("{}: {}", path.display(), err))
+        })?;
+        Ok(Api {
+            openslide_detect_vendor: symbol!(library, path, openslide_detect_vendor),
+            openslide_open: symbol!(library, path, openslide_open),
+            openslide_close: symbol!(library, path, openslide_close),
+            openslide_get_level_count: symbol!(library, path, openslide_get_level_count),
+            openslide_get_level0_dimensions: symbol!(library, path, openslide_get_level0_dimensions),
+            openslide_get_level_dimensions: symbol!(library, path, openslide_get_level_dimensions),
+            openslide_get_level_downsample: symbol!(library, path, openslide_get_level_downsample),
+            openslide_get_best_level_for_downsample: symbol!(
+                library,
+                path,
+                openslide_get_best_level_for_downsample
+            ),
+            openslide_read_region: symbol!(library, path, openslide_read_region),
+            openslide_get_error: symbol!(library, path, openslide_get_error),
+            openslide_get_property_names: symbol!(library, path, openslide_get_property_names),
+            openslide_get_property_value: symbol!(library, path, openslide_get_property_value),
+            openslide_get_associated_image_names: symbol!(
+                library,
+                path,
+                openslide_get_associated_image_names
+            ),
+            openslide_get_associated_image_dimensions: symbol!(
+                library,
+                path,
+                openslide_get_associated_image_dimensions
+            ),
+            openslide_read_associated_image: symbol!(library, path, openslide_read_associated_image),
+            openslide_get_version: symbol!(library, path, openslide_get_version),
+
+            openslide_get_icc_profile_size: optional_symbol!(library, openslide_get_icc_profile_size),
+            openslide_read_icc_profile: optional_symbol!(library, openslide_read_icc_profile),
+            openslide_get_associated_image_icc_profile_size: optional_symbol!(
+                library,
+                openslide_get_associated_image_icc_profile_size
+            ),
+            openslide_read_associated_image_icc_profile: optional_symbol!(
+                library,
+                openslide_read_associated_image_icc_profile
+            ),
+            openslide_cache_create: optional_symbol!(library, openslide_cache_create),
+            openslide_set_cache: optional_symbol!(library, openslide_set_cache),
+            openslide_cache_release: optional_symbol!(library, openslide_cache_release),
+
+            _library: library,
+        })
+    }
+}
@@ -0,0 +1,222 @@
+//! A pool of open slides, for servers that read from many slides
+//!
+//! Opening a slide is expensive, so a tile server should not open a slide on every request, but
+//! it can not keep every slide open either. A `SlidePool` opens slides when they are first
+//! requested, holds at most a given number of them, and evicts the least recently used slide to
+//! make room for another. A slide whose file has been modified since it was opened is opened
+//! again.
+//!
+//! ```no_run
+//! # fn main() -> Result<(), openslide::OpenSlideError> {
+//! use openslide::pool::SlidePool;
+//! use std::path::Path;
+//!
+//! let pool: SlidePool = SlidePool::new(16);
+//! let slide = pool.get(Path::new("assets/CMU-1-Small-Region.svs"))?;
+//! let tile = slide.read_region(0u32, 0, 0, 256, 256)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use convenience::OpenSlide;
+use error::OpenSlideError;
+
+/// A slide that has been opened.
+struct Opened<T> {
+    slide: Arc<T>,
+    /// Modification time of the file when the slide was opened
+    modified: Option<SystemTime>,
+}
+
+/// The place of a slide in the pool. The first thread that gets the slide opens it while holding
+/// the lock of the slot, such that other threads wait for it in stead of opening it again.
+struct Slot<T> {
+    opened: Mutex<Option<Opened<T>>>,
+}
+
+struct Entry<T> {
+    slot: Arc<Slot<T>>,
+    /// Value of the use counter when the slide was last used
+    last_used: u64,
+}
+
+struct Entries<T> {
+    entries: HashMap<PathBuf, Entry<T>>,
+    /// Counts the uses of the pool, which orders the entries from least to most recently used
+    uses: u64,
+}
+
+/// A thread-safe pool of open slides, by their path.
+///
+/// The pool holds at most `capacity` slides, including those that are being opened. It hands out
+/// shared handles (`Arc`), which stay valid when their slide is evicted from the pool: an evicted
+/// slide is closed when the last handle to it is dropped. The capacity therefore bounds the
+/// slides that the pool keeps open, not the handles that callers hold on to: the number of open
+/// slides is at most the capacity, plus the slides that were evicted (or opened again after their
+/// file was modified) and still have handles.
+///
+/// A slide is opened at most once at the same time: threads that get a slide while another
+/// thread opens it wait for that thread, while threads that get other slides do not wait.
+///
+/// The slides are `OpenSlide` objects by default, but the pool can also hold other values that
+/// are opened from a path, e.g. a slide together with a tile generator over it.
+pub struct SlidePool<T = OpenSlide> {
+    capacity: usize,
+    entries: Mutex<Entries<T>>,
+}
+
+impl<T> SlidePool<T> {
+    /// Create an empty pool, which holds at most `capacity` (at least 1) slides.
+    pub fn new(capacity: usize) -> SlidePool<T> {
+        SlidePool {
+            capacity: capacity.max(1),
+            entries: Mutex::new(Entries {
+                entries: HashMap::new(),
+                uses: 0,
+            }),
+        }
+    }
+
+    /// The maximum number of slides in the pool.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of slides in the pool, including those that are being opened.
+    pub fn len(&self) -> usize {
+        lock(&self.entries).entries.len()
+    }
+
+    /// Whether the pool holds no slides.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the pool holds the slide at `path`, or is opening it.
+    pub fn contains(&self, path: &Path) -> bool {
+        lock(&self.entries).entries.contains_key(path)
+    }
+
+    /// Get the slide at `path`, and open it with `open` if it is not open, or if its file has
+    /// been modified since it was opened. If the pool is full, the least recently used slide is
+    /// evicted.
+    ///
+    /// The slide is opened without locking the pool, such that other threads can get other
+    /// slides in the meantime, while threads that get the same slide wait for it. Returns an
+    /// `InvalidPath` error if the file does not exist, or the error of `open`, in which case the
+    /// slide is removed from the pool.
+    pub fn get_with<F>(&self, path: &Path, open: F) -> Result<Arc<T>, OpenSlideError>
+    where
+        F: FnOnce(&Path) -> Result<T, OpenSlideError>,
+    {
+        let modified = modified(path)?;
+        let slot = self.slot(path);
+
+        let mut opened = lock(&slot.opened);
+        if let Some(ref opened) = *opened {
+            if opened.modified == modified {
+                return Ok(opened.slide.clone());
+            }
+        }
+        match open(path) {
+            Ok(slide) => {
+                let slide = Arc::new(slide);
+                *opened = Some(Opened {
+                    slide: slide.clone(),
+                    modified,
+                });
+                Ok(slide)
+            }
+            Err(err) => {
+                // Threads that wait for the slot try to open the slide themselves
+                *opened = None;
+                drop(opened);
+                let mut entries = lock(&self.entries);
+                let current = entries
+                    .entries
+                    .get(path)
+                    .is_some_and(|entry| Arc::ptr_eq(&entry.slot, &slot));
+                if current {
+                    entries.entries.remove(path);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Remove the slide at `path` from the pool. Returns whether it was in the pool.
+    pub fn remove(&self, path: &Path) -> bool {
+        lock(&self.entries).entries.remove(path).is_some()
+    }
+
+    /// Remove all slides from the pool.
+    pub fn clear(&self) {
+        lock(&self.entries).entries.clear();
+    }
+
+    /// Get the slot of the slide at `path`, and add an empty one if the pool has none, which
+    /// evicts the least recently used slide if the pool is full.
+    fn slot(&self, path: &Path) -> Arc<Slot<T>> {
+        let mut entries = lock(&self.entries);
+        entries.uses += 1;
+        let last_used = entries.uses;
+        if let Some(entry) = entries.entries.get_mut(path) {
+            entry.last_used = last_used;
+            return entry.slot.clone();
+        }
+
+        let slot = Arc::new(Slot {
+            opened: Mutex::new(None),
+        });
+        entries.entries.insert(
+            path.to_path_buf(),
+            Entry {
+                slot: slot.clone(),
+                last_used,
+            },
+        );
+        while entries.entries.len() > self.capacity {
+            let least_recently_used = entries
+                .entries
+                .iter()
+                .min_by_key(|&(_, entry)| entry.last_used)
+                .map(|(path, _)| path.clone());
+            match least_recently_used {
+                Some(path) => entries.entries.remove(&path),
+                None => break,
+            };
+        }
+        slot
+    }
+}
+
+impl SlidePool<OpenSlide> {
+    /// Get the slide at `path`, and open it with `OpenSlide::new` if it is not open (see
+    /// `get_with`).
+    pub fn get(&self, path: &Path) -> Result<Arc<OpenSlide>, OpenSlideError> {
+        self.get_with(path, OpenSlide::new)
+    }
+}
+
+/// Lock a mutex of the pool. The pool is consistent between statements, so a panic while holding
+/// the lock, e.g. in the function that opens a slide, does not poison it.
+fn lock<U>(mutex: &Mutex<U>) -> MutexGuard<'_, U> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Modification time of a file, if the platform reports it.
+fn modified(path: &Path) -> Result<Option<SystemTime>, OpenSlideError> {
+    let metadata = fs::metadata(path).map_err(|err| {
+        OpenSlideError::InvalidPath(format!("Could not read {}: {}", path.display(), err))
+    })?;
+    Ok(metadata.modified().ok())
+}
@@ -1,8 +1,8 @@
 //! Aperio properties
 //!
 
-use num::Num;
-use std::{f32, u32};
+use error::OpenSlideError;
+use super::parse;
 
 #[derive(Clone, Debug, Default)]
 pub struct Aperio {
@@ -36,7 +36,7 @@ pub struct Aperio {
 }
 
 impl Aperio {
-    pub fn parse_property_name(&mut self, name: &str, value: &str) {
+    pub fn parse_property_name(&mut self, name: &str, value: &str) -> Result<(), OpenSlideError> {
         match name {
             "aperio.Filename" => self.filename = Some(String::from(value)),
             "aperio.Title" => self.title = Some(String::from(value)),
@@ -50,44 +50,45 @@ impl Aperio {
             "aperio.ICC Profile" => self.icc_profile = Some(String::from(value)),
             "aperio.Parmset" => self.parmset = Some(String::from(value)),
             "aperio.OriginalHeight" => {
-                self.original_height = Some(u32::from_str_radix(value, 10).unwrap())
+                self.original_height = Some(parse(name, value)?)
             }
             "aperio.OriginalWidth" => {
-                self.original_width = Some(u32::from_str_radix(value, 10).unwrap())
+                self.original_width = Some(parse(name, value)?)
             }
-            "aperio.Top" => self.top = Some(f32::from_str_radix(value, 10).unwrap()),
-            "aperio.Left" => self.left = Some(f32::from_str_radix(value, 10).unwrap()),
-            "aperio.MPP" => self.mpp = Some(f32::from_str_radix(value, 10).unwrap()),
+            "aperio.Top" => self.top = Some(parse(name, value)?),
+            "aperio.Left" => self.left = Some(parse(name, value)?),
+            "aperio.MPP" => self.mpp = Some(parse(name, value)?),
             "aperio.LineCameraSkew" => {
-                self.line_camera_skew = Some(f32::from_str_radix(value, 10).unwrap())
+                self.line_camera_skew = Some(parse(name, value)?)
             }
             "aperio.LineAreaXOffset" => {
-                self.line_area_x_offset = Some(f32::from_str_radix(value, 10).unwrap())
+                self.line_area_x_offset = Some(parse(name, value)?)
             }
             "aperio.LineAreaYOffset" => {
-                self.line_area_y_offset = Some(f32::from_str_radix(value, 10).unwrap())
+                self.line_area_y_offset = Some(parse(name, value)?)
             }
             "aperio.Focus Offset" => {
-                self.focus_offset = Some(f32::from_str_radix(value, 10).unwrap())
+                self.focus_offset = Some(parse(name, value)?)
             }
-            "aperio.AppMag" => self.app_mag = Some(u32::from_str_radix(value, 10).unwrap()),
+            "aperio.AppMag" => self.app_mag = Some(parse(name, value)?),
             "aperio.StripeWidth" => {
-                self.stripe_width = Some(u32::from_str_radix(value, 10).unwrap())
+                self.stripe_width = Some(parse(name, value)?)
             }
-            "aperio.Filtered" => self.filtered = Some(u32::from_str_radix(value, 10).unwrap()),
+            "aperio.Filtered" => self.filtered = Some(parse(name, value)?),
             "aperio.DisplayColor" => {
-                self.display_color = Some(u32::from_str_radix(value, 10).unwrap())
+                self.display_color = Some(parse(name, value)?)
             }
             "aperio.Exposure Time" => {
-                self.exposure_time = Some(u32::from_str_radix(value, 10).unwrap())
+                self.exposure_time = Some(parse(name, value)?)
             }
             "aperio.Exposure Scale" => {
-                self.exposure_scale = Some(f32::from_str_radix(value, 10).unwrap())
+                self.exposure_scale = Some(parse(name, value)?)
             }
             "aperio.SessonMode" => self.sesson_mode = Some(String::from(value)),
             //_ => println!("Could not parse property name {} and value {}", name, value),
             _ => {},
         }
+        Ok(())
     }
 
     /// Print available properties (key, value) (where the value is not `None`).
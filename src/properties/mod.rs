@@ -7,7 +7,9 @@ mod tiff;
 //mod hamamatsu;
 
 use std::collections::HashMap;
+use std::str::FromStr;
 
+use error::OpenSlideError;
 use self::openslide::LevelProperties;
 
 /// This struct defines an inferface to the various properties of the various formats.
@@ -45,7 +47,9 @@ impl Properties {
     /// This is done by submitting a property_map, which is obtained from the
     /// `OpenSlide::get_properties()` method, but this is abstracted away from the user, and
     /// happens automatically when defining an `OpenSlide` struct.
-    pub fn new(property_map: &HashMap<String, String>) -> Self {
+    ///
+    /// Returns an error if a known property has a value that can not be parsed into its type.
+    pub fn new(property_map: &HashMap<String, String>) -> Result<Self, OpenSlideError> {
         let mut tiff_properties = tiff::Tiff::default();
         // Openslide properties requires special treatement because we need to find out how many
         // levels there are in the initialization.
@@ -55,19 +59,19 @@ impl Properties {
         for (key, value) in property_map {
             let parent = key.split('.').nth(0);
             match parent {
-                Some("openslide") => openslide_properties.parse_property_name(key, value),
-                Some("tiff") => tiff_properties.parse_property_name(key, value),
-                Some("aperio") => aperio_properties.parse_property_name(key, value),
+                Some("openslide") => openslide_properties.parse_property_name(key, value)?,
+                Some("tiff") => tiff_properties.parse_property_name(key, value)?,
+                Some("aperio") => aperio_properties.parse_property_name(key, value)?,
                 //_ => println!("Could not parse {}", key),
                 _ => {},
             }
         }
 
-        Properties {
+        Ok(Properties {
             tiff_properties,
             openslide_properties,
             aperio_properties,
-        }
+        })
     }
 
     /// Print available properties (key, value) (where the value is not `None`).
@@ -113,6 +117,12 @@ impl Properties {
         self.openslide_properties.comment.clone()
     }
 
+    /// Color of the background of the slide, i.e. of regions that were not scanned, as
+    /// `[red, green, blue]`
+    pub fn background_color(&self) -> Option<[u8; 3]> {
+        self.openslide_properties.background_color
+    }
+
     /// Number of zoom levels
     pub fn level_count(&self) -> Option<u32> {
         self.openslide_properties.level_count
@@ -234,7 +244,7 @@ impl Properties {
 
     /// Slide width
     pub fn original_width(&self) -> Option<u32> {
-        self.aperio_properties.original_height
+        self.aperio_properties.original_width
     }
 
     pub fn top(&self) -> Option<f32> {
@@ -299,3 +309,11 @@ impl Properties {
         self.aperio_properties.sesson_mode.clone()
     }
 }
+
+/// Parse a property value into its type, or return an error naming the property.
+pub(crate) fn parse<T: FromStr>(name: &str, value: &str) -> Result<T, OpenSlideError> {
+    value.parse::<T>().map_err(|_| OpenSlideError::PropertyParse {
+        name: String::from(name),
+        value: String::from(value),
+    })
+}
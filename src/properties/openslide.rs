@@ -1,10 +1,10 @@
 //! Openslide properties
 //!
 
-use num::Num;
 use std::collections::HashMap;
-use std::f32;
-use std::u32;
+
+use error::OpenSlideError;
+use super::parse;
 
 /// Properties defined for every level
 #[derive(Clone, Debug, Default)]
@@ -74,6 +74,7 @@ pub struct OpenSlide {
     pub mpp_y: Option<f32>,
     pub objective_power: Option<u32>,
     pub comment: Option<String>,
+    pub background_color: Option<[u8; 3]>,
     pub level_count: Option<u32>,
     pub levels: Option<Vec<LevelProperties>>,
 }
@@ -87,10 +88,7 @@ impl OpenSlide {
         let computed_level_count = find_max_level(property_map);
         let level_count = match property_map.get("openslide.level-count") {
             Some(val) => {
-                let level_count = match u32::from_str_radix(val, 10) {
-                    Ok(val) => Some(val),
-                    Err(_) => None,
-                };
+                let level_count = val.parse::<u32>().ok();
                 if level_count != computed_level_count {
                     println!("WARNING: Computed level count is different from stated property");
                 }
@@ -119,57 +117,50 @@ impl OpenSlide {
             mpp_y: None,
             objective_power: None,
             comment: None,
+            background_color: None,
             level_count,
             levels,
         }
     }
 
-    pub fn parse_property_name(&mut self, name: &str, value: &str) {
+    pub fn parse_property_name(&mut self, name: &str, value: &str) -> Result<(), OpenSlideError> {
         match name {
             "openslide.vendor" => self.vendor = Some(String::from(value)),
             "openslide.quickhash-1" => self.quickhash_1 = Some(String::from(value)),
-            "openslide.mpp-x" => self.mpp_x = Some(f32::from_str_radix(value, 10).unwrap()),
-            "openslide.mpp-y" => self.mpp_y = Some(f32::from_str_radix(value, 10).unwrap()),
-            "openslide.objective-power" => {
-                self.objective_power = Some(u32::from_str_radix(value, 10).unwrap())
-            }
+            "openslide.mpp-x" => self.mpp_x = Some(parse(name, value)?),
+            "openslide.mpp-y" => self.mpp_y = Some(parse(name, value)?),
+            "openslide.objective-power" => self.objective_power = Some(parse(name, value)?),
             "openslide.comment" => self.comment = Some(String::from(value)),
-            "openslide.level-count" => {
-                self.level_count = Some(u32::from_str_radix(value, 10).unwrap())
-            }
+            "openslide.background-color" => self.background_color = Some(parse_color(name, value)?),
+            "openslide.level-count" => self.level_count = Some(parse(name, value)?),
             _ => {
                 if name.contains("level[") {
-                    let level = {
-                        let starts_with_number = name.split("level[").last().unwrap();
-                        let number_as_string = starts_with_number.split("]").nth(0).unwrap();
-                        u32::from_str_radix(number_as_string, 10).unwrap() as usize
+                    let level: usize = {
+                        let starts_with_number = name.split("level[").last().unwrap_or("");
+                        let number_as_string = starts_with_number.split(']').next().unwrap_or("");
+                        parse(name, number_as_string)?
                     };
                     match self.levels {
                         Some(ref mut vector) => {
+                            let level_properties = vector.get_mut(level).ok_or_else(|| {
+                                OpenSlideError::PropertyParse {
+                                    name: String::from(name),
+                                    value: String::from(value),
+                                }
+                            })?;
                             let last_part = name
                                 .split(&format!("openslide.level[{}].", level))
                                 .last()
-                                .unwrap();
+                                .unwrap_or("");
                             match last_part {
-                                "downsample" => {
-                                    vector[level].downsample =
-                                        Some(f32::from_str_radix(value, 10).unwrap())
-                                }
-                                "height" => {
-                                    vector[level].height =
-                                        Some(u32::from_str_radix(value, 10).unwrap())
-                                }
-                                "width" => {
-                                    vector[level].width =
-                                        Some(u32::from_str_radix(value, 10).unwrap())
-                                }
+                                "downsample" => level_properties.downsample = Some(parse(name, value)?),
+                                "height" => level_properties.height = Some(parse(name, value)?),
+                                "width" => level_properties.width = Some(parse(name, value)?),
                                 "tile-height" => {
-                                    vector[level].tile_height =
-                                        Some(u32::from_str_radix(value, 10).unwrap())
+                                    level_properties.tile_height = Some(parse(name, value)?)
                                 }
                                 "tile-width" => {
-                                    vector[level].tile_width =
-                                        Some(u32::from_str_radix(value, 10).unwrap())
+                                    level_properties.tile_width = Some(parse(name, value)?)
                                 }
                                 //_ => println!(
                                 //    "Could not parse property with name {} and value {}",
@@ -188,6 +179,7 @@ impl OpenSlide {
                 }
             }
         }
+        Ok(())
     }
 
     /// Print available properties (key, value) (where the value is not `None`).
@@ -210,6 +202,9 @@ impl OpenSlide {
         if let Some(ref val) = self.comment {
             println!("Comment: {}", val)
         }
+        if let Some(ref val) = self.background_color {
+            println!("Background color: {:?}", val)
+        }
         if let Some(ref val) = self.level_count {
             println!("Number of levels: {}", val)
         }
@@ -225,19 +220,27 @@ impl OpenSlide {
 
 /// Find the max level from the `openslide.level[<level>].<level-property>` properties.
 fn find_max_level(property_map: &HashMap<String, String>) -> Option<u32> {
-    let mut found_levels = Vec::<u32>::new();
-    for (key, _) in property_map {
-        if key.contains("level[") {
-            let starts_with_number = key.split("level[").last().unwrap();
-            let number_as_string = starts_with_number.split("]").nth(0).unwrap();
-            match u32::from_str_radix(number_as_string, 10) {
-                Ok(val) => found_levels.push(val),
-                Err(_) => {}
-            }
-        }
-    }
-    match found_levels.iter().max() {
-        Some(val) => Some(*val + 1),
-        None => None,
+    property_map
+        .keys()
+        .filter(|key| key.contains("level["))
+        .filter_map(|key| {
+            let starts_with_number = key.split("level[").last().unwrap_or("");
+            let number_as_string = starts_with_number.split(']').next().unwrap_or("");
+            number_as_string.parse::<u32>().ok()
+        })
+        .max()
+        .map(|val| val + 1)
+}
+
+/// Parse a color given as six hexadecimal digits (`RRGGBB`), as in `openslide.background-color`.
+fn parse_color(name: &str, value: &str) -> Result<[u8; 3], OpenSlideError> {
+    let error = || OpenSlideError::PropertyParse {
+        name: String::from(name),
+        value: String::from(value),
+    };
+    if value.len() != 6 || !value.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(error());
     }
+    let channel = |index: usize| u8::from_str_radix(&value[index..index + 2], 16).map_err(|_| error());
+    Ok([channel(0)?, channel(2)?, channel(4)?])
 }
@@ -1,8 +1,8 @@
 //! Tiff properties
 //!
 
-use num::Num;
-use std::f32;
+use error::OpenSlideError;
+use super::parse;
 
 #[derive(Clone, Debug, Default)]
 pub struct Tiff {
@@ -17,19 +17,20 @@ pub struct Tiff {
 }
 
 impl Tiff {
-    pub fn parse_property_name(&mut self, name: &str, value: &str) {
+    pub fn parse_property_name(&mut self, name: &str, value: &str) -> Result<(), OpenSlideError> {
         match name {
             "tiff.ImageDescription" => self.image_description = Some(String::from(value)),
             "tiff.Software" => self.software = Some(String::from(value)),
             "tiff.Model" => self.model = Some(String::from(value)),
             "tiff.DateTime" => self.date_time = Some(String::from(value)),
             "tiff.Make" => self.make = Some(String::from(value)),
-            "tiff.XResolution" => self.x_resolution = Some(f32::from_str_radix(value, 10).unwrap()),
-            "tiff.YResolution" => self.y_resolution = Some(f32::from_str_radix(value, 10).unwrap()),
+            "tiff.XResolution" => self.x_resolution = Some(parse(name, value)?),
+            "tiff.YResolution" => self.y_resolution = Some(parse(name, value)?),
             "tiff.ResolutionUnit" => self.resolution_unit = Some(String::from(value)),
             //_ => println!("Could not parse property name {} and value {}", name, value),
             _ => {},
         }
+        Ok(())
     }
 
     /// Print available properties (key, value) (where the value is not `None`).
@@ -0,0 +1,181 @@
+//! Resampling of pre-multiplied ARGB regions to arbitrary scales
+//!
+//! The filters are applied separably, first along rows and then along columns, on the
+//! pre-multiplied channels, such that transparent pixels do not bleed their (undefined) color into
+//! their neighbours. When downsampling, the filters are stretched by the scale factor, such that
+//! every source pixel contributes to the output (antialiasing).
+//!
+
+use std::f64::consts::PI;
+
+/// Resampling filter of `OpenSlide::read_region_scaled`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Filter {
+    /// The source pixel nearest to the center of the output pixel. This is the fastest filter,
+    /// but it is not antialiased, and aliases fine structures when downsampling.
+    Nearest,
+    /// Linear interpolation between the two nearest source pixels (a triangle filter).
+    Bilinear,
+    /// Windowed sinc filter with three lobes, which keeps edges sharp. It may ring slightly
+    /// around hard edges.
+    Lanczos3,
+    /// The average of the source pixels, weighted by how much of each pixel is covered by the
+    /// output pixel. This is the usual filter for downsampling by large factors.
+    Area,
+}
+
+impl Filter {
+    /// Radius of the filter kernel in source pixels, at a scale of 1.
+    fn support(self) -> f64 {
+        match self {
+            Filter::Nearest => 0.5,
+            Filter::Bilinear => 1.0,
+            Filter::Lanczos3 => 3.0,
+            Filter::Area => 0.5,
+        }
+    }
+
+    /// Weight of a source pixel at the given distance from the center of the output pixel, in
+    /// units of the (stretched) kernel.
+    fn kernel(self, x: f64) -> f64 {
+        let x = x.abs();
+        match self {
+            Filter::Nearest => 1.0,
+            Filter::Bilinear => (1.0 - x).max(0.0),
+            Filter::Lanczos3 => {
+                if x < 3.0 {
+                    sinc(x) * sinc(x / 3.0)
+                } else {
+                    0.0
+                }
+            }
+            Filter::Area => 1.0,
+        }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// The source pixels and their weights that make up one output pixel along one axis.
+struct Contribution {
+    /// First source pixel
+    start: usize,
+    /// Normalised weights of `start`, `start + 1`, ...
+    weights: Vec<f32>,
+}
+
+/// The source pixels that are needed along one axis.
+pub(crate) struct Axis {
+    /// First source pixel, which may be negative, in the reference frame of the source level
+    pub first: i64,
+    /// Number of source pixels
+    pub len: usize,
+    contributions: Vec<Contribution>,
+}
+
+impl Axis {
+    /// Compute the contributions of source pixels to `len` output pixels, where output pixel `i`
+    /// covers `[origin + i * scale, origin + (i + 1) * scale)` in source pixels.
+    pub fn new(origin: f64, scale: f64, len: usize, filter: Filter) -> Axis {
+        // Stretch the kernel when downsampling, which antialiases the output
+        let stretch = if filter == Filter::Nearest {
+            1.0
+        } else {
+            scale.max(1.0)
+        };
+        let support = filter.support() * stretch;
+        let first = (origin + 0.5 * scale - support).floor() as i64;
+
+        let mut contributions = Vec::with_capacity(len);
+        let mut last = first;
+        for index in 0..len {
+            // Center of the output pixel, in source pixels relative to `first`
+            let center = origin + (index as f64 + 0.5) * scale - first as f64;
+            let (start, weights) = if filter == Filter::Nearest {
+                (center.floor().max(0.0) as usize, vec![1.0])
+            } else {
+                let start = (center - support).floor().max(0.0) as usize;
+                let end = (center + support).ceil().max(0.0) as usize;
+                let weights = (start..end)
+                    .map(|source| {
+                        if filter == Filter::Area {
+                            // Overlap of the source pixel with the footprint of the output pixel
+                            let left = (source as f64).max(center - support);
+                            let right = (source as f64 + 1.0).min(center + support);
+                            (right - left).max(0.0)
+                        } else {
+                            filter.kernel((source as f64 + 0.5 - center) / stretch)
+                        }
+                    })
+                    .collect::<Vec<f64>>();
+                (start, weights)
+            };
+            let sum: f64 = weights.iter().sum();
+            let weights = weights
+                .iter()
+                .map(|&weight| if sum == 0.0 { 0.0 } else { (weight / sum) as f32 })
+                .collect::<Vec<f32>>();
+            last = last.max(first + (start + weights.len()) as i64);
+            contributions.push(Contribution { start, weights });
+        }
+
+        Axis {
+            first,
+            len: (last - first) as usize,
+            contributions,
+        }
+    }
+}
+
+/// Resample a `columns.len x rows.len` buffer of pre-multiplied ARGB words into a
+/// `columns.contributions.len() x rows.contributions.len()` buffer.
+pub(crate) fn resample(source: &[u32], columns: &Axis, rows: &Axis) -> Vec<u32> {
+    let width = columns.contributions.len();
+    if width == 0 || rows.contributions.is_empty() {
+        return Vec::new();
+    }
+
+    // Resample the rows into floating point channels
+    let mut horizontal = vec![[0f32; 4]; width * rows.len];
+    for (row, dest) in source
+        .chunks(columns.len)
+        .zip(horizontal.chunks_mut(width))
+    {
+        for (pixel, contribution) in dest.iter_mut().zip(&columns.contributions) {
+            let words = &row[contribution.start..contribution.start + contribution.weights.len()];
+            for (&word, &weight) in words.iter().zip(&contribution.weights) {
+                for (channel, &byte) in pixel.iter_mut().zip(&word.to_be_bytes()) {
+                    *channel += f32::from(byte) * weight;
+                }
+            }
+        }
+    }
+
+    // Resample the columns, and round back into pre-multiplied words
+    let mut dest = Vec::with_capacity(width * rows.contributions.len());
+    for contribution in &rows.contributions {
+        let mut line = vec![[0f32; 4]; width];
+        for (offset, &weight) in contribution.weights.iter().enumerate() {
+            let start = (contribution.start + offset) * width;
+            for (pixel, source) in line.iter_mut().zip(&horizontal[start..start + width]) {
+                for (channel, value) in pixel.iter_mut().zip(source) {
+                    *channel += value * weight;
+                }
+            }
+        }
+        dest.extend(line.iter().map(|&[alpha, red, green, blue]| {
+            // Filters with negative lobes may overshoot, and the color channels of a
+            // pre-multiplied pixel can not exceed its alpha
+            let alpha = alpha.round().clamp(0.0, 255.0);
+            let channel = |value: f32| value.round().clamp(0.0, alpha) as u32;
+            (alpha as u32) << 24 | channel(red) << 16 | channel(green) << 8 | channel(blue)
+        }));
+    }
+    dest
+}
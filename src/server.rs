@@ -0,0 +1,522 @@
+//! Deep Zoom and IIIF tile server for a directory of slides
+//!
+//! `SlideServer` finds the slides in a directory (and its subdirectories), and answers requests
+//! for the Deep Zoom descriptor and the tiles of every slide, which an OpenSeadragon viewer can
+//! show, and requests of the IIIF Image API 3.0 (see the `iiif` module). The slides are opened
+//! when they are first requested, and kept in a `SlidePool` for the following requests. The routes
+//! are
+//!
+//!  - `/`: a minimal OpenSeadragon viewer of the slides
+//!  - `/slides`: a JSON list of the slides
+//!  - `/slide/<id>.dzi`: the Deep Zoom descriptor of a slide
+//!  - `/slide/<id>_files/<level>/<column>_<row>.jpeg`: a tile of a slide
+//!  - `/iiif/<id>/info.json`: the IIIF image information of a slide
+//!  - `/iiif/<id>/<region>/<size>/<rotation>/<quality>.<format>`: an IIIF image request
+//!
+//! where `<id>` is the path of the slide relative to the directory (with `/` escaped as `%2F` in
+//! IIIF identifiers). The `serve` binary runs the server from the command line. This module is
+//! only available with the `server` feature.
+//!
+//! ```no_run
+//! # fn main() -> Result<(), openslide::OpenSlideError> {
+//! use openslide::server::SlideServer;
+//! use std::path::Path;
+//!
+//! let listener = SlideServer::new(Path::new("assets"))?.bind("127.0.0.1:8000")?;
+//! listener.run(4);
+//! # Ok(())
+//! # }
+//! ```
+//!
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use tiny_http;
+
+use convenience::OpenSlide;
+use deepzoom::{DeepZoomGenerator, TileFormat};
+use error::OpenSlideError;
+use iiif::{ImageRequest, ImageService};
+use pool::SlidePool;
+use utils::json_string;
+
+/// File extensions of the slide formats that OpenSlide reads.
+const SLIDE_EXTENSIONS: [&str; 10] = [
+    "bif", "mrxs", "ndpi", "scn", "svs", "svslide", "tif", "tiff", "vms", "vmu",
+];
+
+/// How often the workers of a `Listener` check whether they should stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Opens the slide at a path.
+type Opener = dyn Fn(&Path) -> Result<OpenSlide, OpenSlideError> + Send + Sync;
+
+/// The answer to a request.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Response {
+    /// HTTP status code
+    pub status: u16,
+    /// MIME type of the body
+    pub content_type: &'static str,
+    /// The body
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn ok(content_type: &'static str, body: Vec<u8>) -> Response {
+        Response {
+            status: 200,
+            content_type,
+            body,
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: message.as_bytes().to_vec(),
+        }
+    }
+
+    fn not_found() -> Response {
+        Response::error(404, "Not found")
+    }
+}
+
+/// A slide that has been opened, with the Deep Zoom generator and the IIIF service over it.
+struct OpenedSlide {
+    deepzoom: DeepZoomGenerator,
+    iiif: ImageService,
+}
+
+/// Serves the Deep Zoom tiles and IIIF images of the slides in a directory.
+pub struct SlideServer {
+    /// Paths of the slides, by their id
+    slides: BTreeMap<String, PathBuf>,
+    opener: Box<Opener>,
+    tile_size: u64,
+    overlap: u64,
+    limit_bounds: bool,
+    format: TileFormat,
+    base_url: String,
+    /// The slides that are open, by their path
+    pool: SlidePool<OpenedSlide>,
+}
+
+impl SlideServer {
+    /// Find the slides in a directory and its subdirectories, by their file extension. The
+    /// tiles are 254 pixels, with an overlap of 1 pixel, JPEG with quality 75, and limited to
+    /// the bounds of the slide, and at most 10 slides are kept open, as in openslide-python's
+    /// Deep Zoom server.
+    ///
+    /// Returns an `InvalidPath` error if the directory can not be read.
+    pub fn new(directory: &Path) -> Result<SlideServer, OpenSlideError> {
+        let mut slides = BTreeMap::new();
+        find_slides(directory, directory, &mut slides)?;
+        Ok(SlideServer {
+            slides,
+            opener: Box::new(OpenSlide::new),
+            tile_size: 254,
+            overlap: 1,
+            limit_bounds: true,
+            format: TileFormat::Jpeg(75),
+            base_url: String::new(),
+            pool: SlidePool::new(10),
+        })
+    }
+
+    /// Open the slides with the given function in stead of `OpenSlide::new`, e.g. to read them
+    /// with another backend.
+    pub fn with_opener<F>(mut self, opener: F) -> SlideServer
+    where
+        F: Fn(&Path) -> Result<OpenSlide, OpenSlideError> + Send + Sync + 'static,
+    {
+        self.opener = Box::new(opener);
+        self
+    }
+
+    /// Set the size of the tiles, and the number of pixels they overlap their neighbours with.
+    pub fn with_tile_size(mut self, tile_size: u64, overlap: u64) -> SlideServer {
+        self.tile_size = tile_size;
+        self.overlap = overlap;
+        self
+    }
+
+    /// Set whether only the part of the slides in their `openslide.bounds-*` properties is shown.
+    pub fn with_limit_bounds(mut self, limit_bounds: bool) -> SlideServer {
+        self.limit_bounds = limit_bounds;
+        self
+    }
+
+    /// Set the maximum number of slides that are kept open between requests (see `SlidePool`).
+    pub fn with_max_open_slides(mut self, count: usize) -> SlideServer {
+        self.pool = SlidePool::new(count);
+        self
+    }
+
+    /// Set the image format of the tiles.
+    pub fn with_format(mut self, format: TileFormat) -> SlideServer {
+        self.format = format;
+        self
+    }
+
+    /// Set the URL that the server is reached at, e.g. `https://example.org/slides`, which
+    /// prefixes the IIIF identifiers in `info.json`. By default, the identifiers are relative to
+    /// the host.
+    pub fn with_base_url(mut self, base_url: &str) -> SlideServer {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Paths of the slides, by their id.
+    pub fn slides(&self) -> &BTreeMap<String, PathBuf> {
+        &self.slides
+    }
+
+    /// Answer a `GET` request for the given URL (the path and an optional query).
+    pub fn handle(&self, url: &str) -> Response {
+        let path = match percent_decode(url.split('?').next().unwrap_or("")) {
+            Some(path) => path,
+            None => return Response::error(400, "Invalid URL"),
+        };
+        match path.as_str() {
+            "/" | "/index.html" => {
+                Response::ok("text/html; charset=utf-8", VIEWER.as_bytes().to_vec())
+            }
+            "/slides" => Response::ok("application/json", self.slide_list().into_bytes()),
+            _ => {
+                if let Some(route) = path.strip_prefix("/slide/") {
+                    self.handle_slide(route)
+                } else if let Some(route) = path.strip_prefix("/iiif/") {
+                    self.handle_iiif(route)
+                } else {
+                    Response::not_found()
+                }
+            }
+        }
+    }
+
+    /// Answer a request for the descriptor or a tile of a slide.
+    fn handle_slide(&self, route: &str) -> Response {
+        if let Some(id) = route.strip_suffix(".dzi") {
+            return match self.open(id) {
+                Ok(slide) => Response::ok(
+                    "application/xml",
+                    slide.deepzoom.dzi(self.format).into_bytes(),
+                ),
+                Err(response) => response,
+            };
+        }
+
+        let (id, tile) = match route.rfind("_files/") {
+            Some(index) => (&route[..index], &route[index + "_files/".len()..]),
+            None => return Response::not_found(),
+        };
+        let (level, column, row) = match parse_tile(tile, self.format) {
+            Some(address) => address,
+            None => return Response::not_found(),
+        };
+        let slide = match self.open(id) {
+            Ok(slide) => slide,
+            Err(response) => return response,
+        };
+        match slide.deepzoom.tile_bytes(level, column, row, self.format) {
+            Ok(bytes) => Response::ok(self.format.mime_type(), bytes),
+            // An invalid level or tile address
+            Err(OpenSlideError::InvalidArgument(_)) => Response::not_found(),
+            Err(err) => Response::error(500, &err.to_string()),
+        }
+    }
+
+    /// Answer a request for the image information or an image of a slide.
+    fn handle_iiif(&self, route: &str) -> Response {
+        if let Some(id) = route.strip_suffix("/info.json") {
+            return match self.open(id) {
+                Ok(slide) => {
+                    let url = format!(
+                        "{}/iiif/{}",
+                        self.base_url,
+                        percent_encode(id).replace('/', "%2F")
+                    );
+                    Response::ok(
+                        "application/ld+json;profile=\"http://iiif.io/api/image/3/context.json\"",
+                        slide.iiif.info_json(&url).into_bytes(),
+                    )
+                }
+                Err(response) => response,
+            };
+        }
+
+        // The identifier may contain slashes, so the request is split off from the right
+        let parts = route.rsplitn(5, '/').collect::<Vec<&str>>();
+        if parts.len() != 5 {
+            return Response::not_found();
+        }
+        let slide = match self.open(parts[4]) {
+            Ok(slide) => slide,
+            Err(response) => return response,
+        };
+        let request = format!("{}/{}/{}/{}", parts[3], parts[2], parts[1], parts[0]);
+        let result = request
+            .parse::<ImageRequest>()
+            .and_then(|request| Ok((request.format, slide.iiif.render(&request)?)));
+        // Invalid requests are errors of the client, failures to read or encode the image are not
+        match result {
+            Ok((format, bytes)) => Response::ok(format.mime_type(), bytes),
+            Err(OpenSlideError::InvalidArgument(message)) => Response::error(400, &message),
+            Err(err) => Response::error(500, &err.to_string()),
+        }
+    }
+
+    /// Get an opened slide from the pool, and open it if it is not open.
+    fn open(&self, id: &str) -> Result<Arc<OpenedSlide>, Response> {
+        let path = self.slides.get(id).ok_or_else(Response::not_found)?;
+        self.pool
+            .get_with(path, |path| {
+                let slide = (self.opener)(path)?;
+                let mut iiif = ImageService::new(&slide);
+                if let TileFormat::Jpeg(quality) = self.format {
+                    iiif = iiif.with_jpeg_quality(quality);
+                }
+                Ok(OpenedSlide {
+                    deepzoom: DeepZoomGenerator::new(
+                        &slide,
+                        self.tile_size,
+                        self.overlap,
+                        self.limit_bounds,
+                    )?,
+                    iiif,
+                })
+            })
+            .map_err(|err| Response::error(500, &err.to_string()))
+    }
+
+    /// The JSON list of the slides.
+    fn slide_list(&self) -> String {
+        let entries = self
+            .slides
+            .keys()
+            .map(|id| {
+                let encoded = percent_encode(id);
+                format!(
+                    "{{\"id\":{},\"dzi\":{},\"iiif\":{}}}",
+                    json_string(id),
+                    json_string(&format!("/slide/{}.dzi", encoded)),
+                    json_string(&format!(
+                        "{}/iiif/{}/info.json",
+                        self.base_url,
+                        encoded.replace('/', "%2F")
+                    ))
+                )
+            })
+            .collect::<Vec<String>>();
+        format!("[{}]", entries.join(","))
+    }
+
+    /// Listen for HTTP requests on the given address. Use port 0 to listen on any free port.
+    ///
+    /// Returns an `InvalidArgument` error if the server can not listen on the address.
+    pub fn bind<A: ToSocketAddrs>(self, address: A) -> Result<Listener, OpenSlideError> {
+        let http = tiny_http::Server::http(address).map_err(|err| {
+            OpenSlideError::InvalidArgument(format!("Could not listen for requests: {}", err))
+        })?;
+        Ok(Listener {
+            http,
+            server: self,
+            stopped: AtomicBool::new(false),
+        })
+    }
+}
+
+/// A `SlideServer` that listens for HTTP requests.
+pub struct Listener {
+    http: tiny_http::Server,
+    server: SlideServer,
+    stopped: AtomicBool,
+}
+
+impl Listener {
+    /// The address that the server listens on.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.http.server_addr().to_ip()
+    }
+
+    /// Answer requests on the given number of threads, until `shutdown` is called.
+    pub fn run(&self, threads: usize) {
+        thread::scope(|scope| {
+            for _ in 0..threads.max(1) {
+                scope.spawn(|| self.work());
+            }
+        });
+    }
+
+    /// Stop answering requests, which makes `run` return.
+    pub fn shutdown(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+
+    fn work(&self) {
+        while !self.stopped.load(Ordering::SeqCst) {
+            let request = match self.http.recv_timeout(POLL_INTERVAL) {
+                Ok(Some(request)) => request,
+                Ok(None) => continue,
+                Err(_) => break,
+            };
+            let response = match *request.method() {
+                tiny_http::Method::Get => self.server.handle(request.url()),
+                _ => Response::error(405, "Method not allowed"),
+            };
+            let content_type = tiny_http::Header::from_bytes(
+                &b"Content-Type"[..],
+                response.content_type.as_bytes(),
+            )
+            .expect("Content types are valid headers");
+            // Viewers on other sites, e.g. IIIF portals, may request the tiles
+            let cors =
+                tiny_http::Header::from_bytes(&b"Access-Control-Allow-Origin"[..], &b"*"[..])
+                    .expect("The CORS header is valid");
+            let http_response = tiny_http::Response::from_data(response.body)
+                .with_status_code(response.status)
+                .with_header(content_type)
+                .with_header(cors);
+            // The client may have gone away, which is no reason to stop
+            let _ = request.respond(http_response);
+        }
+    }
+}
+
+/// Add the slides in `directory` to `slides`, with their path relative to `root` as their id.
+fn find_slides(
+    root: &Path,
+    directory: &Path,
+    slides: &mut BTreeMap<String, PathBuf>,
+) -> Result<(), OpenSlideError> {
+    let read_error = |err: ::std::io::Error| {
+        OpenSlideError::InvalidPath(format!("Could not read {}: {}", directory.display(), err))
+    };
+    for entry in fs::read_dir(directory).map_err(read_error)? {
+        let path = entry.map_err(read_error)?.path();
+        let hidden = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_none_or(|name| name.starts_with('.'));
+        if hidden {
+            continue;
+        }
+        if path.is_dir() {
+            find_slides(root, &path, slides)?;
+            continue;
+        }
+        let is_slide = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| SLIDE_EXTENSIONS.contains(&extension.to_lowercase().as_str()));
+        let relative = path.strip_prefix(root).ok().and_then(|relative| {
+            relative
+                .iter()
+                .map(|component| component.to_str())
+                .collect::<Option<Vec<&str>>>()
+        });
+        if let (true, Some(relative)) = (is_slide, relative) {
+            slides.insert(relative.join("/"), path.clone());
+        }
+    }
+    Ok(())
+}
+
+/// Parse `<level>/<column>_<row>.<extension>`, where the extension must be that of `format`.
+fn parse_tile(tile: &str, format: TileFormat) -> Option<(usize, u64, u64)> {
+    let mut parts = tile.splitn(2, '/');
+    let level = parts.next()?.parse().ok()?;
+    let name = parts.next()?.strip_suffix(&format!(".{}", format.name()))?;
+    let mut address = name.splitn(2, '_');
+    let column = address.next()?.parse().ok()?;
+    let row = address.next()?.parse().ok()?;
+    Some((level, column, row))
+}
+
+/// Decode `%XX` escapes, or return `None` if an escape is invalid, or the result is not UTF-8.
+fn percent_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let hex = value.get(index + 1..index + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Escape all bytes of a slide id that are not allowed in a URL path.
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                char::from(byte).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// A page that lists the slides, and shows the selected slide with OpenSeadragon.
+const VIEWER: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Slides</title>
+<script src="https://cdn.jsdelivr.net/npm/openseadragon@4.1/build/openseadragon/openseadragon.min.js"></script>
+<style>
+html, body { height: 100%; margin: 0; font-family: sans-serif; }
+body { display: flex; }
+#slides { width: 20em; overflow: auto; padding: 0.5em; margin: 0; list-style: none; }
+#slides a { display: block; padding: 0.25em; cursor: pointer; word-break: break-all; }
+#viewer { flex: 1; background: black; }
+</style>
+</head>
+<body>
+<ul id="slides"></ul>
+<div id="viewer"></div>
+<script>
+var viewer = OpenSeadragon({
+    id: "viewer",
+    prefixUrl: "https://cdn.jsdelivr.net/npm/openseadragon@4.1/build/openseadragon/images/",
+    showNavigator: true,
+    animationTime: 0.5,
+    timeout: 120000
+});
+fetch("/slides").then(function (response) {
+    return response.json();
+}).then(function (slides) {
+    var list = document.getElementById("slides");
+    slides.forEach(function (slide, index) {
+        var link = document.createElement("a");
+        link.textContent = slide.id;
+        link.onclick = function () { viewer.open(slide.dzi); };
+        var item = document.createElement("li");
+        item.appendChild(link);
+        list.appendChild(item);
+        if (index === 0) {
+            viewer.open(slide.dzi);
+        }
+    });
+});
+</script>
+</body>
+</html>
+"#;
@@ -0,0 +1,374 @@
+//! Iteration over a level, or over a physical scale, in tiles
+//!
+//! A `TileGrid` divides a level (or a virtual level at a given number of microns per pixel) into
+//! tiles, and yields their coordinates in the reference frame of the grid and of level 0. The
+//! tiles can be read lazily with `TileGrid::tiles`, one region per tile:
+//!
+//! ```no_run
+//! # fn main() -> Result<(), openslide::OpenSlideError> {
+//! use openslide::geometry::Level;
+//! use openslide::tiles::{Edge, TileGrid};
+//! use openslide::OpenSlide;
+//! use std::path::Path;
+//!
+//! let slide = OpenSlide::new(Path::new("assets/CMU-1-Small-Region.svs"))?;
+//! let grid = TileGrid::new(&slide, Level(0))?
+//!     .with_tile_size(512, 512)
+//!     .with_overlap(32)
+//!     .with_edge(Edge::Pad);
+//! for tile in grid.tiles() {
+//!     let (tile, image) = tile?;
+//!     println!("Tile {} x {} at {:?}", tile.column, tile.row, tile.level0);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+
+use image::RgbaImage;
+
+use convenience::OpenSlide;
+use error::OpenSlideError;
+use geometry::{Level, Level0, LevelN, Rect, Size};
+use resample::Filter;
+
+/// How the tiles at the right and bottom edges are handled, when the grid is not a whole number
+/// of tiles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Edge {
+    /// Leave out the tiles that extend past the edge.
+    Drop,
+    /// Keep the full tile size, where the pixels past the edge are transparent.
+    Pad,
+    /// Shrink the tiles to the edge.
+    Shrink,
+}
+
+/// What the tiles are read from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Source {
+    /// A level of the slide, read without resampling
+    Level(Level),
+    /// A virtual level at a downsample factor, resampled with the filter
+    Scaled(Filter),
+}
+
+/// One tile of a `TileGrid`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Tile {
+    /// Column of the tile in the grid
+    pub column: u64,
+    /// Row of the tile in the grid
+    pub row: u64,
+    /// The tile in the reference frame of the grid (the level, or the virtual level)
+    pub rect: Rect<LevelN>,
+    /// The tile in the level 0 reference frame
+    pub level0: Rect<Level0>,
+}
+
+/// A division of a level into tiles of a fixed size, which may overlap.
+#[derive(Clone)]
+pub struct TileGrid {
+    slide: OpenSlide,
+    source: Source,
+    /// Downsample factors (x, y) relative to level 0
+    downsample: (f64, f64),
+    size: Size<LevelN>,
+    tile_size: (u64, u64),
+    overlap: u64,
+    stride: Option<(u64, u64)>,
+    edge: Edge,
+}
+
+impl TileGrid {
+    /// Create a grid of 256 x 256 pixel tiles over a level of the slide, without overlap, where
+    /// the tiles at the edges shrink.
+    ///
+    /// Returns an error if the level is invalid.
+    pub fn new(slide: &OpenSlide, level: Level) -> Result<TileGrid, OpenSlideError> {
+        let info = slide.info().level(level.0)?;
+        Ok(TileGrid::with_source(
+            slide,
+            Source::Level(level),
+            (info.downsample, info.downsample),
+            Size::new(info.width, info.height),
+        ))
+    }
+
+    /// Create a grid over a virtual level with square pixels of `target_mpp` microns, whose tiles
+    /// are resampled with the given filter, as in `OpenSlide::read_region_at_mpp`.
+    ///
+    /// Returns an error if the slide has no calibration (see `OpenSlide::calibration`).
+    pub fn at_mpp(
+        slide: &OpenSlide,
+        target_mpp: f64,
+        filter: Filter,
+    ) -> Result<TileGrid, OpenSlideError> {
+        let downsample = slide.calibration()?.downsample_for_mpp(target_mpp)?;
+        let (width, height) = slide.info().dimensions();
+        let scaled = |length: u64, downsample: f64| (length as f64 / downsample).ceil() as u64;
+        Ok(TileGrid::with_source(
+            slide,
+            Source::Scaled(filter),
+            downsample,
+            Size::new(scaled(width, downsample.0), scaled(height, downsample.1)),
+        ))
+    }
+
+    fn with_source(
+        slide: &OpenSlide,
+        source: Source,
+        downsample: (f64, f64),
+        size: Size<LevelN>,
+    ) -> TileGrid {
+        TileGrid {
+            slide: slide.clone(),
+            source,
+            downsample,
+            size,
+            tile_size: (256, 256),
+            overlap: 0,
+            stride: None,
+            edge: Edge::Shrink,
+        }
+    }
+
+    /// Set the size (width, height) of the tiles, in pixels of the grid.
+    pub fn with_tile_size(mut self, tile_width: u64, tile_height: u64) -> TileGrid {
+        self.tile_size = (tile_width.max(1), tile_height.max(1));
+        self
+    }
+
+    /// Set the number of pixels that neighbouring tiles share. The stride between tiles is the
+    /// tile size minus the overlap, unless it is set with `with_stride`.
+    pub fn with_overlap(mut self, overlap: u64) -> TileGrid {
+        self.overlap = overlap;
+        self
+    }
+
+    /// Set the distance (x, y) between the top left corners of neighbouring tiles, in pixels of
+    /// the grid. This overrides the overlap.
+    pub fn with_stride(mut self, stride_x: u64, stride_y: u64) -> TileGrid {
+        self.stride = Some((stride_x.max(1), stride_y.max(1)));
+        self
+    }
+
+    /// Set how the tiles at the right and bottom edges are handled.
+    pub fn with_edge(mut self, edge: Edge) -> TileGrid {
+        self.edge = edge;
+        self
+    }
+
+    /// Round the tile size and the stride up to whole multiples of the native tile size of the
+    /// level (`openslide.level[<level>].tile-width` and `tile-height`), such that every tile is
+    /// read from whole native tiles, which the backend can decode and cache once.
+    ///
+    /// Returns an `Unsupported` error if the slide does not report the tile size of the level,
+    /// or if the grid is over a virtual level, which has no native tiles.
+    pub fn snap_to_native_tiles(mut self) -> Result<TileGrid, OpenSlideError> {
+        let level = match self.source {
+            Source::Level(level) => level,
+            Source::Scaled(_) => {
+                return Err(OpenSlideError::Unsupported(String::from(
+                    "A grid at a physical scale has no native tiles to snap to",
+                )))
+            }
+        };
+        let info = self.slide.info().level(level.0)?;
+        let (native_width, native_height) = match (info.tile_width, info.tile_height) {
+            (Some(width), Some(height)) if width > 0 && height > 0 => (width, height),
+            _ => {
+                return Err(OpenSlideError::Unsupported(format!(
+                    "The slide does not report the tile size of {}",
+                    level
+                )))
+            }
+        };
+        let snap = |length: u64, native: u64| length.div_ceil(native) * native;
+        let (stride_x, stride_y) = self.stride();
+        self.tile_size = (
+            snap(self.tile_size.0, native_width),
+            snap(self.tile_size.1, native_height),
+        );
+        self.stride = Some((snap(stride_x, native_width), snap(stride_y, native_height)));
+        Ok(self)
+    }
+
+    /// Size of the grid, i.e. of the level or the virtual level, in pixels.
+    pub fn dimensions(&self) -> Size<LevelN> {
+        self.size
+    }
+
+    /// Downsample factors (x, y) of the grid relative to level 0, which only differ for grids at
+    /// a physical scale over slides with non-square pixels.
+    pub fn downsample(&self) -> (f64, f64) {
+        self.downsample
+    }
+
+    /// Size (width, height) of the tiles.
+    pub fn tile_size(&self) -> (u64, u64) {
+        self.tile_size
+    }
+
+    /// Distance (x, y) between the top left corners of neighbouring tiles.
+    pub fn stride(&self) -> (u64, u64) {
+        self.stride.unwrap_or_else(|| {
+            let stride = |tile: u64| tile.saturating_sub(self.overlap).max(1);
+            (stride(self.tile_size.0), stride(self.tile_size.1))
+        })
+    }
+
+    /// Number of (columns, rows) of tiles.
+    pub fn grid_size(&self) -> (u64, u64) {
+        let (stride_x, stride_y) = self.stride();
+        (
+            tile_count(self.size.width, self.tile_size.0, stride_x, self.edge),
+            tile_count(self.size.height, self.tile_size.1, stride_y, self.edge),
+        )
+    }
+
+    /// Number of tiles
+    pub fn len(&self) -> u64 {
+        let (columns, rows) = self.grid_size();
+        columns * rows
+    }
+
+    /// Whether the grid has no tiles, e.g. when the tiles are larger than the level and are
+    /// dropped.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the tile at the given column and row, or `None` if it is outside the grid.
+    pub fn tile(&self, column: u64, row: u64) -> Option<Tile> {
+        let (columns, rows) = self.grid_size();
+        if column >= columns || row >= rows {
+            return None;
+        }
+        let (stride_x, stride_y) = self.stride();
+        let (x, y) = (column * stride_x, row * stride_y);
+        let (mut width, mut height) = self.tile_size;
+        if self.edge == Edge::Shrink {
+            width = width.min(self.size.width - x);
+            height = height.min(self.size.height - y);
+        }
+        let rect = Rect::new(x as i64, y as i64, width, height);
+
+        // Rounded up, as `Rect::to_level0`
+        let to_level0 = |value: f64, downsample: f64| (value * downsample).ceil() as i64;
+        let (downsample_x, downsample_y) = self.downsample;
+        let left = to_level0(rect.origin.x as f64, downsample_x);
+        let top = to_level0(rect.origin.y as f64, downsample_y);
+        let level0 = Rect::new(
+            left,
+            top,
+            (to_level0(rect.right() as f64, downsample_x) - left) as u64,
+            (to_level0(rect.bottom() as f64, downsample_y) - top) as u64,
+        );
+        Some(Tile {
+            column,
+            row,
+            rect,
+            level0,
+        })
+    }
+
+    /// Iterate over the coordinates of the tiles, row by row, without reading them.
+    pub fn iter(&self) -> TileIter<'_> {
+        TileIter {
+            grid: self,
+            index: 0,
+        }
+    }
+
+    /// Iterate over the tiles, row by row, and read each tile when the iterator reaches it.
+    pub fn tiles(&self) -> Tiles<'_> {
+        Tiles { tiles: self.iter() }
+    }
+
+    /// Read a tile of this grid. Pixels outside the slide are transparent.
+    pub fn read(&self, tile: &Tile) -> Result<RgbaImage, OpenSlideError> {
+        let size = tile.rect.size;
+        match self.source {
+            Source::Level(level) => {
+                let mut image = RgbaImage::new(size.width as u32, size.height as u32);
+                self.slide.read_region_into_image(
+                    &mut image,
+                    tile.level0.origin.y,
+                    tile.level0.origin.x,
+                    i64::from(level.0),
+                )?;
+                Ok(image)
+            }
+            Source::Scaled(filter) => self.slide.read_resampled_image(
+                tile.level0.origin.x as f64,
+                tile.level0.origin.y as f64,
+                self.downsample,
+                size,
+                filter,
+            ),
+        }
+    }
+}
+
+/// Number of tiles along one axis of the given length.
+fn tile_count(length: u64, tile: u64, stride: u64, edge: Edge) -> u64 {
+    if length == 0 {
+        0
+    } else if edge == Edge::Drop {
+        if length < tile {
+            0
+        } else {
+            (length - tile) / stride + 1
+        }
+    } else if length <= tile {
+        1
+    } else {
+        // Stop at the first tile that reaches the edge, or at the edge itself, if the stride is
+        // larger than the tiles
+        ((length - tile).div_ceil(stride) + 1).min(length.div_ceil(stride))
+    }
+}
+
+/// Iterator over the coordinates of the tiles of a `TileGrid`.
+pub struct TileIter<'a> {
+    grid: &'a TileGrid,
+    index: u64,
+}
+
+impl<'a> Iterator for TileIter<'a> {
+    type Item = Tile;
+
+    fn next(&mut self) -> Option<Tile> {
+        let (columns, _) = self.grid.grid_size();
+        if columns == 0 {
+            return None;
+        }
+        let tile = self.grid.tile(self.index % columns, self.index / columns)?;
+        self.index += 1;
+        Some(tile)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.grid.len().saturating_sub(self.index) as usize;
+        (remaining, Some(remaining))
+    }
+}
+
+/// Iterator over the tiles of a `TileGrid`, which reads every tile when it is reached.
+pub struct Tiles<'a> {
+    tiles: TileIter<'a>,
+}
+
+impl<'a> Iterator for Tiles<'a> {
+    type Item = Result<(Tile, RgbaImage), OpenSlideError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let tile = self.tiles.next()?;
+        Some(self.tiles.grid.read(&tile).map(|image| (tile, image)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.tiles.size_hint()
+    }
+}
@@ -0,0 +1,190 @@
+//! Conversions between pixels, micrometres (microns) and nominal magnification
+//!
+//! The conversions need the calibration of the slide: the size of a level 0 pixel in microns
+//! (`openslide.mpp-x` and `openslide.mpp-y`), and for magnifications, the objective power that
+//! level 0 was scanned with (`openslide.objective-power`). Not every slide has these properties,
+//! so they can also be given explicitly with `OpenSlide::with_calibration`.
+//!
+//! ```no_run
+//! # fn main() -> Result<(), openslide::OpenSlideError> {
+//! use openslide::geometry::Size;
+//! use openslide::resample::Filter;
+//! use openslide::units::PointUm;
+//! use openslide::OpenSlide;
+//! use std::path::Path;
+//!
+//! let slide = OpenSlide::new(Path::new("assets/CMU-1-Small-Region.svs"))?;
+//! // 256 x 256 pixels of 0.5 microns, 100 microns from the top left corner of the slide
+//! let origin = PointUm::new(100.0, 100.0);
+//! let image = slide.read_region_at_mpp(origin, Size::new(256, 256), 0.5, Filter::Area)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+
+use error::OpenSlideError;
+use geometry::{Level0, Point};
+use info::SlideInfo;
+
+/// A position in microns, relative to the top left corner of level 0.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PointUm {
+    /// Distance to the right, in microns
+    pub x: f64,
+    /// Distance downwards, in microns
+    pub y: f64,
+}
+
+impl PointUm {
+    /// Create a position from its distances in microns.
+    pub fn new(x: f64, y: f64) -> PointUm {
+        PointUm { x, y }
+    }
+}
+
+/// The physical size of the pixels of a slide.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Calibration {
+    /// Microns per level 0 pixel in the x direction
+    pub mpp_x: f64,
+    /// Microns per level 0 pixel in the y direction
+    pub mpp_y: f64,
+    /// Objective power (nominal magnification) of level 0, e.g. 40.0
+    pub objective_power: Option<f64>,
+}
+
+impl Calibration {
+    /// Create a calibration from the microns per level 0 pixel, without an objective power.
+    ///
+    /// Returns an `InvalidArgument` error if the microns per pixel are not positive.
+    pub fn new(mpp_x: f64, mpp_y: f64) -> Result<Calibration, OpenSlideError> {
+        check_positive("Microns per pixel", mpp_x)?;
+        check_positive("Microns per pixel", mpp_y)?;
+        Ok(Calibration {
+            mpp_x,
+            mpp_y,
+            objective_power: None,
+        })
+    }
+
+    /// Set the objective power of level 0.
+    ///
+    /// Returns an `InvalidArgument` error if the objective power is not positive.
+    pub fn with_objective_power(
+        mut self,
+        objective_power: f64,
+    ) -> Result<Calibration, OpenSlideError> {
+        check_positive("Objective power", objective_power)?;
+        self.objective_power = Some(objective_power);
+        Ok(self)
+    }
+
+    /// Read the calibration from the properties of a slide, or return a `MissingCalibration`
+    /// error if the slide does not have its microns per pixel.
+    pub fn from_info(info: &SlideInfo) -> Result<Calibration, OpenSlideError> {
+        let (mpp_x, mpp_y) = match (info.mpp_x(), info.mpp_y()) {
+            (Some(mpp_x), Some(mpp_y)) => (mpp_x, mpp_y),
+            _ => {
+                return Err(OpenSlideError::MissingCalibration(String::from(
+                    "The slide has no microns per pixel (openslide.mpp-x and openslide.mpp-y). \
+                     Give them with OpenSlide::with_calibration",
+                )))
+            }
+        };
+        let calibration = Calibration::new(mpp_x, mpp_y)?;
+        match info.objective_power() {
+            Some(objective_power) => calibration.with_objective_power(objective_power),
+            None => Ok(calibration),
+        }
+    }
+
+    /// Microns per level 0 pixel, averaged over both directions. The conversions use the
+    /// microns per pixel of each direction, which differ on slides with non-square pixels.
+    pub fn mpp(&self) -> f64 {
+        (self.mpp_x + self.mpp_y) / 2.0
+    }
+
+    /// Convert a level 0 pixel position to microns.
+    pub fn to_microns(&self, point: Point<Level0>) -> PointUm {
+        PointUm::new(point.x as f64 * self.mpp_x, point.y as f64 * self.mpp_y)
+    }
+
+    /// Convert a position in microns to the level 0 pixel that contains it.
+    pub fn to_pixels(&self, point: PointUm) -> Result<Point<Level0>, OpenSlideError> {
+        let to_i64 = |value: f64| {
+            let value = value.floor();
+            if value.is_finite() && value >= i64::MIN as f64 && value <= i64::MAX as f64 {
+                Ok(value as i64)
+            } else {
+                Err(OpenSlideError::InvalidArgument(format!(
+                    "Position {:?} is not a valid pixel position",
+                    point
+                )))
+            }
+        };
+        Ok(Point::new(
+            to_i64(point.x / self.mpp_x)?,
+            to_i64(point.y / self.mpp_y)?,
+        ))
+    }
+
+    /// Downsample factors (x, y) relative to level 0 that give square pixels of `mpp` microns.
+    pub fn downsample_for_mpp(&self, mpp: f64) -> Result<(f64, f64), OpenSlideError> {
+        check_positive("Microns per pixel", mpp)?;
+        Ok((mpp / self.mpp_x, mpp / self.mpp_y))
+    }
+
+    /// Microns per pixel (x, y) at a downsample factor relative to level 0.
+    pub fn mpp_at_downsample(&self, downsample: f64) -> (f64, f64) {
+        (self.mpp_x * downsample, self.mpp_y * downsample)
+    }
+
+    /// Downsample factor relative to level 0 that gives the nominal `magnification`, e.g. 4.0
+    /// for a magnification of 10 on a slide that was scanned at 40x.
+    ///
+    /// Returns a `MissingCalibration` error if the objective power is unknown.
+    pub fn downsample_for_magnification(&self, magnification: f64) -> Result<f64, OpenSlideError> {
+        downsample_for_magnification(self.require_objective_power()?, magnification)
+    }
+
+    /// Nominal magnification at a downsample factor relative to level 0.
+    ///
+    /// Returns a `MissingCalibration` error if the objective power is unknown.
+    pub fn magnification_at_downsample(&self, downsample: f64) -> Result<f64, OpenSlideError> {
+        check_positive("Downsample factor", downsample)?;
+        Ok(self.require_objective_power()? / downsample)
+    }
+
+    fn require_objective_power(&self) -> Result<f64, OpenSlideError> {
+        self.objective_power.ok_or_else(missing_objective_power)
+    }
+}
+
+/// Downsample factor relative to level 0 that gives the nominal `magnification`, for a slide
+/// that was scanned with `objective_power`.
+pub(crate) fn downsample_for_magnification(
+    objective_power: f64,
+    magnification: f64,
+) -> Result<f64, OpenSlideError> {
+    check_positive("Objective power", objective_power)?;
+    check_positive("Magnification", magnification)?;
+    Ok(objective_power / magnification)
+}
+
+pub(crate) fn missing_objective_power() -> OpenSlideError {
+    OpenSlideError::MissingCalibration(String::from(
+        "The slide has no objective power (openslide.objective-power). Give it with \
+         OpenSlide::with_calibration",
+    ))
+}
+
+fn check_positive(name: &str, value: f64) -> Result<(), OpenSlideError> {
+    if value.is_finite() && value > 0.0 {
+        Ok(())
+    } else {
+        Err(OpenSlideError::InvalidArgument(format!(
+            "{} must be positive, not {}",
+            name, value
+        )))
+    }
+}
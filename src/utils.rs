@@ -1,11 +1,11 @@
 //! Misc utility definitions
 
-use byteorder::ByteOrder;
-use failure::{format_err, Error};
-use image::{Rgba, RgbaImage};
+use image::RgbaImage;
 use num::{Integer, ToPrimitive, Unsigned};
 use std::fmt::{Debug, Display};
 
+use error::{conversion_error, OpenSlideError};
+
 /// A list of supported formats
 ///
 /// Information gathered from [https://openslide.org/formats/](https://openslide.org/formats/)
@@ -15,23 +15,23 @@ pub enum Format {
     /// Single-file pyramidal tiled TIFF, with non-standard metadata and compression.
     ///
     /// File extensions:
-    /// 	.svs, .tif
+    ///     .svs, .tif
     Aperio,
     /// Multi-file JPEG/NGR with proprietary metadata and index file formats, and single-file
     /// TIFF-like format with proprietary metadata.
     ///
     /// File extensions:
-    /// 	.vms, .vmu, .ndpi
+    ///     .vms, .vmu, .ndpi
     Hamamatsu,
     /// Single-file pyramidal tiled BigTIFF with non-standard metadata.
     ///
     /// File extensions
-    /// 	.scn
+    ///     .scn
     Leica,
     /// Multi-file with very complicated proprietary metadata and indexes.
     ///
     /// File extensions
-    /// 	.mrxs
+    ///     .mrxs
     Mirax,
     /// Single-file pyramidal tiled TIFF or BigTIFF with non-standard metadata.
     ///
@@ -76,54 +76,371 @@ pub enum WordRepresentation {
     LittleEndian,
 }
 
+/// The pixel layouts that a region can be decoded into.
+///
+/// All layouts are row-major. Except for `Composited`, the color values are un-premultiplied.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelFormat {
+    /// RGBA, 4 bytes per pixel. This is the layout of `OpenSlide::read_region`.
+    Rgba8,
+    /// RGB with the alpha channel dropped, 3 bytes per pixel. Transparent pixels are black.
+    Rgb8,
+    /// RGB composited onto the given `[red, green, blue]` background color, 3 bytes per pixel.
+    /// Transparent pixels get the background color.
+    Composited([u8; 3]),
+    /// Luma of the RGB values (with the Rec. 709 weights, as in the `image` crate), 1 byte per
+    /// pixel.
+    Gray8,
+    /// RGB normalised to `[0, 1]`, 3 floats per pixel (height x width x channel).
+    RgbF32,
+    /// RGB normalised to `[0, 1]`, in planes (channel x height x width): all red values, then all
+    /// green values, then all blue values.
+    PlanarRgbF32,
+}
+
+impl PixelFormat {
+    /// Get the number of channels (samples per pixel) of the format.
+    pub fn channels(self) -> usize {
+        match self {
+            PixelFormat::Rgba8 => 4,
+            PixelFormat::Gray8 => 1,
+            _ => 3,
+        }
+    }
+}
+
+/// The samples of a decoded region, which are bytes or floats depending on the pixel format.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Samples {
+    U8(Vec<u8>),
+    F32(Vec<f32>),
+}
+
+/// A region that is decoded into a `PixelFormat`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedRegion {
+    pub width: u32,
+    pub height: u32,
+    pub format: PixelFormat,
+    pub samples: Samples,
+}
+
+impl DecodedRegion {
+    /// Get the samples if the format has byte samples.
+    pub fn as_u8(&self) -> Option<&[u8]> {
+        match self.samples {
+            Samples::U8(ref samples) => Some(samples),
+            Samples::F32(_) => None,
+        }
+    }
+
+    /// Get the samples if the format has float samples.
+    pub fn as_f32(&self) -> Option<&[f32]> {
+        match self.samples {
+            Samples::F32(ref samples) => Some(samples),
+            Samples::U8(_) => None,
+        }
+    }
+}
+
+/// Regions with at least this many pixels are decoded on several threads (with the `parallel`
+/// feature).
+#[cfg(feature = "parallel")]
+const PARALLEL_THRESHOLD: usize = 1 << 18;
+
+/// Number of pixels that each thread decodes at a time.
+#[cfg(feature = "parallel")]
+const PARALLEL_CHUNK: usize = 1 << 14;
+
+/// Number of pixels that are checked for opacity, and decoded, together.
+const LANES: usize = 16;
+
+/// Un-premultiplied value of each pre-multiplied color value (inner index), for each alpha value
+/// (outer index).
+static UNPREMULTIPLY: [[u8; 256]; 256] = unpremultiply_table();
+
+const fn unpremultiply_table() -> [[u8; 256]; 256] {
+    let mut table = [[0u8; 256]; 256];
+    let mut alpha = 0;
+    while alpha < 256 {
+        let mut value = 0;
+        while value < 256 {
+            table[alpha][value] = if alpha == 0 || alpha == 255 {
+                value as u8
+            } else {
+                // Round to the nearest value, and clamp invalid (larger than alpha) values
+                let scaled = (value * 255 + alpha / 2) / alpha;
+                if scaled > 255 {
+                    255
+                } else {
+                    scaled as u8
+                }
+            };
+            value += 1;
+        }
+        alpha += 1;
+    }
+    table
+}
+
 /// This function takes a buffer, as the one obtained from openslide::read_region, and decodes into
 /// an Rgba image buffer.
+///
+/// The pixels are un-premultiplied with a lookup table, and runs of opaque pixels, which make up
+/// most of a slide, are only reordered. With the `parallel` feature, large regions are decoded on
+/// several threads.
 pub fn decode_buffer<T: Unsigned + Integer + ToPrimitive + Debug + Display + Clone + Copy>(
-    buffer: &Vec<u32>,
+    buffer: &[u32],
+    height: T,
+    width: T,
+    word_representation: WordRepresentation,
+) -> Result<RgbaImage, OpenSlideError> {
+    let (width, height, len) = checked_dimensions(buffer, height, width)?;
+    let mut data = vec![0u8; len * 4];
+    match word_representation {
+        WordRepresentation::BigEndian => decode_words(&buffer[..len], &mut data, u32::to_be_bytes),
+        WordRepresentation::LittleEndian => {
+            decode_words(&buffer[..len], &mut data, u32::to_le_bytes)
+        }
+    }
+    RgbaImage::from_raw(width, height, data).ok_or_else(conversion_error)
+}
+
+/// This function takes a buffer, as the one obtained from openslide::read_region, and decodes it
+/// into the given pixel format, in one pass over the buffer.
+pub fn decode_buffer_as<T: Unsigned + Integer + ToPrimitive + Debug + Display + Clone + Copy>(
+    buffer: &[u32],
     height: T,
     width: T,
     word_representation: WordRepresentation,
-) -> Result<RgbaImage, Error> {
-    let mut rgba_image = RgbaImage::new(
-        width
-            .to_u32()
-            .ok_or(format_err!("Conversion to primitive error"))?,
-        height
-            .to_u32()
-            .ok_or(format_err!("Conversion to primitive error"))?,
-    );
-
-    for (col, row, pixel) in rgba_image.enumerate_pixels_mut() {
-        let curr_pos = row * width
-            .to_u32()
-            .ok_or(format_err!("Conversion to primitive error"))?
-            + col;
-        let value = buffer[curr_pos as usize];
-
-        let mut buf = [0; 4];
-        match word_representation {
-            WordRepresentation::BigEndian => byteorder::BigEndian::write_u32(&mut buf, value),
-            WordRepresentation::LittleEndian => byteorder::BigEndian::write_u32(&mut buf, value),
-        };
-        let [alpha, mut red, mut green, mut blue] = buf;
-
-        if alpha != 0 && alpha != 255 {
-            red = (red as f32 * (255.0 / alpha as f32))
-                .round()
-                .max(0.0)
-                .min(255.0) as u8;
-            green = (green as f32 * (255.0 / alpha as f32))
-                .round()
-                .max(0.0)
-                .min(255.0) as u8;
-            blue = (blue as f32 * (255.0 / alpha as f32))
-                .round()
-                .max(0.0)
-                .min(255.0) as u8;
-        }
-
-        *pixel = Rgba([red, green, blue, alpha]);
+    format: PixelFormat,
+) -> Result<DecodedRegion, OpenSlideError> {
+    let (width, height, len) = checked_dimensions(buffer, height, width)?;
+    let samples = match word_representation {
+        WordRepresentation::BigEndian => decode_samples(&buffer[..len], format, u32::to_be_bytes),
+        WordRepresentation::LittleEndian => {
+            decode_samples(&buffer[..len], format, u32::to_le_bytes)
+        }
+    };
+    Ok(DecodedRegion {
+        width,
+        height,
+        format,
+        samples,
+    })
+}
+
+/// Convert the dimensions to `u32`, and check that the buffer has room for them. Returns the
+/// width, the height and the number of pixels.
+fn checked_dimensions<T: ToPrimitive>(
+    buffer: &[u32],
+    height: T,
+    width: T,
+) -> Result<(u32, u32, usize), OpenSlideError> {
+    let width = width.to_u32().ok_or_else(conversion_error)?;
+    let height = height.to_u32().ok_or_else(conversion_error)?;
+    let len = (width as usize)
+        .checked_mul(height as usize)
+        .ok_or_else(conversion_error)?;
+    if buffer.len() < len {
+        return Err(OpenSlideError::InvalidArgument(format!(
+            "Buffer of {} pixels is too small for an image of {} x {} pixels",
+            buffer.len(),
+            width,
+            height
+        )));
     }
+    Ok((width, height, len))
+}
+
+/// Decode words into samples of the given format, where `to_argb` gives the
+/// `[alpha, red, green, blue]` bytes of a word.
+fn decode_samples<F: Fn(u32) -> [u8; 4] + Sync>(
+    words: &[u32],
+    format: PixelFormat,
+    to_argb: F,
+) -> Samples {
+    let rgb = |word: u32| {
+        let [alpha, red, green, blue] = to_argb(word);
+        let [red, green, blue, _] = unpremultiply(alpha, red, green, blue);
+        [red, green, blue]
+    };
+    let normalise = |value: u8| f32::from(value) / 255.0;
+
+    match format {
+        PixelFormat::Rgba8 => {
+            let mut data = vec![0u8; words.len() * 4];
+            decode_words(words, &mut data, to_argb);
+            Samples::U8(data)
+        }
+        PixelFormat::Rgb8 => {
+            let mut data = vec![0u8; words.len() * 3];
+            for (pixel, &word) in data.chunks_exact_mut(3).zip(words) {
+                pixel.copy_from_slice(&rgb(word));
+            }
+            Samples::U8(data)
+        }
+        PixelFormat::Composited(background) => {
+            let mut data = vec![0u8; words.len() * 3];
+            for (pixel, &word) in data.chunks_exact_mut(3).zip(words) {
+                let [alpha, red, green, blue] = to_argb(word);
+                pixel.copy_from_slice(&composite([red, green, blue], alpha, background));
+            }
+            Samples::U8(data)
+        }
+        PixelFormat::Gray8 => Samples::U8(words.iter().map(|&word| luma(rgb(word))).collect()),
+        PixelFormat::RgbF32 => {
+            let mut data = vec![0f32; words.len() * 3];
+            for (pixel, &word) in data.chunks_exact_mut(3).zip(words) {
+                let [red, green, blue] = rgb(word);
+                pixel.copy_from_slice(&[normalise(red), normalise(green), normalise(blue)]);
+            }
+            Samples::F32(data)
+        }
+        PixelFormat::PlanarRgbF32 => {
+            let mut data = vec![0f32; words.len() * 3];
+            {
+                let (reds, rest) = data.split_at_mut(words.len());
+                let (greens, blues) = rest.split_at_mut(words.len());
+                for (index, &word) in words.iter().enumerate() {
+                    let [red, green, blue] = rgb(word);
+                    reds[index] = normalise(red);
+                    greens[index] = normalise(green);
+                    blues[index] = normalise(blue);
+                }
+            }
+            Samples::F32(data)
+        }
+    }
+}
+
+/// Composite pre-multiplied color values onto an opaque background color.
+fn composite(color: [u8; 3], alpha: u8, background: [u8; 3]) -> [u8; 3] {
+    let transparency = 255 - u32::from(alpha);
+    let blend = |value: u8, background: u8| {
+        ((u32::from(value) * 255 + u32::from(background) * transparency + 127) / 255).min(255) as u8
+    };
+    [
+        blend(color[0], background[0]),
+        blend(color[1], background[1]),
+        blend(color[2], background[2]),
+    ]
+}
+
+/// Luma of an RGB pixel, with the Rec. 709 weights.
+fn luma([red, green, blue]: [u8; 3]) -> u8 {
+    ((2126 * u32::from(red) + 7152 * u32::from(green) + 722 * u32::from(blue) + 5000) / 10000) as u8
+}
+
+/// Decode words into RGBA bytes, on several threads if there are many.
+#[cfg(feature = "parallel")]
+fn decode_words<F: Fn(u32) -> [u8; 4] + Sync>(words: &[u32], data: &mut [u8], to_argb: F) {
+    use rayon::prelude::*;
+
+    if words.len() < PARALLEL_THRESHOLD {
+        return decode_chunk(words, data, &to_argb);
+    }
+    data.par_chunks_mut(PARALLEL_CHUNK * 4)
+        .zip(words.par_chunks(PARALLEL_CHUNK))
+        .for_each(|(data, words)| decode_chunk(words, data, &to_argb));
+}
+
+/// Decode words into RGBA bytes.
+#[cfg(not(feature = "parallel"))]
+fn decode_words<F: Fn(u32) -> [u8; 4]>(words: &[u32], data: &mut [u8], to_argb: F) {
+    decode_chunk(words, data, &to_argb)
+}
 
-    Ok(rgba_image)
+/// Decode words into RGBA bytes, where `to_argb` gives the `[alpha, red, green, blue]` bytes of a
+/// word.
+fn decode_chunk<F: Fn(u32) -> [u8; 4]>(words: &[u32], data: &mut [u8], to_argb: &F) {
+    let mut word_lanes = words.chunks_exact(LANES);
+    let mut data_lanes = data.chunks_exact_mut(LANES * 4);
+    for (words, data) in (&mut word_lanes).zip(&mut data_lanes) {
+        if words.iter().all(|&word| to_argb(word)[0] == 255) {
+            // Opaque pixels need no lookups, only a byte shuffle, so this loop is vectorised
+            for (pixel, &word) in data.chunks_exact_mut(4).zip(words) {
+                let argb = u32::from_be_bytes(to_argb(word));
+                pixel.copy_from_slice(&argb.rotate_left(8).to_be_bytes());
+            }
+        } else {
+            for (pixel, &word) in data.chunks_exact_mut(4).zip(words) {
+                let [alpha, red, green, blue] = to_argb(word);
+                pixel.copy_from_slice(&unpremultiply(alpha, red, green, blue));
+            }
+        }
+    }
+    let remainder = word_lanes.remainder();
+    for (pixel, &word) in data_lanes.into_remainder().chunks_exact_mut(4).zip(remainder) {
+        let [alpha, red, green, blue] = to_argb(word);
+        pixel.copy_from_slice(&unpremultiply(alpha, red, green, blue));
+    }
+}
+
+/// Un-premultiply a buffer of pre-multiplied ARGB words, as the one obtained from
+/// `read_region_into`, in place. The words stay in ARGB order.
+pub fn unpremultiply_in_place(buffer: &mut [u32]) {
+    for words in buffer.chunks_mut(LANES) {
+        // Opaque pixels are already un-premultiplied
+        if words.iter().all(|&word| word >= 0xff00_0000) {
+            continue;
+        }
+        for word in words.iter_mut() {
+            let [red, green, blue, alpha] = unpremultiply_word(*word);
+            *word = u32::from_be_bytes([alpha, red, green, blue]);
+        }
+    }
+}
+
+/// Un-premultiply a buffer of pre-multiplied ARGB words in place, and reorder each word such that
+/// its bytes in memory are `[red, green, blue, alpha]`, as in the raw data of an `RgbaImage`.
+pub fn argb_to_rgba_in_place(buffer: &mut [u32]) {
+    for words in buffer.chunks_mut(LANES) {
+        if words.iter().all(|&word| word >= 0xff00_0000) {
+            for word in words.iter_mut() {
+                *word = u32::from_ne_bytes(word.rotate_left(8).to_be_bytes());
+            }
+        } else {
+            for word in words.iter_mut() {
+                *word = u32::from_ne_bytes(unpremultiply_word(*word));
+            }
+        }
+    }
+}
+
+/// Un-premultiply one pre-multiplied ARGB word into `[red, green, blue, alpha]`.
+fn unpremultiply_word(word: u32) -> [u8; 4] {
+    let [alpha, red, green, blue] = word.to_be_bytes();
+    unpremultiply(alpha, red, green, blue)
+}
+
+/// Un-premultiply the color channels of one pixel, rounding to the nearest value, and return
+/// `[red, green, blue, alpha]`.
+#[inline]
+fn unpremultiply(alpha: u8, red: u8, green: u8, blue: u8) -> [u8; 4] {
+    let table = &UNPREMULTIPLY[alpha as usize];
+    [
+        table[red as usize],
+        table[green as usize],
+        table[blue as usize],
+        alpha,
+    ]
+}
+
+/// Quote and escape a string for JSON.
+pub(crate) fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for character in value.chars() {
+        match character {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            character if (character as u32) < 0x20 => {
+                quoted.push_str(&format!("\\u{:04x}", character as u32))
+            }
+            character => quoted.push(character),
+        }
+    }
+    quoted.push('"');
+    quoted
 }
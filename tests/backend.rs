@@ -0,0 +1,254 @@
+//! Integration tests of the OpenSlide front-end over a custom backend
+//!
+
+extern crate image;
+extern crate openslide;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use openslide::{OpenSlide, OpenSlideError, SlideBackend};
+
+/// A test double with two levels, where every pixel is opaque and has the value of its level 0
+/// column in the red channel, and its level 0 row in the green channel.
+struct Gradient;
+
+const LEVELS: [(u64, u64, f64); 2] = [(200, 100, 1.0), (50, 25, 4.0)];
+
+impl SlideBackend for Gradient {
+    fn level_count(&self) -> Result<u32, OpenSlideError> {
+        Ok(LEVELS.len() as u32)
+    }
+
+    fn level_dimensions(&self, level: u32) -> Result<(u64, u64), OpenSlideError> {
+        let (width, height, _) = LEVELS[level as usize];
+        Ok((width, height))
+    }
+
+    fn level_downsample(&self, level: u32) -> Result<f64, OpenSlideError> {
+        Ok(LEVELS[level as usize].2)
+    }
+
+    fn read_region(
+        &self,
+        dest: &mut [u32],
+        x: i64,
+        y: i64,
+        level: u32,
+        width: u64,
+        height: u64,
+    ) -> Result<(), OpenSlideError> {
+        let downsample = LEVELS[level as usize].2;
+        for row in 0..height {
+            for col in 0..width {
+                let red = (x as f64 + col as f64 * downsample) as u32 & 0xff;
+                let green = (y as f64 + row as f64 * downsample) as u32 & 0xff;
+                dest[(row * width + col) as usize] = 0xff00_0000 | red << 16 | green << 8;
+            }
+        }
+        Ok(())
+    }
+
+    fn properties(&self) -> Result<HashMap<String, String>, OpenSlideError> {
+        let mut properties = HashMap::new();
+        properties.insert(String::from("openslide.vendor"), String::from("gradient"));
+        properties.insert(String::from("openslide.mpp-x"), String::from("0.5"));
+        Ok(properties)
+    }
+
+    fn associated_image_names(&self) -> Result<Vec<String>, OpenSlideError> {
+        Ok(vec![String::from("label")])
+    }
+
+    fn associated_image_dimensions(&self, name: &str) -> Result<(u64, u64), OpenSlideError> {
+        match name {
+            "label" => Ok((3, 2)),
+            _ => Err(OpenSlideError::InvalidArgument(format!("No associated image {}", name))),
+        }
+    }
+
+    fn read_associated_image(&self, name: &str, dest: &mut [u32]) -> Result<(), OpenSlideError> {
+        self.associated_image_dimensions(name)?;
+        for pixel in dest.iter_mut().take(6) {
+            *pixel = 0xff00_00ff;
+        }
+        Ok(())
+    }
+}
+
+/// Wraps `Gradient`, and counts the calls for the levels of the slide.
+struct Counting {
+    level_calls: Arc<AtomicUsize>,
+}
+
+impl Counting {
+    fn count(&self) {
+        self.level_calls.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+impl SlideBackend for Counting {
+    fn level_count(&self) -> Result<u32, OpenSlideError> {
+        self.count();
+        Gradient.level_count()
+    }
+
+    fn level_dimensions(&self, level: u32) -> Result<(u64, u64), OpenSlideError> {
+        self.count();
+        Gradient.level_dimensions(level)
+    }
+
+    fn level_downsample(&self, level: u32) -> Result<f64, OpenSlideError> {
+        self.count();
+        Gradient.level_downsample(level)
+    }
+
+    fn read_region(
+        &self,
+        dest: &mut [u32],
+        x: i64,
+        y: i64,
+        level: u32,
+        width: u64,
+        height: u64,
+    ) -> Result<(), OpenSlideError> {
+        Gradient.read_region(dest, x, y, level, width, height)
+    }
+
+    fn properties(&self) -> Result<HashMap<String, String>, OpenSlideError> {
+        let mut properties = Gradient.properties()?;
+        properties.insert(String::from("openslide.level[1].tile-width"), String::from("16"));
+        properties.insert(String::from("openslide.bounds-x"), String::from("10"));
+        properties.insert(String::from("openslide.bounds-y"), String::from("-5"));
+        properties.insert(String::from("openslide.bounds-width"), String::from("150"));
+        properties.insert(String::from("openslide.bounds-height"), String::from("80"));
+        Ok(properties)
+    }
+
+    fn associated_image_names(&self) -> Result<Vec<String>, OpenSlideError> {
+        Gradient.associated_image_names()
+    }
+
+    fn associated_image_dimensions(&self, name: &str) -> Result<(u64, u64), OpenSlideError> {
+        Gradient.associated_image_dimensions(name)
+    }
+
+    fn read_associated_image(&self, name: &str, dest: &mut [u32]) -> Result<(), OpenSlideError> {
+        Gradient.read_associated_image(name, dest)
+    }
+}
+
+fn get_slide() -> OpenSlide {
+    OpenSlide::from_backend(Gradient)
+        .unwrap_or_else(|msg| panic!("Unable to create slide:\n{}", msg))
+}
+
+#[test]
+fn test_levels() {
+    let slide = get_slide();
+    assert_eq!(slide.get_level_count(), Ok(2));
+    assert_eq!(slide.get_level0_dimensions(), Ok((200, 100)));
+    assert_eq!(slide.get_level_dimensions(1u32), Ok((50, 25)));
+    assert_eq!(slide.get_level_downsample(1u32), Ok(4.0));
+}
+
+#[test]
+fn test_invalid_level() {
+    let slide = get_slide();
+    assert_eq!(
+        slide.get_level_dimensions(2u32),
+        Err(OpenSlideError::InvalidLevel {
+            level: 2,
+            level_count: 2
+        })
+    );
+    assert_eq!(
+        slide.get_level_dimensions(-1),
+        Err(OpenSlideError::InvalidLevel {
+            level: -1,
+            level_count: 2
+        })
+    );
+}
+
+#[test]
+fn test_default_best_level_for_downsample() {
+    let slide = get_slide();
+    assert_eq!(slide.get_best_level_for_downsample(0.5), Ok(0));
+    assert_eq!(slide.get_best_level_for_downsample(3.9), Ok(0));
+    assert_eq!(slide.get_best_level_for_downsample(4.0), Ok(1));
+    assert_eq!(slide.get_best_level_for_downsample(100.0), Ok(1));
+}
+
+#[test]
+fn test_read_region() {
+    let slide = get_slide();
+    let region = slide
+        .read_region(10u32, 20u32, 1u32, 2u32, 3u32)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    assert_eq!(region.dimensions(), (3, 2));
+    assert_eq!(region.get_pixel(0, 0).data, [20, 10, 0, 255]);
+    assert_eq!(region.get_pixel(2, 1).data, [28, 14, 0, 255]);
+}
+
+#[test]
+fn test_properties() {
+    let slide = get_slide();
+    assert_eq!(slide.properties.vendor(), Some(String::from("gradient")));
+    assert_eq!(slide.properties.mpp_x(), Some(0.5));
+    assert_eq!(slide.get_properties().map(|map| map.len()), Ok(2));
+}
+
+#[test]
+fn test_associated_image() {
+    let slide = get_slide();
+    assert_eq!(slide.associated_image_names(), Ok(vec![String::from("label")]));
+    let label = slide
+        .read_associated_image("label")
+        .unwrap_or_else(|msg| panic!("Read associated image error:\n{}", msg));
+    assert_eq!(label.dimensions(), (3, 2));
+    assert_eq!(label.get_pixel(1, 1).data, [0, 0, 255, 255]);
+}
+
+#[test]
+fn test_unsupported_icc_profile() {
+    let slide = get_slide();
+    match slide.backend().icc_profile() {
+        Err(OpenSlideError::Unsupported(_)) => {}
+        other => panic!("Expected an unsupported error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_slide_info() {
+    let level_calls = Arc::new(AtomicUsize::new(0));
+    let slide = OpenSlide::from_backend(Counting {
+        level_calls: level_calls.clone(),
+    })
+    .unwrap_or_else(|msg| panic!("Unable to create slide:\n{}", msg));
+    let calls_on_open = level_calls.load(Ordering::SeqCst);
+
+    let info = slide.info();
+    assert_eq!(info.level_count(), 2);
+    assert_eq!(info.dimensions(), (200, 100));
+    assert_eq!(info.levels()[1].width, 50);
+    assert_eq!(info.levels()[1].tile_width, Some(16));
+    assert_eq!(info.levels()[1].tile_height, None);
+    assert_eq!(info.level(1).map(|level| level.downsample), Ok(4.0));
+    assert!(info.level(2).is_err());
+    assert_eq!(info.mpp_x(), Some(0.5));
+    assert_eq!(info.mpp_y(), None);
+    let bounds = info.bounds().expect("Missing bounds");
+    assert_eq!((bounds.origin.x, bounds.origin.y), (10, -5));
+    assert_eq!((bounds.size.width, bounds.size.height), (150, 80));
+
+    // Validation and geometry use the cached information in stead of the backend
+    assert_eq!(slide.get_level_count(), Ok(2));
+    assert_eq!(slide.get_level_dimensions(1u32), Ok((50, 25)));
+    assert_eq!(slide.get_level_downsample(1u32), Ok(4.0));
+    slide
+        .read_region(10u32, 20u32, 1u32, 2u32, 3u32)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    assert_eq!(level_calls.load(Ordering::SeqCst), calls_on_open);
+}
@@ -0,0 +1,57 @@
+//! Integration tests of the bindings module
+//!
+
+extern crate openslide;
+
+#[cfg(feature = "dynamic")]
+use std::path::Path;
+
+use openslide::{bindings, OpenSlideError};
+
+const FILENAME: &str = "assets/CMU-1-Small-Region.svs";
+
+#[test]
+fn test_detect_vendor() {
+    let value = bindings::detect_vendor(FILENAME)
+        .unwrap_or_else(|msg| panic!("Detect vendor error:\n{}", msg));
+    assert_eq!(value, Some(String::from("aperio")))
+}
+
+#[test]
+fn test_detect_vendor_unknown_file() {
+    let value = bindings::detect_vendor("Cargo.toml")
+        .unwrap_or_else(|msg| panic!("Detect vendor error:\n{}", msg));
+    assert_eq!(value, None)
+}
+
+#[test]
+fn test_open_unknown_file() {
+    match bindings::open("Cargo.toml") {
+        Err(OpenSlideError::UnsupportedFormat(_)) => {},
+        Err(msg) => panic!("Wrong error for unsupported format:\n{}", msg),
+        Ok(_) => panic!("Opening a non-slide file should fail"),
+    }
+}
+
+#[test]
+fn test_property_value() {
+    let osr = bindings::open(FILENAME).unwrap_or_else(|msg| panic!("Open error:\n{}", msg));
+    let (vendor, missing) = unsafe {
+        let vendor = bindings::get_property_value(osr, "openslide.vendor");
+        let missing = bindings::get_property_value(osr, "no.such.property");
+        bindings::close(osr);
+        (vendor, missing)
+    };
+    assert_eq!(vendor, Ok(Some(String::from("aperio"))));
+    assert_eq!(missing, Ok(None));
+}
+
+#[cfg(feature = "dynamic")]
+#[test]
+fn test_load_missing_library() {
+    match bindings::load_library(Path::new("/nonexisting/libopenslide.so")) {
+        Err(OpenSlideError::LibraryNotLoaded(_)) => {},
+        Err(msg) => panic!("Wrong error for missing library:\n{}", msg),
+        Ok(_) => panic!("Loading a nonexisting library should fail"),
+    }
+}
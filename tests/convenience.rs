@@ -1,14 +1,20 @@
 //! Integration tests of the convenience module
 //!
 
+// The original tests fail with `assert!(false, ...)` and borrow paths that are already references
+#![allow(non_fmt_panics, clippy::assertions_on_constants, clippy::needless_borrow)]
+#![allow(clippy::legacy_numeric_constants)]
+
 extern crate openslide;
-extern crate failure;
 extern crate float_cmp;
 extern crate image;
 
 use std::path::Path;
+use std::sync::Arc;
+use std::thread;
 use float_cmp::ApproxEq;
 use image::{RgbaImage};
+use openslide::OpenSlideError;
 
 fn get_slide() -> openslide::OpenSlide {
     let filename = Path::new("assets/CMU-1-Small-Region.svs");
@@ -108,3 +114,122 @@ fn test_read_region() {
                           254, 245, 241, 255, 246, 246, 246, 255];
     assert_eq!(true_value, value.into_vec())
 }
+
+#[test]
+fn test_associated_image_names() {
+    let slide = get_slide();
+    let mut value = match slide.associated_image_names() {
+        Ok(val) => val,
+        Err(msg) => {
+            assert!(false, format!("Associated image names error:\n{}", msg));
+            Vec::new() // For compilation
+        },
+    };
+    value.sort();
+    assert_eq!(value, vec!["label", "macro", "thumbnail"])
+}
+
+#[test]
+fn test_read_associated_image() {
+    let slide = get_slide();
+    let value = match slide.read_associated_image("label") {
+        Ok(val) => val,
+        Err(msg) => {
+            assert!(false, format!("Read associated image error:\n{}", msg));
+            RgbaImage::new(0, 0) // For compilation
+        },
+    };
+    assert_eq!(value.dimensions(), (387, 463))
+}
+
+#[test]
+fn test_invalid_path() {
+    match openslide::OpenSlide::new(Path::new("assets/does-not-exist.svs")) {
+        Err(OpenSlideError::InvalidPath(_)) => {},
+        Err(msg) => panic!("Wrong error for invalid path:\n{}", msg),
+        Ok(_) => panic!("Opening a nonexisting path should fail"),
+    }
+}
+
+#[test]
+fn test_unsupported_format() {
+    match openslide::OpenSlide::new(Path::new("Cargo.toml")) {
+        Err(OpenSlideError::UnsupportedFormat(_)) => {},
+        Err(msg) => panic!("Wrong error for unsupported format:\n{}", msg),
+        Ok(_) => panic!("Opening a non-slide file should fail"),
+    }
+}
+
+#[test]
+fn test_invalid_level() {
+    let slide = get_slide();
+    match slide.get_level_dimensions(1u8) {
+        Err(OpenSlideError::InvalidLevel { level: 1, level_count: 1 }) => {},
+        Err(msg) => panic!("Wrong error for invalid level:\n{}", msg),
+        Ok(_) => panic!("Level 1 should not exist"),
+    }
+}
+
+#[test]
+fn test_clone_shares_handle() {
+    let slide = get_slide();
+    let clone = slide.clone();
+    drop(slide);
+    let value = clone.get_level0_dimensions()
+        .unwrap_or_else(|msg| panic!("Level 0 dimension error after drop of original:\n{}", msg));
+    assert_eq!(value, (2220, 2967))
+}
+
+#[test]
+fn test_read_region_from_threads() {
+    let slide = Arc::new(get_slide());
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let slide = Arc::clone(&slide);
+            thread::spawn(move || {
+                slide.read_region(1510u32, 1510u32, 0u32, 4u32, 4u32)
+                    .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg))
+                    .into_vec()
+            })
+        })
+        .collect();
+    let expected = slide.read_region(1510u32, 1510u32, 0u32, 4u32, 4u32)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg))
+        .into_vec();
+    for handle in handles {
+        assert_eq!(expected, handle.join().unwrap())
+    }
+}
+
+#[test]
+fn test_version() {
+    let value = openslide::OpenSlide::version()
+        .unwrap_or_else(|msg| panic!("Version error:\n{}", msg));
+    assert!(value.starts_with("3.") || value.starts_with("4."))
+}
+
+#[cfg(openslide4)]
+#[test]
+fn test_icc_profile() {
+    let slide = get_slide();
+    let value = slide.icc_profile()
+        .unwrap_or_else(|msg| panic!("ICC profile error:\n{}", msg));
+    assert_eq!(value, None)
+}
+
+#[cfg(openslide4)]
+#[test]
+fn test_shared_tile_cache() {
+    let cache = openslide::TileCache::new(32 * 1024 * 1024)
+        .unwrap_or_else(|msg| panic!("Tile cache error:\n{}", msg));
+    let first = get_slide();
+    let second = get_slide();
+    first.set_cache(&cache).unwrap_or_else(|msg| panic!("Set cache error:\n{}", msg));
+    second.set_cache(&cache).unwrap_or_else(|msg| panic!("Set cache error:\n{}", msg));
+    drop(cache);
+    let value = first.read_region(1510u32, 1510u32, 0u32, 4u32, 4u32)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    let expected = second.read_region(1510u32, 1510u32, 0u32, 4u32, 4u32)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    assert_eq!(expected.into_vec(), value.into_vec())
+}
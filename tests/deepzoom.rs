@@ -0,0 +1,184 @@
+//! Tests of the Deep Zoom generator, with the in-memory backend
+//!
+//! The expected geometry is that of the generator in openslide-python for the same slides.
+//!
+
+extern crate openslide;
+
+use openslide::backend::MemoryBackend;
+use openslide::deepzoom::{DeepZoomGenerator, TileCoordinates, TileFormat};
+use openslide::geometry::{Level, Point, Size};
+use openslide::{OpenSlide, OpenSlideError};
+
+/// A slide of 300 x 250 pixels, with a level of 150 x 125 pixels.
+fn get_slide(bounds: bool) -> OpenSlide {
+    let mut backend = MemoryBackend::generated(300, 250, &[1, 2])
+        .unwrap_or_else(|msg| panic!("Unable to create backend:\n{}", msg));
+    if bounds {
+        backend = backend
+            .with_property("openslide.bounds-x", "10")
+            .with_property("openslide.bounds-y", "20")
+            .with_property("openslide.bounds-width", "200")
+            .with_property("openslide.bounds-height", "150");
+    }
+    OpenSlide::from_backend(backend)
+        .unwrap_or_else(|msg| panic!("Unable to create slide:\n{}", msg))
+}
+
+fn get_generator(
+    slide: &OpenSlide,
+    tile_size: u64,
+    overlap: u64,
+    limit_bounds: bool,
+) -> DeepZoomGenerator {
+    DeepZoomGenerator::new(slide, tile_size, overlap, limit_bounds)
+        .unwrap_or_else(|msg| panic!("Generator error:\n{}", msg))
+}
+
+fn coordinates(x: i64, y: i64, level: u32, width: u64, height: u64) -> TileCoordinates {
+    TileCoordinates {
+        origin: Point::new(x, y),
+        level: Level(level),
+        size: Size::new(width, height),
+    }
+}
+
+#[test]
+fn test_geometry() {
+    let slide = get_slide(false);
+    let generator = get_generator(&slide, 254, 1, false);
+    assert_eq!(generator.level_count(), 10);
+    assert_eq!(
+        generator.level_dimensions(),
+        &[
+            (1, 1),
+            (2, 1),
+            (3, 2),
+            (5, 4),
+            (10, 8),
+            (19, 16),
+            (38, 32),
+            (75, 63),
+            (150, 125),
+            (300, 250)
+        ][..]
+    );
+    let mut level_tiles = vec![(1, 1); 9];
+    level_tiles.push((2, 1));
+    assert_eq!(generator.level_tiles(), &level_tiles[..]);
+    assert_eq!(generator.tile_count(), 11);
+
+    assert_eq!(
+        generator.tile_coordinates(9, 1, 0),
+        Ok(coordinates(253, 0, 0, 47, 250))
+    );
+    assert_eq!(generator.tile_dimensions(9, 1, 0), Ok((47, 250)));
+    assert_eq!(
+        generator.tile_coordinates(9, 0, 0),
+        Ok(coordinates(0, 0, 0, 255, 250))
+    );
+    assert_eq!(
+        generator.tile_coordinates(8, 0, 0),
+        Ok(coordinates(0, 0, 1, 150, 125))
+    );
+
+    match generator.tile_coordinates(10, 0, 0) {
+        Err(OpenSlideError::InvalidArgument(_)) => {}
+        other => panic!("Expected an invalid level, got {:?}", other),
+    }
+    match generator.tile_coordinates(9, 2, 0) {
+        Err(OpenSlideError::InvalidArgument(_)) => {}
+        other => panic!("Expected an invalid address, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_limit_bounds() {
+    let slide = get_slide(true);
+    let generator = get_generator(&slide, 254, 1, false);
+    assert_eq!(generator.level_dimensions().last(), Some(&(300, 250)));
+
+    let generator = get_generator(&slide, 64, 2, true);
+    assert_eq!(generator.level_count(), 9);
+    assert_eq!(generator.level_dimensions().last(), Some(&(200, 150)));
+    assert_eq!(generator.level_tiles()[7..], [(2, 2), (4, 3)]);
+    assert_eq!(generator.tile_count(), 23);
+
+    assert_eq!(
+        generator.tile_coordinates(8, 0, 0),
+        Ok(coordinates(10, 20, 0, 66, 66))
+    );
+    assert_eq!(
+        generator.tile_coordinates(8, 3, 2),
+        Ok(coordinates(200, 146, 0, 10, 24))
+    );
+    assert_eq!(
+        generator.tile_coordinates(7, 1, 1),
+        Ok(coordinates(134, 144, 1, 38, 13))
+    );
+    assert_eq!(
+        generator.tile_coordinates(6, 0, 0),
+        Ok(coordinates(10, 20, 1, 100, 75))
+    );
+    assert_eq!(generator.tile_dimensions(6, 0, 0), Ok((50, 38)));
+}
+
+#[test]
+fn test_limit_bounds_partial() {
+    // Only the origin of the bounds, such that the size is that of level 0
+    let backend = MemoryBackend::generated(300, 250, &[1, 2])
+        .unwrap_or_else(|msg| panic!("Unable to create backend:\n{}", msg))
+        .with_property("openslide.bounds-x", "10")
+        .with_property("openslide.bounds-y", "20");
+    let slide = OpenSlide::from_backend(backend)
+        .unwrap_or_else(|msg| panic!("Unable to create slide:\n{}", msg));
+
+    let generator = get_generator(&slide, 64, 2, true);
+    assert_eq!(generator.level_count(), 10);
+    assert_eq!(generator.level_dimensions().last(), Some(&(300, 250)));
+    assert_eq!(
+        generator.tile_coordinates(9, 0, 0),
+        Ok(coordinates(10, 20, 0, 66, 66))
+    );
+    assert_eq!(
+        generator.tile_coordinates(9, 4, 3),
+        Ok(coordinates(264, 210, 0, 46, 60))
+    );
+}
+
+#[test]
+fn test_tiles() {
+    let slide = get_slide(false);
+    let generator = get_generator(&slide, 254, 1, false);
+
+    let tile = generator.tile(9, 1, 0).expect("Tile error");
+    assert_eq!(tile.dimensions(), (47, 250));
+    // The tile starts at column 253 of level 0, which has the column in the red channel
+    assert_eq!(tile.get_pixel(0, 10).data, [253, 10, 255]);
+
+    // Scaled down from level 1
+    let tile = generator.tile(7, 0, 0).expect("Tile error");
+    assert_eq!(tile.dimensions(), (75, 63));
+
+    let jpeg = generator
+        .tile_bytes(9, 0, 0, TileFormat::Jpeg(75))
+        .expect("Tile error");
+    assert_eq!(jpeg[..2], [0xff, 0xd8]);
+    let png = generator
+        .tile_bytes(9, 0, 0, TileFormat::Png)
+        .expect("Tile error");
+    assert_eq!(png[..4], [0x89, b'P', b'N', b'G']);
+}
+
+#[test]
+fn test_dzi() {
+    let slide = get_slide(true);
+    let generator = get_generator(&slide, 254, 1, true);
+    assert_eq!(
+        generator.dzi(TileFormat::Jpeg(75)),
+        "<Image TileSize=\"254\" Overlap=\"1\" Format=\"jpeg\" \
+         xmlns=\"http://schemas.microsoft.com/deepzoom/2008\">\
+         <Size Width=\"200\" Height=\"150\" /></Image>"
+    );
+    assert!(generator.dzi(TileFormat::Png).contains("Format=\"png\""));
+}
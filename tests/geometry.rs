@@ -0,0 +1,82 @@
+//! Tests of the geometry types, with the in-memory backend
+//!
+
+extern crate openslide;
+
+use openslide::backend::MemoryBackend;
+use openslide::geometry::{Level, Level0, LevelN, Point, Rect, Size};
+use openslide::OpenSlide;
+
+fn get_slide() -> OpenSlide {
+    let backend = MemoryBackend::generated(1000, 600, &[1, 4, 16])
+        .unwrap_or_else(|msg| panic!("Unable to create backend:\n{}", msg));
+    OpenSlide::from_backend(backend)
+        .unwrap_or_else(|msg| panic!("Unable to create slide:\n{}", msg))
+}
+
+#[test]
+fn test_level_size() {
+    let slide = get_slide();
+    assert_eq!(slide.level_size(Level(0)), Ok(Size::new(1000, 600)));
+    assert_eq!(slide.level_size(Level(2)), Ok(Size::new(63, 38)));
+    assert!(slide.level_size(Level(3)).is_err());
+}
+
+#[test]
+fn test_conversions() {
+    let slide = get_slide();
+    let level = Level(1);
+
+    let point: Point<Level0> = Point::new(-3, 9);
+    assert_eq!(point.to_level(&slide, level), Ok(Point::new(-1, 2)));
+    let point: Point<LevelN> = Point::new(-1, 2);
+    assert_eq!(point.to_level0(&slide, level), Ok(Point::new(-4, 8)));
+
+    let size: Size<Level0> = Size::new(10, 8);
+    assert_eq!(size.to_level(&slide, level), Ok(Size::new(3, 2)));
+    let size: Size<LevelN> = Size::new(3, 2);
+    assert_eq!(size.to_level0(&slide, level), Ok(Size::new(12, 8)));
+
+    // The level rectangle covers the level 0 rectangle
+    let rect: Rect<Level0> = Rect::new(3, 5, 6, 4);
+    let level_rect = rect
+        .to_level(&slide, level)
+        .unwrap_or_else(|msg| panic!("Conversion error:\n{}", msg));
+    assert_eq!(level_rect, Rect::new(0, 1, 3, 2));
+    assert_eq!(level_rect.right(), 3);
+    assert_eq!(level_rect.bottom(), 3);
+
+    // The corners and the area do not overflow
+    let rect: Rect<LevelN> = Rect::new(i64::MAX, i64::MIN, u64::MAX, u64::MAX);
+    assert_eq!(rect.right(), i128::from(i64::MAX) + i128::from(u64::MAX));
+    assert_eq!(rect.bottom(), i128::from(u64::MAX) - (1 << 63));
+    assert_eq!(rect.size.area(), u128::from(u64::MAX) * u128::from(u64::MAX));
+    assert_eq!(
+        level_rect.to_level0(&slide, level),
+        Ok(Rect::new(0, 4, 12, 8))
+    );
+}
+
+#[test]
+fn test_read_rect() {
+    let slide = get_slide();
+    let expected = slide
+        .read_region(100u32, 300u32, 1u32, 20u32, 30u32)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg))
+        .into_raw();
+
+    let rect: Rect<LevelN> = Rect::new(75, 25, 30, 20);
+    let image = slide
+        .read_rect(Level(1), rect)
+        .unwrap_or_else(|msg| panic!("Read rect error:\n{}", msg));
+    assert_eq!(image.dimensions(), (30, 20));
+    assert_eq!(image.into_raw(), expected);
+
+    let rect: Rect<Level0> = Rect::new(300, 100, 120, 80);
+    let image = slide
+        .read_rect(Level(1), rect)
+        .unwrap_or_else(|msg| panic!("Read rect error:\n{}", msg));
+    assert_eq!(image.into_raw(), expected);
+
+    assert!(slide.read_rect(Level(3), rect).is_err());
+}
@@ -0,0 +1,196 @@
+//! Tests of the IIIF Image API, with the in-memory backend
+//!
+
+extern crate image;
+extern crate openslide;
+
+use openslide::backend::MemoryBackend;
+use openslide::geometry::{Rect, Size};
+use openslide::iiif::{
+    Format, ImageRequest, ImageService, ImageSize, Quality, Region, Rotation, SizeRequest,
+};
+use openslide::{OpenSlide, OpenSlideError};
+
+/// A slide of 1000 x 600 pixels, with levels of 250 x 150 and 63 x 38 pixels, and native tiles
+/// of 240 x 120 pixels.
+fn get_service() -> ImageService {
+    let backend = MemoryBackend::generated(1000, 600, &[1, 4, 16])
+        .unwrap_or_else(|msg| panic!("Unable to create backend:\n{}", msg))
+        .with_tile_size(240, 120);
+    let slide = OpenSlide::from_backend(backend)
+        .unwrap_or_else(|msg| panic!("Unable to create slide:\n{}", msg));
+    ImageService::new(&slide)
+}
+
+fn parse(request: &str) -> ImageRequest {
+    request
+        .parse()
+        .unwrap_or_else(|msg| panic!("Unable to parse {}:\n{}", request, msg))
+}
+
+fn render(service: &ImageService, request: &str) -> image::RgbImage {
+    let bytes = service
+        .render(&parse(request))
+        .unwrap_or_else(|msg| panic!("Unable to render {}:\n{}", request, msg));
+    image::load_from_memory(&bytes)
+        .expect("Could not decode image")
+        .to_rgb()
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(
+        parse("pct:10,20.5,30,40/^!200,100/!90/gray.png"),
+        ImageRequest {
+            region: Region::Percent {
+                x: 10.0,
+                y: 20.5,
+                width: 30.0,
+                height: 40.0
+            },
+            size: SizeRequest {
+                size: ImageSize::Confined(200, 100),
+                upscale: true
+            },
+            rotation: Rotation {
+                degrees: 90,
+                mirror: true
+            },
+            quality: Quality::Gray,
+            format: Format::Png,
+        }
+    );
+    let request = parse("0,10,20,30/max/360/default.jpg");
+    assert_eq!(
+        request.region,
+        Region::Pixels {
+            x: 0,
+            y: 10,
+            width: 20,
+            height: 30
+        }
+    );
+    assert_eq!(request.rotation.degrees, 0);
+    let sizes = [
+        ("100,", ImageSize::Width(100)),
+        (",100", ImageSize::Height(100)),
+        ("pct:50", ImageSize::Percent(50.0)),
+        ("100,50", ImageSize::Exact(100, 50)),
+    ];
+    for &(value, size) in &sizes {
+        assert_eq!(
+            value.parse::<SizeRequest>().map(|request| request.size),
+            Ok(size)
+        );
+    }
+
+    for request in &[
+        "full/max/0/default",
+        "full/max/0/default.gif",
+        "full/max/45/default.jpg",
+        "full/max/0/sepia.jpg",
+        "1,2,3/max/0/default.jpg",
+        "full/!100,/0/default.jpg",
+        "pct:-10,0,50,50/max/0/default.jpg",
+        "full/pct:-50/0/default.jpg",
+        "full/max/0",
+    ] {
+        match request.parse::<ImageRequest>() {
+            Err(OpenSlideError::InvalidArgument(_)) => {}
+            other => panic!(
+                "Expected an invalid request for {}, got {:?}",
+                request, other
+            ),
+        }
+    }
+}
+
+#[test]
+fn test_info() {
+    let service = get_service();
+    assert_eq!(service.scale_factors(), [1, 4, 16]);
+    let info = service.info_json("https://example.org/iiif/slide");
+    for expected in &[
+        "\"id\":\"https://example.org/iiif/slide\"",
+        "\"type\":\"ImageService3\"",
+        "\"width\":1000,\"height\":600",
+        "\"tiles\":[{\"width\":240,\"height\":120,\"scaleFactors\":[1,4,16]}]",
+        "\"sizes\":[{\"width\":63,\"height\":38},{\"width\":250,\"height\":150},\
+         {\"width\":1000,\"height\":600}]",
+    ] {
+        assert!(info.contains(expected), "{} is not in {}", expected, info);
+    }
+}
+
+#[test]
+fn test_resolve() {
+    let service = get_service().with_max_size(800, 800);
+    let resolve = |request: &str| service.resolve(&parse(request));
+    assert_eq!(
+        resolve("full/max/0/default.jpg"),
+        Ok((Rect::new(0, 0, 1000, 600), Size::new(800, 480)))
+    );
+    assert_eq!(
+        resolve("square/300,/0/default.jpg"),
+        Ok((Rect::new(200, 0, 600, 600), Size::new(300, 300)))
+    );
+    assert_eq!(
+        resolve("pct:10,10,50,50/pct:50/0/default.jpg"),
+        Ok((Rect::new(100, 60, 500, 300), Size::new(250, 150)))
+    );
+    assert_eq!(
+        resolve("900,500,500,500/,50/0/default.jpg"),
+        Ok((Rect::new(900, 500, 100, 100), Size::new(50, 50)))
+    );
+    assert_eq!(
+        resolve("0,0,400,100/!100,100/0/default.jpg"),
+        Ok((Rect::new(0, 0, 400, 100), Size::new(100, 25)))
+    );
+    assert_eq!(
+        resolve("0,0,40,10/^!100,100/0/default.jpg"),
+        Ok((Rect::new(0, 0, 40, 10), Size::new(100, 25)))
+    );
+    assert_eq!(
+        resolve("0,0,40,10/^max/0/default.jpg"),
+        Ok((Rect::new(0, 0, 40, 10), Size::new(800, 200)))
+    );
+    for request in &[
+        "1000,0,10,10/max/0/default.jpg",
+        "0,0,0,10/max/0/default.jpg",
+        "full/pct:0/0/default.jpg",
+        "full/900,/0/default.jpg",
+        "0,0,40,10/^1000,/0/default.jpg",
+        "0,0,40,10/100,5/0/default.jpg",
+    ] {
+        match resolve(request) {
+            Err(OpenSlideError::InvalidArgument(_)) => {}
+            other => panic!(
+                "Expected an invalid request for {}, got {:?}",
+                request, other
+            ),
+        }
+    }
+}
+
+#[test]
+fn test_render() {
+    let service = get_service();
+
+    // Level 0 pixels have the column in the red channel, and the row in the green channel
+    let image = render(&service, "0,0,20,10/max/0/default.png");
+    assert_eq!(image.dimensions(), (20, 10));
+    assert_eq!(image.get_pixel(3, 7).data, [3, 7, 0]);
+
+    let image = render(&service, "0,0,20,10/max/90/color.png");
+    assert_eq!(image.dimensions(), (10, 20));
+    assert_eq!(image.get_pixel(0, 0).data, [0, 9, 0]);
+    let image = render(&service, "0,0,20,10/max/!0/default.png");
+    assert_eq!(image.get_pixel(0, 0).data, [19, 0, 0]);
+
+    let image = render(&service, "0,64,20,10/max/0/bitonal.png");
+    assert!(image
+        .pixels()
+        .all(|pixel| pixel.data == [0, 0, 0] || pixel.data == [255, 255, 255]));
+    let image = render(&service, "full/250,/0/gray.jpg");
+    assert_eq!(image.dimensions(), (250, 150));
+}
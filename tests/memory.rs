@@ -0,0 +1,365 @@
+//! Integration tests of the in-memory backend
+//!
+
+extern crate image;
+extern crate openslide;
+
+use image::{Rgba, RgbaImage};
+use openslide::backend::{MemoryBackend, SlideBackend};
+use openslide::utils::PixelFormat;
+use openslide::{BoundsPolicy, OpenSlide, OpenSlideError};
+
+fn get_slide() -> OpenSlide {
+    let backend = MemoryBackend::generated(1000, 600, &[1, 4, 16])
+        .unwrap_or_else(|msg| panic!("Unable to create backend:\n{}", msg))
+        .with_vendor("synthetic")
+        .with_mpp(0.25, 0.5)
+        .with_tile_size(240, 120)
+        .with_property("aperio.AppMag", "20");
+    OpenSlide::from_backend(backend)
+        .unwrap_or_else(|msg| panic!("Unable to create slide:\n{}", msg))
+}
+
+#[test]
+fn test_levels() {
+    let slide = get_slide();
+    assert_eq!(slide.get_level_count(), Ok(3));
+    assert_eq!(slide.get_level0_dimensions(), Ok((1000, 600)));
+    assert_eq!(slide.get_level_dimensions(1u32), Ok((250, 150)));
+    assert_eq!(slide.get_level_dimensions(2u32), Ok((63, 38)));
+    assert_eq!(slide.get_level_downsample(2u32), Ok(16.0));
+    assert_eq!(slide.get_best_level_for_downsample(8.0), Ok(1));
+}
+
+#[test]
+fn test_properties() {
+    let slide = get_slide();
+    assert_eq!(slide.properties.vendor(), Some(String::from("synthetic")));
+    assert_eq!(slide.properties.mpp_x(), Some(0.25));
+    assert_eq!(slide.properties.mpp_y(), Some(0.5));
+    assert_eq!(slide.properties.level_count(), Some(3));
+    assert_eq!(slide.properties.app_mag(), Some(20));
+
+    let levels = slide.properties.levels().expect("Missing level properties");
+    assert_eq!(levels.len(), 3);
+    assert_eq!(levels[1].width(), Some(250));
+    assert_eq!(levels[1].downsample(), Some(4.0));
+    assert_eq!(levels[2].tile_width(), Some(240));
+    assert_eq!(levels[2].tile_height(), Some(120));
+}
+
+#[test]
+fn test_original_dimensions() {
+    let backend = MemoryBackend::generated(16, 16, &[1])
+        .unwrap_or_else(|msg| panic!("Unable to create backend:\n{}", msg))
+        .with_property("aperio.OriginalWidth", "46000")
+        .with_property("aperio.OriginalHeight", "32914");
+    let slide = OpenSlide::from_backend(backend)
+        .unwrap_or_else(|msg| panic!("Unable to create slide:\n{}", msg));
+    assert_eq!(slide.properties.original_width(), Some(46000));
+    assert_eq!(slide.properties.original_height(), Some(32914));
+}
+
+#[test]
+fn test_read_region_level0() {
+    let slide = get_slide();
+    let region = slide
+        .read_region(100u32, 300u32, 0u32, 2u32, 2u32)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    // Column 300 is in the sixth checkerboard column, row 100 in the second row
+    assert_eq!(region.get_pixel(0, 0).data, [44, 100, 255, 255]);
+    assert_eq!(region.get_pixel(1, 1).data, [45, 101, 255, 255]);
+}
+
+#[test]
+fn test_read_region_downsampled() {
+    let image = RgbaImage::from_fn(4, 2, |col, _| {
+        if col % 2 == 0 {
+            Rgba([0, 0, 0, 255])
+        } else {
+            Rgba([200, 100, 50, 255])
+        }
+    });
+    let backend = MemoryBackend::new(&image, &[1, 2])
+        .unwrap_or_else(|msg| panic!("Unable to create backend:\n{}", msg));
+    let slide = OpenSlide::from_backend(backend)
+        .unwrap_or_else(|msg| panic!("Unable to create slide:\n{}", msg));
+
+    let region = slide
+        .read_region(0u32, 0u32, 1u32, 1u32, 2u32)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    assert_eq!(region.get_pixel(0, 0).data, [100, 50, 25, 255]);
+    assert_eq!(region.get_pixel(1, 0).data, [100, 50, 25, 255]);
+}
+
+#[test]
+fn test_associated_image() {
+    let label = RgbaImage::from_pixel(30, 20, Rgba([10, 20, 30, 255]));
+    let backend = MemoryBackend::generated(64, 64, &[1])
+        .unwrap_or_else(|msg| panic!("Unable to create backend:\n{}", msg))
+        .with_associated_image("label", &label);
+    let slide = OpenSlide::from_backend(backend)
+        .unwrap_or_else(|msg| panic!("Unable to create slide:\n{}", msg));
+
+    assert_eq!(slide.associated_image_names(), Ok(vec![String::from("label")]));
+    assert_eq!(slide.associated_image_dimensions("label"), Ok((30, 20)));
+    let image = slide
+        .read_associated_image("label")
+        .unwrap_or_else(|msg| panic!("Read associated image error:\n{}", msg));
+    assert_eq!(image.get_pixel(29, 19).data, [10, 20, 30, 255]);
+    assert!(slide.read_associated_image("macro").is_err());
+}
+
+#[test]
+fn test_invalid_downsamples() {
+    let image = RgbaImage::new(8, 8);
+    match MemoryBackend::new(&image, &[2, 4]) {
+        Err(OpenSlideError::InvalidArgument(_)) => {}
+        Err(err) => panic!("Wrong error for invalid downsamples:\n{}", err),
+        Ok(_) => panic!("Downsamples not starting at 1 were accepted"),
+    }
+    match MemoryBackend::new(&image, &[1, 4, 4]) {
+        Err(OpenSlideError::InvalidArgument(_)) => {}
+        Err(err) => panic!("Wrong error for invalid downsamples:\n{}", err),
+        Ok(_) => panic!("Downsamples that are not increasing were accepted"),
+    }
+}
+
+#[test]
+fn test_region_too_large() {
+    let backend = MemoryBackend::generated(16, 16, &[1])
+        .unwrap_or_else(|msg| panic!("Unable to create backend:\n{}", msg));
+    let mut dest = vec![0; 16];
+    match backend.read_region(&mut dest, 0, 0, 0, u64::MAX, 2) {
+        Err(OpenSlideError::InvalidArgument(_)) => {}
+        Err(err) => panic!("Wrong error for a region that is too large:\n{}", err),
+        Ok(_) => panic!("A region of more than u64::MAX pixels was read"),
+    }
+}
+
+#[test]
+fn test_read_region_into() {
+    let slide = get_slide();
+    let expected = slide
+        .read_region(100u32, 300u32, 0u32, 3u32, 5u32)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+
+    // Reuse one buffer, larger than the region, for several reads
+    let mut buffer = vec![0u32; 20];
+    for _ in 0..2 {
+        slide
+            .read_region_into(&mut buffer, 100u32, 300u32, 0u32, 3u32, 5u32)
+            .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+        assert_eq!(buffer[0], 0xff2c_64ff);
+        let mut image = RgbaImage::new(5, 3);
+        for (pixel, &word) in image.pixels_mut().zip(&buffer) {
+            let [alpha, red, green, blue] = word.to_be_bytes();
+            *pixel = Rgba([red, green, blue, alpha]);
+        }
+        assert_eq!(image.into_raw(), expected.clone().into_raw());
+    }
+
+    match slide.read_region_into(&mut buffer, 0u32, 0u32, 0u32, 5u32, 5u32) {
+        Err(OpenSlideError::InvalidArgument(_)) => {}
+        Err(err) => panic!("Wrong error for a small buffer:\n{}", err),
+        Ok(_) => panic!("A buffer that is too small was accepted"),
+    }
+    assert!(slide
+        .read_region_into(&mut buffer, 0u32, 0u32, 3u32, 1u32, 1u32)
+        .is_err());
+}
+
+#[test]
+fn test_read_region_into_image() {
+    let slide = get_slide();
+    let mut image = RgbaImage::new(16, 8);
+    for &(row, col, level) in &[(100u32, 300u32, 0u32), (40, 80, 1), (0, 0, 2)] {
+        slide
+            .read_region_into_image(&mut image, row, col, level)
+            .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+        let expected = slide
+            .read_region(row, col, level, 8, 16)
+            .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+        assert_eq!(image.clone().into_raw(), expected.into_raw());
+    }
+
+    // Pixels outside of the slide are transparent
+    slide
+        .read_region_into_image(&mut image, 596u32, 992u32, 0u32)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    assert_eq!(image.get_pixel(7, 3).data[3], 255);
+    assert_eq!(image.get_pixel(8, 3).data, [0, 0, 0, 0]);
+    assert_eq!(image.get_pixel(7, 4).data, [0, 0, 0, 0]);
+}
+
+#[test]
+fn test_unpremultiply_in_place() {
+    let mut buffer = [0xff10_2030, 0x8040_2000, 0x0000_0000, 0x4040_4040];
+    openslide::utils::unpremultiply_in_place(&mut buffer);
+    assert_eq!(buffer, [0xff10_2030, 0x8080_4000, 0x0000_0000, 0x40ff_ffff]);
+}
+
+#[test]
+fn test_read_region_as() {
+    let slide = get_slide();
+    let image = slide
+        .read_region(590u32, 990u32, 0u32, 20u32, 20u32)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    let region = slide
+        .read_region_as(590u32, 990u32, 0u32, 20u32, 20u32, PixelFormat::Rgb8)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+
+    // The region is clipped to the slide, just as with read_region
+    assert_eq!((region.width, region.height), image.dimensions());
+    let rgb = image
+        .pixels()
+        .flat_map(|pixel| pixel.data[..3].to_vec())
+        .collect::<Vec<u8>>();
+    assert_eq!(region.as_u8(), Some(&rgb[..]));
+
+    let planar = slide
+        .read_region_as(0u32, 0u32, 1u32, 2u32, 3u32, PixelFormat::PlanarRgbF32)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    let samples = planar.as_f32().expect("Missing float samples");
+    assert_eq!(samples.len(), 3 * 2 * 3);
+    // Red is the average column of each 4 x 4 block, and blue is 0 in the top left checkerboard
+    // square
+    assert_eq!(samples[..3], [2.0 / 255.0, 6.0 / 255.0, 10.0 / 255.0]);
+    assert_eq!(samples[6..9], [2.0 / 255.0, 2.0 / 255.0, 2.0 / 255.0]);
+    assert!(samples[12..].iter().all(|&blue| blue == 0.0));
+}
+
+/// A slide of 8 x 4 pixels, where the right half is transparent and the bottom left quarter is
+/// half transparent.
+fn get_partly_transparent_slide(background_color: Option<&str>) -> Result<OpenSlide, OpenSlideError> {
+    let image = RgbaImage::from_fn(8, 4, |col, row| match (col < 4, row < 2) {
+        (true, true) => Rgba([200, 100, 50, 255]),
+        (true, false) => Rgba([200, 100, 50, 128]),
+        _ => Rgba([0, 0, 0, 0]),
+    });
+    let mut backend = MemoryBackend::new(&image, &[1])?;
+    if let Some(color) = background_color {
+        backend = backend.with_property("openslide.background-color", color);
+    }
+    OpenSlide::from_backend(backend)
+}
+
+#[test]
+fn test_background_color() {
+    let slide = get_partly_transparent_slide(Some("F0e6Dc"))
+        .unwrap_or_else(|msg| panic!("Unable to create slide:\n{}", msg));
+    assert_eq!(slide.properties.background_color(), Some([240, 230, 220]));
+
+    let slide = get_partly_transparent_slide(None)
+        .unwrap_or_else(|msg| panic!("Unable to create slide:\n{}", msg));
+    assert_eq!(slide.properties.background_color(), None);
+
+    for invalid in &["FFF", "GGGGGG", "#FFFFFF", "FFFFFFF"] {
+        match get_partly_transparent_slide(Some(invalid)) {
+            Err(OpenSlideError::PropertyParse { .. }) => {}
+            Err(err) => panic!("Wrong error for background color {}:\n{}", invalid, err),
+            Ok(_) => panic!("Invalid background color {} was accepted", invalid),
+        }
+    }
+}
+
+#[test]
+fn test_read_region_composited() {
+    let slide = get_partly_transparent_slide(Some("F0E6DC"))
+        .unwrap_or_else(|msg| panic!("Unable to create slide:\n{}", msg));
+    let image = slide
+        .read_region_composited(0u32, 0u32, 0u32, 4u32, 8u32, None)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    assert_eq!(image.dimensions(), (8, 4));
+    assert_eq!(image.get_pixel(0, 0).data, [200, 100, 50]);
+    assert_eq!(image.get_pixel(7, 0).data, [240, 230, 220]);
+    // Half of the color, and half of the background
+    assert_eq!(image.get_pixel(0, 3).data, [220, 165, 135]);
+
+    let image = slide
+        .read_region_composited(0u32, 0u32, 0u32, 4u32, 8u32, Some([0, 0, 255]))
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    assert_eq!(image.get_pixel(7, 3).data, [0, 0, 255]);
+
+    // White if the slide has no background color
+    let slide = get_partly_transparent_slide(None)
+        .unwrap_or_else(|msg| panic!("Unable to create slide:\n{}", msg));
+    let image = slide
+        .read_region_composited(0u32, 4u32, 0u32, 1u32, 1u32, None)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    assert_eq!(image.get_pixel(0, 0).data, [255, 255, 255]);
+}
+
+#[test]
+fn test_bounds_policy_clamp() {
+    let slide = get_partly_transparent_slide(None)
+        .unwrap_or_else(|msg| panic!("Unable to create slide:\n{}", msg));
+    assert_eq!(slide.bounds_policy(), BoundsPolicy::Clamp);
+
+    // Negative coordinates, clipped at the top left
+    let region = slide
+        .read_region(-1, -2, 0, 3, 3)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    assert_eq!(region.dimensions(), (1, 2));
+    assert_eq!(region.get_pixel(0, 0).data, [200, 100, 50, 255]);
+
+    // Clipped at the bottom right
+    let region = slide
+        .read_region(2u32, 6u32, 0u32, 4u32, 4u32)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    assert_eq!(region.dimensions(), (2, 2));
+
+    match slide.read_region(10u32, 0u32, 0u32, 2u32, 2u32) {
+        Err(OpenSlideError::OutOfBounds(_)) => {}
+        Err(err) => panic!("Wrong error for a region outside the slide:\n{}", err),
+        Ok(_) => panic!("A region outside the slide was read"),
+    }
+    match slide.read_region(0, 0, 0, -2, 2) {
+        Err(OpenSlideError::InvalidArgument(_)) => {}
+        Err(err) => panic!("Wrong error for a negative height:\n{}", err),
+        Ok(_) => panic!("A region with a negative height was read"),
+    }
+}
+
+#[test]
+fn test_bounds_policy_error() {
+    let slide = get_partly_transparent_slide(None)
+        .unwrap_or_else(|msg| panic!("Unable to create slide:\n{}", msg))
+        .with_bounds_policy(BoundsPolicy::Error);
+    let region = slide
+        .read_region(0u32, 0u32, 0u32, 4u32, 8u32)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    assert_eq!(region.dimensions(), (8, 4));
+
+    for &(row, col) in &[(-1, 0), (0, -1), (1, 0), (0, 1)] {
+        match slide.read_region(row, col, 0, 4, 8) {
+            Err(OpenSlideError::OutOfBounds(_)) => {}
+            Err(err) => panic!("Wrong error for a region at ({}, {}):\n{}", col, row, err),
+            Ok(_) => panic!("A region at ({}, {}) was read", col, row),
+        }
+    }
+}
+
+#[test]
+fn test_bounds_policy_pad() {
+    let slide = get_partly_transparent_slide(Some("F0E6DC"))
+        .unwrap_or_else(|msg| panic!("Unable to create slide:\n{}", msg))
+        .with_bounds_policy(BoundsPolicy::Pad);
+    let region = slide
+        .read_region(-1, -1, 0, 2, 2)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    assert_eq!(region.dimensions(), (2, 2));
+    assert_eq!(region.get_pixel(0, 0).data, [0, 0, 0, 0]);
+    assert_eq!(region.get_pixel(1, 1).data, [200, 100, 50, 255]);
+
+    let region = slide
+        .read_region(10u32, 20u32, 0u32, 3u32, 5u32)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    assert_eq!(region.dimensions(), (5, 3));
+
+    let image = slide
+        .read_region_composited(-1, -1, 0, 2, 2, None)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    assert_eq!(image.get_pixel(0, 0).data, [240, 230, 220]);
+    assert_eq!(image.get_pixel(1, 1).data, [200, 100, 50]);
+}
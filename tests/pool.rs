@@ -0,0 +1,212 @@
+//! Tests of the pool of open slides, with slides from the in-memory backend
+//!
+
+extern crate openslide;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Barrier, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use openslide::backend::MemoryBackend;
+use openslide::pool::SlidePool;
+use openslide::{OpenSlide, OpenSlideError};
+
+/// A directory with empty slide files.
+fn get_directory(name: &str, count: usize) -> (PathBuf, Vec<PathBuf>) {
+    let directory = std::env::temp_dir().join(format!("openslide-pool-{}", name));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).expect("Could not create directory");
+    let files = (0..count)
+        .map(|index| {
+            let file = directory.join(format!("{}.svs", index));
+            fs::write(&file, b"").expect("Could not create file");
+            file
+        })
+        .collect();
+    (directory, files)
+}
+
+/// Open a generated slide of 100 x 80 pixels, and count the opened slides.
+fn open(opened: &AtomicUsize) -> impl Fn(&Path) -> Result<OpenSlide, OpenSlideError> + '_ {
+    move |_| {
+        opened.fetch_add(1, Ordering::SeqCst);
+        OpenSlide::from_backend(MemoryBackend::generated(100, 80, &[1, 2])?)
+    }
+}
+
+#[test]
+fn test_lazy_open() {
+    let (_, files) = get_directory("lazy", 1);
+    let opened = AtomicUsize::new(0);
+    let pool: SlidePool = SlidePool::new(2);
+    assert!(pool.is_empty());
+    assert_eq!(opened.load(Ordering::SeqCst), 0);
+
+    let first = pool.get_with(&files[0], open(&opened)).unwrap();
+    let second = pool.get_with(&files[0], open(&opened)).unwrap();
+    assert_eq!(opened.load(Ordering::SeqCst), 1);
+    assert!(Arc::ptr_eq(&first, &second));
+    assert_eq!(first.get_level0_dimensions().unwrap(), (100, 80));
+    assert_eq!(pool.len(), 1);
+
+    assert!(pool.remove(&files[0]));
+    assert!(!pool.remove(&files[0]));
+    pool.get_with(&files[0], open(&opened)).unwrap();
+    assert_eq!(opened.load(Ordering::SeqCst), 2);
+    pool.clear();
+    assert!(pool.is_empty());
+}
+
+#[test]
+fn test_eviction() {
+    let (_, files) = get_directory("eviction", 4);
+    let opened = AtomicUsize::new(0);
+    let pool: SlidePool = SlidePool::new(3);
+    assert_eq!(pool.capacity(), 3);
+    for file in &files[..3] {
+        pool.get_with(file, open(&opened)).unwrap();
+    }
+    // Use the first slide, such that the second is the least recently used
+    let first = pool.get_with(&files[0], open(&opened)).unwrap();
+    pool.get_with(&files[3], open(&opened)).unwrap();
+    assert_eq!(pool.len(), 3);
+    assert_eq!(opened.load(Ordering::SeqCst), 4);
+    assert!(!pool.contains(&files[1]));
+    for file in &[&files[0], &files[2], &files[3]] {
+        assert!(pool.contains(file));
+    }
+
+    // Handles stay valid after their slide is evicted
+    pool.clear();
+    assert_eq!(first.get_level_count().unwrap(), 2);
+    assert_eq!(SlidePool::<OpenSlide>::new(0).capacity(), 1);
+}
+
+#[test]
+fn test_modified() {
+    let (directory, files) = get_directory("modified", 1);
+    let opened = AtomicUsize::new(0);
+    let pool: SlidePool = SlidePool::new(2);
+    let first = pool.get_with(&files[0], open(&opened)).unwrap();
+
+    let file = fs::OpenOptions::new().write(true).open(&files[0]).unwrap();
+    file.set_modified(SystemTime::now() + Duration::from_secs(60))
+        .unwrap();
+    let second = pool.get_with(&files[0], open(&opened)).unwrap();
+    assert_eq!(opened.load(Ordering::SeqCst), 2);
+    assert!(!Arc::ptr_eq(&first, &second));
+    assert_eq!(pool.len(), 1);
+
+    match pool.get_with(&directory.join("missing.svs"), open(&opened)) {
+        Err(OpenSlideError::InvalidPath(_)) => {}
+        other => panic!("Expected an invalid path, got {:?}", other.map(|_| ())),
+    }
+    match pool.get_with(&files[0], |path| {
+        Err(OpenSlideError::UnsupportedFormat(
+            path.display().to_string(),
+        ))
+    }) {
+        Ok(_) => {}
+        other => panic!("Expected the open slide, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_threads() {
+    let (_, files) = get_directory("threads", 6);
+    let opened = AtomicUsize::new(0);
+    let pool: SlidePool = SlidePool::new(4);
+    thread::scope(|scope| {
+        for worker in 0..8 {
+            let (pool, files, opened) = (&pool, &files, &opened);
+            scope.spawn(move || {
+                for index in 0..50 {
+                    let file = &files[(worker + index) % files.len()];
+                    let slide = pool.get_with(file, open(opened)).unwrap();
+                    let pixels = slide.read_region(0u32, 0, 1, 4, 4).unwrap();
+                    assert_eq!(pixels.len(), 4 * 4 * 4);
+                    assert!(pool.len() <= 4);
+                }
+            });
+        }
+    });
+    assert_eq!(pool.len(), 4);
+    assert!(opened.load(Ordering::SeqCst) >= files.len());
+}
+
+#[test]
+fn test_open_error() {
+    let (_, files) = get_directory("open-error", 1);
+    let pool: SlidePool = SlidePool::new(2);
+    let result = pool.get_with(&files[0], |path| {
+        Err(OpenSlideError::UnsupportedFormat(
+            path.display().to_string(),
+        ))
+    });
+    match result {
+        Err(OpenSlideError::UnsupportedFormat(_)) => {}
+        other => panic!(
+            "Expected an unsupported format, got {:?}",
+            other.map(|_| ())
+        ),
+    }
+    // A slide that could not be opened does not take a place in the pool
+    assert!(!pool.contains(&files[0]));
+    assert!(pool.is_empty());
+}
+
+/// Threads that get a slide while it is being opened wait for it in stead of opening it again,
+/// while other slides can be opened in the meantime.
+#[test]
+fn test_concurrent_open() {
+    let (_, files) = get_directory("concurrent", 2);
+    let opened = AtomicUsize::new(0);
+    let pool: SlidePool = SlidePool::new(4);
+    let barrier = Barrier::new(8);
+    let (started_sender, started) = mpsc::channel();
+    let (release, release_receiver) = mpsc::channel::<()>();
+    let (started_sender, release_receiver) =
+        (Mutex::new(started_sender), Mutex::new(release_receiver));
+
+    let slides = thread::scope(|scope| {
+        let workers = (0..8)
+            .map(|_| {
+                let (pool, files, opened) = (&pool, &files, &opened);
+                let (barrier, started_sender, release_receiver) =
+                    (&barrier, &started_sender, &release_receiver);
+                scope.spawn(move || {
+                    barrier.wait();
+                    pool.get_with(&files[0], |path| {
+                        started_sender.lock().unwrap().send(()).unwrap();
+                        release_receiver
+                            .lock()
+                            .unwrap()
+                            .recv_timeout(Duration::from_secs(10))
+                            .expect("The slide was opened by more than one thread");
+                        open(opened)(path)
+                    })
+                    .unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        started
+            .recv_timeout(Duration::from_secs(10))
+            .expect("The slide was not opened");
+        // The other slide does not wait for the first one
+        pool.get_with(&files[1], open(&opened)).unwrap();
+        release.send(()).unwrap();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("Worker panicked"))
+            .collect::<Vec<_>>()
+    });
+
+    assert_eq!(opened.load(Ordering::SeqCst), 2);
+    assert!(slides.iter().all(|slide| Arc::ptr_eq(slide, &slides[0])));
+    assert_eq!(pool.len(), 2);
+}
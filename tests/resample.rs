@@ -0,0 +1,145 @@
+//! Tests of reading regions at arbitrary downsample factors, with the in-memory backend
+//!
+
+extern crate image;
+extern crate openslide;
+
+use image::{Rgba, RgbaImage};
+use openslide::backend::MemoryBackend;
+use openslide::geometry::{Anchor, Point, Size};
+use openslide::resample::Filter;
+use openslide::{OpenSlide, OpenSlideError};
+
+const FILTERS: [Filter; 4] = [
+    Filter::Nearest,
+    Filter::Bilinear,
+    Filter::Lanczos3,
+    Filter::Area,
+];
+
+/// A slide of 64 x 32 pixels with vertical stripes that are one pixel wide, and a level with
+/// downsample 4.
+fn get_striped_slide() -> OpenSlide {
+    let image = RgbaImage::from_fn(64, 32, |col, _| {
+        if col % 2 == 0 {
+            Rgba([200, 100, 0, 255])
+        } else {
+            Rgba([0, 100, 200, 255])
+        }
+    });
+    let backend = MemoryBackend::new(&image, &[1, 4])
+        .unwrap_or_else(|msg| panic!("Unable to create backend:\n{}", msg));
+    OpenSlide::from_backend(backend)
+        .unwrap_or_else(|msg| panic!("Unable to create slide:\n{}", msg))
+}
+
+fn top_left(x: i64, y: i64) -> Anchor {
+    Anchor::TopLeft(Point::new(x, y))
+}
+
+#[test]
+fn test_native_level() {
+    // At the downsample of a level, and aligned with its pixels, every filter copies the level
+    let backend = MemoryBackend::generated(1000, 600, &[1, 4, 16])
+        .unwrap_or_else(|msg| panic!("Unable to create backend:\n{}", msg));
+    let slide = OpenSlide::from_backend(backend)
+        .unwrap_or_else(|msg| panic!("Unable to create slide:\n{}", msg));
+    let expected = slide
+        .read_region(100u32, 300u32, 1u32, 20u32, 30u32)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg))
+        .into_raw();
+    for &filter in FILTERS.iter() {
+        let image = slide
+            .read_region_scaled(top_left(300, 100), 4.0, Size::new(30, 20), filter)
+            .unwrap_or_else(|msg| panic!("Read region error with {:?}:\n{}", filter, msg));
+        assert_eq!(image.into_raw(), expected, "Wrong pixels with {:?}", filter);
+    }
+}
+
+#[test]
+fn test_antialiasing() {
+    let slide = get_striped_slide();
+    // Halving the resolution averages the stripes, except with the nearest filter
+    for &filter in &[Filter::Bilinear, Filter::Area] {
+        let image = slide
+            .read_region_scaled(top_left(8, 8), 2.0, Size::new(8, 4), filter)
+            .unwrap_or_else(|msg| panic!("Read region error with {:?}:\n{}", filter, msg));
+        assert_eq!(image.dimensions(), (8, 4));
+        for pixel in image.pixels() {
+            assert_eq!(pixel.data, [100, 100, 100, 255], "Aliasing with {:?}", filter);
+        }
+    }
+    let image = slide
+        .read_region_scaled(top_left(8, 8), 2.0, Size::new(8, 4), Filter::Nearest)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    assert!(image.pixels().all(|pixel| pixel.data == [0, 100, 200, 255]));
+
+    // A fractional downsample factor, where the Lanczos filter stays close to the average
+    let image = slide
+        .read_region_scaled(top_left(10, 10), 3.7, Size::new(10, 4), Filter::Lanczos3)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    for pixel in image.pixels() {
+        assert!(
+            (i32::from(pixel.data[0]) - 100).abs() <= 8,
+            "Aliasing with Lanczos3: {:?}",
+            pixel.data
+        );
+    }
+}
+
+#[test]
+fn test_upsampling_and_anchor() {
+    let slide = get_striped_slide();
+    let image = slide
+        .read_region_scaled(top_left(4, 4), 0.5, Size::new(6, 2), Filter::Nearest)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    let reds = image.pixels().take(6).map(|pixel| pixel.data[0]).collect::<Vec<u8>>();
+    assert_eq!(reds, [200, 200, 0, 0, 200, 200]);
+
+    let centered = slide
+        .read_region_scaled(
+            Anchor::Center(Point::new(5, 5)),
+            0.5,
+            Size::new(4, 4),
+            Filter::Bilinear,
+        )
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    let expected = slide
+        .read_region_scaled(top_left(4, 4), 0.5, Size::new(4, 4), Filter::Bilinear)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    assert_eq!(centered.into_raw(), expected.into_raw());
+}
+
+#[test]
+fn test_outside_slide() {
+    let slide = get_striped_slide();
+    for &filter in FILTERS.iter() {
+        let image = slide
+            .read_region_scaled(top_left(-16, -16), 2.0, Size::new(16, 16), filter)
+            .unwrap_or_else(|msg| panic!("Read region error with {:?}:\n{}", filter, msg));
+        assert_eq!(image.dimensions(), (16, 16));
+        assert_eq!(image.get_pixel(0, 0).data, [0, 0, 0, 0]);
+        assert_eq!(image.get_pixel(15, 15).data[3], 255);
+    }
+    assert!(slide
+        .read_region_scaled(top_left(0, 0), 0.0, Size::new(1, 1), Filter::Area)
+        .is_err());
+}
+
+#[test]
+fn test_zero_size() {
+    let slide = get_striped_slide();
+    for &size in &[Size::new(0, 16), Size::new(16, 0), Size::new(0, 0)] {
+        for &filter in FILTERS.iter() {
+            match slide.read_region_scaled(top_left(0, 0), 2.0, size, filter) {
+                Err(OpenSlideError::InvalidArgument(_)) => {}
+                other => panic!(
+                    "Expected an invalid size for {:?} with {:?}, got {:?}",
+                    size,
+                    filter,
+                    other.map(|image| image.dimensions())
+                ),
+            }
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Tests of the Deep Zoom tile server, with slides from the in-memory backend
+//!
+
+#![cfg(feature = "server")]
+
+extern crate openslide;
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+use openslide::backend::MemoryBackend;
+use openslide::server::SlideServer;
+use openslide::OpenSlide;
+
+/// A directory with empty slide files, and a file that is not a slide.
+fn get_directory(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("openslide-server-{}", name));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(directory.join("nested")).expect("Could not create directory");
+    for file in &["a.svs", "nested/b c.tiff", "notes.txt"] {
+        fs::write(directory.join(file), b"").expect("Could not create file");
+    }
+    directory
+}
+
+/// A server that opens every slide as a generated slide of 300 x 250 pixels.
+fn get_server(directory: &Path) -> SlideServer {
+    SlideServer::new(directory)
+        .unwrap_or_else(|msg| panic!("Unable to create server:\n{}", msg))
+        .with_opener(|_| OpenSlide::from_backend(MemoryBackend::generated(300, 250, &[1, 2])?))
+}
+
+#[test]
+fn test_routes() {
+    let directory = get_directory("routes");
+    let server = get_server(&directory);
+    assert_eq!(
+        server.slides().keys().collect::<Vec<&String>>(),
+        ["a.svs", "nested/b c.tiff"]
+    );
+
+    let slides = server.handle("/slides");
+    assert_eq!(slides.status, 200);
+    assert_eq!(
+        String::from_utf8(slides.body).unwrap(),
+        "[{\"id\":\"a.svs\",\"dzi\":\"/slide/a.svs.dzi\",\"iiif\":\"/iiif/a.svs/info.json\"},\
+         {\"id\":\"nested/b c.tiff\",\"dzi\":\"/slide/nested/b%20c.tiff.dzi\",\
+         \"iiif\":\"/iiif/nested%2Fb%20c.tiff/info.json\"}]"
+    );
+
+    let dzi = server.handle("/slide/nested/b%20c.tiff.dzi");
+    assert_eq!((dzi.status, dzi.content_type), (200, "application/xml"));
+    assert!(String::from_utf8(dzi.body)
+        .unwrap()
+        .contains("<Size Width=\"300\" Height=\"250\" />"));
+
+    let tile = server.handle("/slide/a.svs_files/9/1_0.jpeg?cache=1");
+    assert_eq!((tile.status, tile.content_type), (200, "image/jpeg"));
+    assert_eq!(tile.body[..2], [0xff, 0xd8]);
+
+    assert_eq!(server.handle("/").status, 200);
+    for url in &[
+        "/slide/notes.txt.dzi",
+        "/slide/missing.svs.dzi",
+        "/slide/a.svs_files/10/0_0.jpeg",
+        "/slide/a.svs_files/9/2_0.jpeg",
+        "/slide/a.svs_files/9/0_0.png",
+        "/slide/a.svs_files/9/0.jpeg",
+        "/other",
+    ] {
+        assert_eq!(server.handle(url).status, 404, "{}", url);
+    }
+    assert_eq!(server.handle("/slide/a%zz.dzi").status, 400);
+}
+
+#[test]
+fn test_iiif_routes() {
+    let directory = get_directory("iiif");
+    let server = get_server(&directory)
+        .with_base_url("http://example.org/")
+        .with_max_open_slides(1);
+
+    let info = server.handle("/iiif/nested%2Fb%20c.tiff/info.json");
+    assert_eq!(info.status, 200);
+    assert!(String::from_utf8(info.body)
+        .unwrap()
+        .contains("\"id\":\"http://example.org/iiif/nested%2Fb%20c.tiff\""));
+
+    let image = server.handle("/iiif/a.svs/0,0,100,50/50,/90/gray.png");
+    assert_eq!((image.status, image.content_type), (200, "image/png"));
+    assert_eq!(image.body[..4], [0x89, b'P', b'N', b'G']);
+    let image = server.handle("/iiif/nested%2Fb%20c.tiff/full/max/0/default.jpg");
+    assert_eq!((image.status, image.content_type), (200, "image/jpeg"));
+
+    assert_eq!(
+        server.handle("/iiif/a.svs/full/max/45/default.jpg").status,
+        400
+    );
+    assert_eq!(
+        server.handle("/iiif/a.svs/full/400,/0/default.jpg").status,
+        400
+    );
+    assert_eq!(server.handle("/iiif/missing.svs/info.json").status, 404);
+    assert_eq!(server.handle("/iiif/a.svs/full/max/0").status, 404);
+}
+
+#[test]
+fn test_open_error() {
+    let directory = get_directory("open-error");
+    let server = SlideServer::new(&directory)
+        .unwrap_or_else(|msg| panic!("Unable to create server:\n{}", msg))
+        .with_opener(|path| {
+            Err(openslide::OpenSlideError::UnsupportedFormat(
+                path.display().to_string(),
+            ))
+        });
+    assert_eq!(server.handle("/slide/a.svs.dzi").status, 500);
+}
+
+/// Send a request, and return the status line and the body of the response.
+fn get(address: &str, path: &str) -> (String, Vec<u8>) {
+    let mut stream = TcpStream::connect(address).expect("Could not connect");
+    write!(stream, "GET {} HTTP/1.0\r\nHost: {}\r\n\r\n", path, address).unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    let end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .expect("Missing end of headers");
+    let head = String::from_utf8_lossy(&response[..end]).into_owned();
+    let status = head.lines().next().unwrap_or("").to_string();
+    (status, response[end + 4..].to_vec())
+}
+
+#[test]
+fn test_localhost() {
+    let directory = get_directory("localhost");
+    let listener = Arc::new(
+        get_server(&directory)
+            .bind("127.0.0.1:0")
+            .unwrap_or_else(|msg| panic!("Unable to listen:\n{}", msg)),
+    );
+    let address = listener.local_addr().expect("Missing address").to_string();
+    let worker = {
+        let listener = listener.clone();
+        thread::spawn(move || listener.run(2))
+    };
+
+    let (status, body) = get(&address, "/slide/a.svs.dzi");
+    assert!(status.ends_with("200 OK"), "{}", status);
+    assert!(String::from_utf8(body)
+        .unwrap()
+        .starts_with("<Image TileSize=\"254\""));
+    let (status, body) = get(&address, "/slide/a.svs_files/8/0_0.jpeg");
+    assert!(status.ends_with("200 OK"), "{}", status);
+    assert_eq!(body[..2], [0xff, 0xd8]);
+    let (status, _) = get(&address, "/slide/a.svs_files/8/1_0.jpeg");
+    assert!(status.contains("404"), "{}", status);
+
+    listener.shutdown();
+    worker.join().expect("Server panicked");
+}
@@ -0,0 +1,456 @@
+//! Integration tests of the pure-Rust TIFF backend
+//!
+//! The pixels and properties are compared with known values from the OpenSlide C library, and
+//! with the C library itself if it can be loaded.
+
+#![cfg(feature = "tiff")]
+
+extern crate openslide;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use openslide::backend::{LibOpenSlideBackend, SlideBackend, TiffBackend};
+use openslide::{OpenSlide, OpenSlideError};
+
+fn get_slide() -> OpenSlide {
+    let filename = Path::new("assets/CMU-1-Small-Region.svs");
+    let backend =
+        TiffBackend::open(filename).unwrap_or_else(|msg| panic!("Unable to open slide:\n{}", msg));
+    OpenSlide::from_backend(backend).unwrap_or_else(|msg| panic!("Unable to load slide:\n{}", msg))
+}
+
+/// A tiled 8 bit grayscale image, for `write_tiff`.
+struct TestImage {
+    width: u32,
+    height: u32,
+    tile_size: u32,
+    /// TIFF compression: 1 for none, 7 for JPEG
+    compression: u16,
+    tiles: Vec<Vec<u8>>,
+    /// Byte counts of the tiles, if not their actual length
+    byte_counts: Option<Vec<u32>>,
+}
+
+impl TestImage {
+    /// An uncompressed image, with the pixel values given by `value(x, y)`.
+    fn uncompressed<F: Fn(u32, u32) -> u8>(
+        width: u32,
+        height: u32,
+        tile_size: u32,
+        value: F,
+    ) -> TestImage {
+        let mut tiles = Vec::new();
+        for tile_row in 0..height.div_ceil(tile_size) {
+            for tile_col in 0..width.div_ceil(tile_size) {
+                let mut tile = Vec::new();
+                for y in 0..tile_size {
+                    for x in 0..tile_size {
+                        tile.push(value(tile_col * tile_size + x, tile_row * tile_size + y));
+                    }
+                }
+                tiles.push(tile);
+            }
+        }
+        TestImage {
+            width,
+            height,
+            tile_size,
+            compression: 1,
+            tiles,
+            byte_counts: None,
+        }
+    }
+}
+
+/// Write a little endian TIFF file with the given images in the temporary directory, and return
+/// its path.
+fn write_tiff(name: &str, images: &[TestImage]) -> PathBuf {
+    let mut file = b"II\x2a\x00\x00\x00\x00\x00".to_vec();
+    let mut next_directory = 4;
+    for image in images {
+        let mut offsets = Vec::new();
+        for tile in &image.tiles {
+            offsets.push(file.len() as u32);
+            file.extend_from_slice(tile);
+        }
+        let byte_counts = image
+            .byte_counts
+            .clone()
+            .unwrap_or_else(|| image.tiles.iter().map(|tile| tile.len() as u32).collect());
+        // Arrays of one value are stored in the entry itself
+        let mut array = |values: &[u32]| {
+            let position = file.len() as u32;
+            for value in values {
+                file.extend_from_slice(&value.to_le_bytes());
+            }
+            if values.len() == 1 {
+                values[0]
+            } else {
+                position
+            }
+        };
+        let offsets = array(&offsets);
+        let byte_counts = array(&byte_counts);
+        let count = image.tiles.len() as u32;
+
+        let directory = file.len() as u32;
+        file[next_directory..next_directory + 4].copy_from_slice(&directory.to_le_bytes());
+        let entries: [(u16, u16, u32, u32); 10] = [
+            (256, 4, 1, image.width),
+            (257, 4, 1, image.height),
+            (258, 3, 1, 8),
+            (259, 3, 1, u32::from(image.compression)),
+            (262, 3, 1, 1),
+            (277, 3, 1, 1),
+            (322, 4, 1, image.tile_size),
+            (323, 4, 1, image.tile_size),
+            (324, 4, count, offsets),
+            (325, 4, count, byte_counts),
+        ];
+        file.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        for &(tag, field_type, count, value) in &entries {
+            file.extend_from_slice(&tag.to_le_bytes());
+            file.extend_from_slice(&field_type.to_le_bytes());
+            file.extend_from_slice(&count.to_le_bytes());
+            file.extend_from_slice(&value.to_le_bytes());
+        }
+        next_directory = file.len();
+        file.extend_from_slice(&[0; 4]);
+    }
+
+    let path = std::env::temp_dir().join(format!("openslide-tiff-{}.tiff", name));
+    fs::write(&path, &file).expect("Could not write TIFF file");
+    path
+}
+
+/// Read a region of a slide that is expected to be corrupt, and return the error.
+fn read_corrupt(path: &Path) -> OpenSlideError {
+    let backend =
+        TiffBackend::open(path).unwrap_or_else(|msg| panic!("Unable to open slide:\n{}", msg));
+    let mut buffer = vec![0u32; 16 * 16];
+    match backend.read_region(&mut buffer, 0, 0, 0, 16, 16) {
+        Err(err) => err,
+        Ok(()) => panic!("Corrupt region was read"),
+    }
+}
+
+#[test]
+fn test_levels() {
+    let slide = get_slide();
+    assert_eq!(slide.get_level_count(), Ok(1));
+    assert_eq!(slide.get_level0_dimensions(), Ok((2220, 2967)));
+    assert_eq!(slide.get_level_downsample(0u32), Ok(1.0));
+}
+
+#[test]
+fn test_read_region() {
+    let slide = get_slide();
+    let value = slide
+        .read_region(1510u32, 1510u32, 0u32, 4u32, 4u32)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    let true_value = vec![
+        152, 123, 172, 255, 148, 122, 171, 255, 137, 123, 167, 255, 167, 158, 175, 255, 179, 185,
+        205, 255, 183, 197, 213, 255, 198, 210, 221, 255, 224, 226, 224, 255, 247, 249, 246, 255,
+        248, 255, 247, 255, 255, 249, 243, 255, 236, 255, 255, 255, 249, 239, 250, 255, 250, 246,
+        252, 255, 254, 245, 241, 255, 246, 246, 246, 255,
+    ];
+    assert_eq!(true_value, value.into_vec())
+}
+
+#[test]
+fn test_read_region_outside() {
+    let slide = get_slide();
+    let value = slide.backend();
+    let mut buffer = vec![1u32; 4 * 3];
+    value
+        .read_region(&mut buffer, 2218, -1, 0, 4, 3)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    // Only the two columns at the right edge of the rows 0 and 1 are inside the slide
+    for (index, pixel) in buffer.iter().enumerate() {
+        let inside = index / 4 > 0 && index % 4 < 2;
+        assert_eq!(*pixel != 0, inside, "Pixel {} is {:08x}", index, pixel);
+    }
+}
+
+/// Regions on level 1 are copies of the level pixels, from the origin divided by the downsample
+/// and rounded down.
+#[test]
+fn test_read_region_levels() {
+    let level0 = TestImage::uncompressed(64, 48, 16, |x, y| (x + y) as u8);
+    let level1 = TestImage::uncompressed(32, 24, 16, |x, y| (128 + 2 * x + y) as u8);
+    let path = write_tiff("levels", &[level0, level1]);
+    let backend =
+        TiffBackend::open(&path).unwrap_or_else(|msg| panic!("Unable to open slide:\n{}", msg));
+    assert_eq!(backend.level_count(), Ok(2));
+    assert_eq!(backend.level_downsample(1), Ok(2.0));
+
+    let gray = |value: u32| 0xff00_0000 | value << 16 | value << 8 | value;
+    let mut buffer = vec![0u32; 20 * 10];
+    for &(x, y) in &[(10, 6), (11, 7)] {
+        backend
+            .read_region(&mut buffer, x, y, 1, 20, 10)
+            .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+        // Level pixels (5, 3) to (24, 12), across the tile boundary at 16
+        for (index, &pixel) in buffer.iter().enumerate() {
+            let (col, row) = (5 + index as u32 % 20, 3 + index as u32 / 20);
+            assert_eq!(
+                pixel,
+                gray(128 + 2 * col + row),
+                "Pixel {} at ({}, {})",
+                index,
+                x,
+                y
+            );
+        }
+    }
+}
+
+#[test]
+fn test_properties() {
+    let slide = get_slide();
+    let properties = slide
+        .get_properties()
+        .unwrap_or_else(|msg| panic!("Properties error:\n{}", msg));
+    assert_eq!(properties["openslide.vendor"], "aperio");
+    assert_eq!(properties["openslide.objective-power"], "20");
+    assert_eq!(properties["openslide.mpp-x"], "0.499");
+    assert_eq!(properties["openslide.level[0].tile-width"], "240");
+    assert_eq!(properties["aperio.Filename"], "CMU-1");
+    assert_eq!(properties["aperio.OriginalWidth"], "46000");
+
+    assert_eq!(slide.properties.vendor(), Some(String::from("aperio")));
+    assert_eq!(slide.properties.mpp(), Some(0.499));
+    assert_eq!(slide.properties.app_mag(), Some(20));
+    assert_eq!(slide.properties.level_count(), Some(1));
+}
+
+#[test]
+fn test_associated_images() {
+    let slide = get_slide();
+    let mut names = slide
+        .associated_image_names()
+        .unwrap_or_else(|msg| panic!("Associated image names error:\n{}", msg));
+    names.sort();
+    assert_eq!(names, vec!["label", "macro", "thumbnail"]);
+    assert_eq!(
+        slide.associated_image_dimensions("thumbnail"),
+        Ok((574, 768))
+    );
+    assert_eq!(slide.associated_image_dimensions("macro"), Ok((1280, 431)));
+
+    let label = slide
+        .read_associated_image("label")
+        .unwrap_or_else(|msg| panic!("Read label error:\n{}", msg));
+    assert_eq!(label.dimensions(), (387, 463));
+    assert!(label.pixels().all(|pixel| pixel.data[3] == 255));
+}
+
+#[test]
+fn test_unsupported_format() {
+    match TiffBackend::open(Path::new("Cargo.toml")) {
+        Err(OpenSlideError::UnsupportedFormat(_)) => {}
+        Err(err) => panic!("Wrong error for unsupported format:\n{}", err),
+        Ok(_) => panic!("Cargo.toml was opened as a slide"),
+    }
+}
+
+/// Compare whole regions and the label with the OpenSlide C library, if it can be loaded. The
+/// asset only has level 0; other levels are tested in `test_read_region_levels`.
+///
+/// Set `OPENSLIDE_REQUIRE_LIBRARY` to fail in stead of skipping the comparison when the C library
+/// can not be loaded.
+#[test]
+fn test_matches_libopenslide() {
+    let filename = Path::new("assets/CMU-1-Small-Region.svs");
+    let reference = match LibOpenSlideBackend::open(filename) {
+        Ok(backend) => OpenSlide::from_backend(backend)
+            .unwrap_or_else(|msg| panic!("Unable to load slide:\n{}", msg)),
+        Err(OpenSlideError::LibraryNotLoaded(ref msg))
+            if std::env::var_os("OPENSLIDE_REQUIRE_LIBRARY").is_none() =>
+        {
+            eprintln!("Skipping comparison with the OpenSlide C library: {}", msg);
+            return;
+        }
+        Err(err) => panic!(
+            "Unable to open slide with the OpenSlide C library:\n{}",
+            err
+        ),
+    };
+    let slide = get_slide();
+
+    for &(row, col, height, width) in &[(0u32, 0u32, 512u32, 512u32), (2800, 2000, 300, 300)] {
+        let expected = reference
+            .read_region(row, col, 0, height, width)
+            .unwrap_or_else(|msg| panic!("Reference read region error:\n{}", msg));
+        let value = slide
+            .read_region(row, col, 0, height, width)
+            .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+        assert!(
+            expected.into_raw() == value.into_raw(),
+            "Region at ({}, {}) differs",
+            row,
+            col
+        );
+    }
+
+    let expected = reference
+        .read_associated_image("label")
+        .unwrap_or_else(|msg| panic!("Reference read label error:\n{}", msg));
+    let value = slide
+        .read_associated_image("label")
+        .unwrap_or_else(|msg| panic!("Read label error:\n{}", msg));
+    assert!(expected.into_raw() == value.into_raw(), "Label differs");
+
+    let expected = reference
+        .get_properties()
+        .unwrap_or_else(|msg| panic!("Reference properties error:\n{}", msg));
+    let value = slide
+        .get_properties()
+        .unwrap_or_else(|msg| panic!("Properties error:\n{}", msg));
+    for (name, expected_value) in &expected {
+        if name == "openslide.quickhash-1" || name.starts_with("openslide.associated.") {
+            continue;
+        }
+        assert_eq!(
+            value.get(name),
+            Some(expected_value),
+            "Property {} differs",
+            name
+        );
+    }
+}
+
+#[test]
+fn test_corrupt_huffman_table() {
+    // A DHT segment with three codes of 1 bit, where only two fit
+    let mut tile = vec![0xff, 0xd8, 0xff, 0xc4, 0x00, 0x16, 0x00, 0x03];
+    tile.extend_from_slice(&[0; 15]);
+    tile.extend_from_slice(&[0x01, 0x02, 0x03, 0xff, 0xd9]);
+    let image = TestImage {
+        width: 16,
+        height: 16,
+        tile_size: 16,
+        compression: 7,
+        tiles: vec![tile],
+        byte_counts: None,
+    };
+    match read_corrupt(&write_tiff("huffman", &[image])) {
+        OpenSlideError::Library(ref msg) if msg.contains("Huffman") => {}
+        err => panic!("Wrong error for an over-subscribed Huffman table:\n{}", err),
+    }
+}
+
+/// A JPEG tile of 32 x 32 pixels with only a frame header, with the given sampling factors of
+/// three components, once or twice.
+fn frame_header_tile(sampling: [u8; 3], frames: usize) -> TestImage {
+    let mut tile = vec![0xff, 0xd8];
+    for _ in 0..frames {
+        tile.extend_from_slice(&[0xff, 0xc0, 0x00, 0x11, 0x08, 0x00, 0x20, 0x00, 0x20, 0x03]);
+        for (id, &factors) in sampling.iter().enumerate() {
+            tile.extend_from_slice(&[id as u8 + 1, factors, 0x00]);
+        }
+    }
+    tile.extend_from_slice(&[0xff, 0xd9]);
+    TestImage {
+        width: 32,
+        height: 32,
+        tile_size: 32,
+        compression: 7,
+        tiles: vec![tile],
+        byte_counts: None,
+    }
+}
+
+#[test]
+fn test_fractional_sampling() {
+    let image = frame_header_tile([0x44, 0x33, 0x11], 1);
+    match read_corrupt(&write_tiff("fractional-sampling", &[image])) {
+        OpenSlideError::Unsupported(ref msg) if msg.contains("fractional sampling") => {}
+        err => panic!("Wrong error for fractional sampling factors:\n{}", err),
+    }
+}
+
+#[test]
+fn test_frame_larger_than_tile() {
+    let mut image = frame_header_tile([0x11, 0x11, 0x11], 1);
+    image.width = 16;
+    image.height = 16;
+    image.tile_size = 16;
+    match read_corrupt(&write_tiff("frame-size", &[image])) {
+        OpenSlideError::Library(ref msg) if msg.contains("larger than its tile") => {}
+        err => panic!("Wrong error for a JPEG image larger than its tile:\n{}", err),
+    }
+}
+
+#[test]
+fn test_tile_too_large() {
+    let image = TestImage {
+        width: 16,
+        height: 16,
+        tile_size: 1 << 16,
+        compression: 1,
+        tiles: vec![Vec::new()],
+        byte_counts: None,
+    };
+    match read_corrupt(&write_tiff("tile-size", &[image])) {
+        OpenSlideError::Library(ref msg) if msg.contains("too large") => {}
+        err => panic!("Wrong error for a tile that is too large:\n{}", err),
+    }
+}
+
+#[test]
+fn test_too_many_tiles() {
+    // A BigTIFF file of 2^40 x 2^40 pixels in tiles of 1 pixel
+    let mut file = b"II\x2b\x00\x08\x00\x00\x00\x10\x00\x00\x00\x00\x00\x00\x00".to_vec();
+    let entries: [(u16, u16, u64); 10] = [
+        (256, 16, 1 << 40),
+        (257, 16, 1 << 40),
+        (258, 3, 8),
+        (259, 3, 1),
+        (262, 3, 1),
+        (277, 3, 1),
+        (322, 3, 1),
+        (323, 3, 1),
+        (324, 16, 0),
+        (325, 16, 0),
+    ];
+    file.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+    for &(tag, field_type, value) in &entries {
+        file.extend_from_slice(&tag.to_le_bytes());
+        file.extend_from_slice(&field_type.to_le_bytes());
+        file.extend_from_slice(&1u64.to_le_bytes());
+        file.extend_from_slice(&value.to_le_bytes());
+    }
+    file.extend_from_slice(&[0; 8]);
+    let path = std::env::temp_dir().join("openslide-tiff-tile-count.tiff");
+    fs::write(&path, &file).expect("Could not write TIFF file");
+
+    match TiffBackend::open(&path) {
+        Err(OpenSlideError::Library(ref msg)) if msg.contains("Too many tiles") => {}
+        Err(err) => panic!("Wrong error for too many tiles:\n{}", err),
+        Ok(_) => panic!("A slide with too many tiles was opened"),
+    }
+}
+
+#[test]
+fn test_multiple_frame_headers() {
+    let image = frame_header_tile([0x22, 0x11, 0x11], 2);
+    match read_corrupt(&write_tiff("frame-headers", &[image])) {
+        OpenSlideError::Library(ref msg) if msg.contains("frame header") => {}
+        err => panic!("Wrong error for more than one frame header:\n{}", err),
+    }
+}
+
+#[test]
+fn test_chunk_beyond_end_of_file() {
+    let mut image = TestImage::uncompressed(16, 16, 16, |x, y| (x + y) as u8);
+    image.byte_counts = Some(vec![u32::MAX]);
+    match read_corrupt(&write_tiff("beyond-end", &[image])) {
+        OpenSlideError::Library(ref msg) if msg.contains("end of the file") => {}
+        err => panic!(
+            "Wrong error for a tile beyond the end of the file:\n{}",
+            err
+        ),
+    }
+}
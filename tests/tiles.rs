@@ -0,0 +1,132 @@
+//! Tests of the tile grid, with the in-memory backend
+//!
+
+extern crate openslide;
+
+use openslide::backend::MemoryBackend;
+use openslide::geometry::{Level, Rect};
+use openslide::resample::Filter;
+use openslide::tiles::{Edge, TileGrid};
+use openslide::{OpenSlide, OpenSlideError};
+
+/// A slide of 1000 x 600 pixels of 0.25 microns, with levels of 250 x 150 and 63 x 38 pixels, and
+/// native tiles of 240 x 120 pixels.
+fn get_slide() -> OpenSlide {
+    let backend = MemoryBackend::generated(1000, 600, &[1, 4, 16])
+        .unwrap_or_else(|msg| panic!("Unable to create backend:\n{}", msg))
+        .with_mpp(0.25, 0.25)
+        .with_tile_size(240, 120);
+    OpenSlide::from_backend(backend)
+        .unwrap_or_else(|msg| panic!("Unable to create slide:\n{}", msg))
+}
+
+fn get_grid(slide: &OpenSlide, level: u32) -> TileGrid {
+    TileGrid::new(slide, Level(level)).unwrap_or_else(|msg| panic!("Grid error:\n{}", msg))
+}
+
+#[test]
+fn test_edges() {
+    let slide = get_slide();
+    let grid = get_grid(&slide, 1).with_tile_size(100, 100);
+    assert_eq!(grid.grid_size(), (3, 2));
+    let last = grid.tile(2, 1).expect("Missing tile");
+    assert_eq!(last.rect, Rect::new(200, 100, 50, 50));
+    assert_eq!(last.level0, Rect::new(800, 400, 200, 200));
+    assert_eq!(grid.tile(3, 0), None);
+
+    let grid = grid.with_edge(Edge::Pad);
+    assert_eq!(grid.grid_size(), (3, 2));
+    assert_eq!(grid.tile(2, 1).map(|tile| tile.rect), Some(Rect::new(200, 100, 100, 100)));
+
+    let grid = grid.with_edge(Edge::Drop);
+    assert_eq!(grid.grid_size(), (2, 1));
+    assert_eq!(grid.len(), 2);
+    assert!(get_grid(&slide, 2).with_edge(Edge::Drop).is_empty());
+}
+
+#[test]
+fn test_overlap_and_stride() {
+    let slide = get_slide();
+    let grid = get_grid(&slide, 1).with_tile_size(100, 100).with_overlap(20);
+    assert_eq!(grid.stride(), (80, 80));
+    // Columns at 0, 80 and 160, where the last one reaches the edge
+    assert_eq!(grid.grid_size(), (3, 2));
+    assert_eq!(grid.tile(1, 1).map(|tile| tile.rect), Some(Rect::new(80, 80, 100, 70)));
+
+    let grid = grid.with_stride(50, 150);
+    assert_eq!(grid.grid_size(), (4, 1));
+    let origins = grid
+        .iter()
+        .map(|tile| tile.rect.origin.x)
+        .collect::<Vec<i64>>();
+    assert_eq!(origins, [0, 50, 100, 150]);
+}
+
+#[test]
+fn test_snap_to_native_tiles() {
+    let slide = get_slide();
+    let grid = get_grid(&slide, 0)
+        .with_tile_size(300, 100)
+        .snap_to_native_tiles()
+        .unwrap_or_else(|msg| panic!("Grid error:\n{}", msg));
+    assert_eq!(grid.tile_size(), (480, 120));
+    assert_eq!(grid.stride(), (480, 120));
+    assert!(grid
+        .iter()
+        .all(|tile| tile.rect.origin.x % 240 == 0 && tile.rect.origin.y % 120 == 0));
+
+    let grid = TileGrid::at_mpp(&slide, 1.0, Filter::Area)
+        .unwrap_or_else(|msg| panic!("Grid error:\n{}", msg));
+    match grid.snap_to_native_tiles() {
+        Err(OpenSlideError::Unsupported(_)) => {}
+        Err(err) => panic!("Wrong error for a grid at a physical scale:\n{}", err),
+        Ok(_) => panic!("A grid at a physical scale was snapped to native tiles"),
+    }
+}
+
+#[test]
+fn test_read_tiles() {
+    let slide = get_slide();
+    let grid = get_grid(&slide, 1)
+        .with_tile_size(100, 100)
+        .with_edge(Edge::Pad);
+    let mut count = 0;
+    for tile in grid.tiles() {
+        let (tile, image) = tile.unwrap_or_else(|msg| panic!("Read tile error:\n{}", msg));
+        assert_eq!(image.dimensions(), (100, 100));
+        let expected = slide
+            .read_region(
+                tile.level0.origin.y,
+                tile.level0.origin.x,
+                1,
+                50.min(tile.rect.size.height as i64),
+                50.min(tile.rect.size.width as i64),
+            )
+            .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+        assert_eq!(image.get_pixel(0, 0), expected.get_pixel(0, 0));
+        count += 1;
+    }
+    assert_eq!(count, 6);
+    let last = grid.tile(2, 1).expect("Missing tile");
+    let image = grid
+        .read(&last)
+        .unwrap_or_else(|msg| panic!("Read tile error:\n{}", msg));
+    assert_eq!(image.get_pixel(49, 49).data[3], 255);
+    assert_eq!(image.get_pixel(50, 50).data, [0, 0, 0, 0]);
+
+    // At a physical scale, where 1 micron per pixel is the downsample of level 1
+    let grid = TileGrid::at_mpp(&slide, 1.0, Filter::Area)
+        .unwrap_or_else(|msg| panic!("Grid error:\n{}", msg))
+        .with_tile_size(100, 100);
+    assert_eq!(grid.downsample(), (4.0, 4.0));
+    assert_eq!(grid.dimensions(), get_grid(&slide, 1).dimensions());
+    let tile = grid.tile(1, 1).expect("Missing tile");
+    let image = grid
+        .read(&tile)
+        .unwrap_or_else(|msg| panic!("Read tile error:\n{}", msg));
+    let expected = get_grid(&slide, 1)
+        .with_tile_size(100, 100)
+        .read(&tile)
+        .unwrap_or_else(|msg| panic!("Read tile error:\n{}", msg));
+    assert_eq!(image.into_raw(), expected.into_raw());
+}
@@ -0,0 +1,165 @@
+//! Tests of the conversions between pixels and physical units, with the in-memory backend
+//!
+
+extern crate openslide;
+
+use openslide::backend::MemoryBackend;
+use openslide::geometry::{Anchor, Point, Rect, Size};
+use openslide::resample::Filter;
+use openslide::tiles::TileGrid;
+use openslide::units::{Calibration, PointUm};
+use openslide::{OpenSlide, OpenSlideError};
+
+/// A slide of 1000 x 600 pixels of 0.25 microns, scanned at 40x.
+fn get_slide(calibrated: bool) -> OpenSlide {
+    let mut backend = MemoryBackend::generated(1000, 600, &[1, 4, 16])
+        .unwrap_or_else(|msg| panic!("Unable to create backend:\n{}", msg));
+    if calibrated {
+        backend = backend
+            .with_mpp(0.25, 0.25)
+            .with_property("openslide.objective-power", "40");
+    }
+    OpenSlide::from_backend(backend)
+        .unwrap_or_else(|msg| panic!("Unable to create slide:\n{}", msg))
+}
+
+#[test]
+fn test_calibration() {
+    let calibration = get_slide(true)
+        .calibration()
+        .unwrap_or_else(|msg| panic!("Calibration error:\n{}", msg));
+    assert_eq!(calibration.mpp(), 0.25);
+    assert_eq!(calibration.objective_power, Some(40.0));
+    assert_eq!(
+        calibration.to_microns(Point::new(100, -8)),
+        PointUm::new(25.0, -2.0)
+    );
+    assert_eq!(
+        calibration.to_pixels(PointUm::new(25.1, -2.0)),
+        Ok(Point::new(100, -8))
+    );
+    assert_eq!(calibration.downsample_for_mpp(1.0), Ok((4.0, 4.0)));
+    assert_eq!(calibration.mpp_at_downsample(16.0), (4.0, 4.0));
+    assert_eq!(calibration.downsample_for_magnification(10.0), Ok(4.0));
+    assert_eq!(calibration.magnification_at_downsample(2.0), Ok(20.0));
+    assert!(calibration.downsample_for_mpp(0.0).is_err());
+}
+
+#[test]
+fn test_non_square_pixels() {
+    let backend = MemoryBackend::generated(1000, 600, &[1, 4, 16])
+        .unwrap_or_else(|msg| panic!("Unable to create backend:\n{}", msg))
+        .with_mpp(0.25, 0.5);
+    let slide = OpenSlide::from_backend(backend)
+        .unwrap_or_else(|msg| panic!("Unable to create slide:\n{}", msg));
+    let calibration = slide
+        .calibration()
+        .unwrap_or_else(|msg| panic!("Calibration error:\n{}", msg));
+    assert_eq!(calibration.downsample_for_mpp(1.0), Ok((4.0, 2.0)));
+    assert_eq!(calibration.mpp_at_downsample(2.0), (0.5, 1.0));
+
+    // Level 0 pixels have the column in the red channel, and the row in the green channel
+    let image = slide
+        .read_region_at_mpp(PointUm::new(0.0, 0.0), Size::new(8, 8), 1.0, Filter::Nearest)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    assert_eq!(image.get_pixel(3, 5).data[..2], [14, 11]);
+
+    let grid = TileGrid::at_mpp(&slide, 1.0, Filter::Nearest)
+        .unwrap_or_else(|msg| panic!("Tile grid error:\n{}", msg))
+        .with_tile_size(8, 8);
+    assert_eq!(grid.dimensions(), Size::new(250, 300));
+    let tile = grid.tile(0, 0).expect("Missing tile");
+    assert_eq!(tile.level0, Rect::new(0, 0, 32, 16));
+    let tile = grid
+        .read(&tile)
+        .unwrap_or_else(|msg| panic!("Read tile error:\n{}", msg));
+    assert_eq!(tile.into_raw(), image.into_raw());
+}
+
+#[test]
+fn test_read_region_at_mpp_and_magnification() {
+    let slide = get_slide(true);
+    let expected = slide
+        .read_region_scaled(
+            Anchor::TopLeft(Point::new(300, 100)),
+            4.0,
+            Size::new(30, 20),
+            Filter::Area,
+        )
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg))
+        .into_raw();
+
+    let image = slide
+        .read_region_at_mpp(PointUm::new(75.0, 25.0), Size::new(30, 20), 1.0, Filter::Area)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    assert_eq!(image.into_raw(), expected);
+
+    let image = slide
+        .read_region_at_magnification(Point::new(300, 100), Size::new(30, 20), 10.0, Filter::Area)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    assert_eq!(image.into_raw(), expected);
+}
+
+#[test]
+fn test_magnification_without_mpp() {
+    let backend = MemoryBackend::generated(1000, 600, &[1, 4, 16])
+        .unwrap_or_else(|msg| panic!("Unable to create backend:\n{}", msg))
+        .with_property("openslide.objective-power", "40");
+    let slide = OpenSlide::from_backend(backend)
+        .unwrap_or_else(|msg| panic!("Unable to create slide:\n{}", msg));
+    assert_eq!(slide.objective_power(), Ok(40.0));
+
+    let expected = slide
+        .read_region_scaled(
+            Anchor::TopLeft(Point::new(300, 100)),
+            4.0,
+            Size::new(30, 20),
+            Filter::Area,
+        )
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg))
+        .into_raw();
+    let image = slide
+        .read_region_at_magnification(Point::new(300, 100), Size::new(30, 20), 10.0, Filter::Area)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    assert_eq!(image.into_raw(), expected);
+
+    match slide.read_region_at_mpp(PointUm::new(0.0, 0.0), Size::new(4, 4), 1.0, Filter::Area) {
+        Err(OpenSlideError::MissingCalibration(_)) => {}
+        Err(err) => panic!("Wrong error for a slide without microns per pixel:\n{}", err),
+        Ok(_) => panic!("A slide without microns per pixel was read at a physical scale"),
+    }
+}
+
+#[test]
+fn test_missing_calibration() {
+    let slide = get_slide(false);
+    match slide.read_region_at_mpp(PointUm::new(0.0, 0.0), Size::new(4, 4), 1.0, Filter::Area) {
+        Err(OpenSlideError::MissingCalibration(_)) => {}
+        Err(err) => panic!("Wrong error for a slide without calibration:\n{}", err),
+        Ok(_) => panic!("A slide without calibration was read at a physical scale"),
+    }
+
+    // The calibration given by the caller
+    let calibration =
+        Calibration::new(0.5, 0.5).unwrap_or_else(|msg| panic!("Calibration error:\n{}", msg));
+    let slide = slide.with_calibration(calibration);
+    let image = slide
+        .read_region_at_mpp(PointUm::new(0.0, 0.0), Size::new(4, 4), 2.0, Filter::Area)
+        .unwrap_or_else(|msg| panic!("Read region error:\n{}", msg));
+    assert_eq!(image.dimensions(), (4, 4));
+    match slide.read_region_at_magnification(Point::new(0, 0), Size::new(4, 4), 10.0, Filter::Area)
+    {
+        Err(OpenSlideError::MissingCalibration(_)) => {}
+        Err(err) => panic!("Wrong error for a slide without objective power:\n{}", err),
+        Ok(_) => panic!("A slide without objective power was read at a magnification"),
+    }
+
+    let calibration = calibration
+        .with_objective_power(20.0)
+        .unwrap_or_else(|msg| panic!("Calibration error:\n{}", msg));
+    let slide = slide.with_calibration(calibration);
+    assert_eq!(
+        slide.calibration().and_then(|calibration| calibration.downsample_for_magnification(5.0)),
+        Ok(4.0)
+    );
+}
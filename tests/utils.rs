@@ -0,0 +1,161 @@
+//! Tests of the buffer decoding utilities
+//!
+
+extern crate openslide;
+
+use openslide::utils::{self, PixelFormat, WordRepresentation};
+use openslide::OpenSlideError;
+
+/// Un-premultiply one channel value with floating point arithmetic.
+fn unpremultiply(value: u32, alpha: u32) -> u8 {
+    if alpha == 0 || alpha == 255 {
+        value as u8
+    } else {
+        (f64::from(value) * 255.0 / f64::from(alpha)).round().min(255.0) as u8
+    }
+}
+
+/// A buffer with opaque runs, and transparent and semi-transparent pixels in between.
+fn mixed_buffer(len: u32) -> Vec<u32> {
+    (0..len)
+        .map(|index| {
+            let alpha = match index % 37 {
+                0 => 0,
+                1..=5 => 1 + index % 254,
+                _ => 255,
+            };
+            let value = |seed: u32| (index * seed + 7) % (alpha + 1);
+            alpha << 24 | value(3) << 16 | value(5) << 8 | value(11)
+        })
+        .collect()
+}
+
+#[test]
+fn test_decode_buffer() {
+    // 23 x 13 pixels, which is not a multiple of the number of pixels that are decoded together
+    let buffer = mixed_buffer(23 * 13);
+    let image = utils::decode_buffer(&buffer, 13u32, 23u32, WordRepresentation::BigEndian)
+        .unwrap_or_else(|msg| panic!("Decode error:\n{}", msg));
+    assert_eq!(image.dimensions(), (23, 13));
+    for (pixel, &word) in image.pixels().zip(&buffer) {
+        let alpha = word >> 24;
+        let expected = [
+            unpremultiply(word >> 16 & 0xff, alpha),
+            unpremultiply(word >> 8 & 0xff, alpha),
+            unpremultiply(word & 0xff, alpha),
+            alpha as u8,
+        ];
+        assert_eq!(pixel.data, expected, "Wrong pixel for word {:08x}", word);
+    }
+}
+
+#[test]
+fn test_decode_large_buffer() {
+    // Large enough to be decoded on several threads with the `parallel` feature
+    let buffer = mixed_buffer(1024 * 600);
+    let image = utils::decode_buffer(&buffer, 600u32, 1024u32, WordRepresentation::BigEndian)
+        .unwrap_or_else(|msg| panic!("Decode error:\n{}", msg));
+    let mut words = buffer.clone();
+    utils::argb_to_rgba_in_place(&mut words);
+    let bytes = words
+        .iter()
+        .flat_map(|word| word.to_ne_bytes().to_vec())
+        .collect::<Vec<u8>>();
+    assert_eq!(image.into_raw(), bytes);
+}
+
+#[test]
+fn test_decode_small_buffer() {
+    let buffer = vec![0xff00_0000; 15];
+    match utils::decode_buffer(&buffer, 4u32, 4u32, WordRepresentation::BigEndian) {
+        Err(OpenSlideError::InvalidArgument(_)) => {}
+        Err(err) => panic!("Wrong error for a small buffer:\n{}", err),
+        Ok(_) => panic!("A buffer that is too small was decoded"),
+    }
+}
+
+#[test]
+fn test_unpremultiply_in_place_matches_decode() {
+    let buffer = mixed_buffer(100);
+    let mut words = buffer.clone();
+    utils::unpremultiply_in_place(&mut words);
+    let image = utils::decode_buffer(&buffer, 10u32, 10u32, WordRepresentation::BigEndian)
+        .unwrap_or_else(|msg| panic!("Decode error:\n{}", msg));
+    for (pixel, &word) in image.pixels().zip(&words) {
+        let [alpha, red, green, blue] = word.to_be_bytes();
+        assert_eq!(pixel.data, [red, green, blue, alpha]);
+    }
+}
+
+#[test]
+fn test_decode_little_endian_buffer() {
+    let big = mixed_buffer(50);
+    let little = big.iter().map(|word| word.swap_bytes()).collect::<Vec<u32>>();
+    let expected = utils::decode_buffer(&big, 5u32, 10u32, WordRepresentation::BigEndian)
+        .unwrap_or_else(|msg| panic!("Decode error:\n{}", msg));
+    let image = utils::decode_buffer(&little, 5u32, 10u32, WordRepresentation::LittleEndian)
+        .unwrap_or_else(|msg| panic!("Decode error:\n{}", msg));
+    assert_eq!(image.into_raw(), expected.into_raw());
+
+    let region = utils::decode_buffer_as(
+        &little,
+        5u32,
+        10u32,
+        WordRepresentation::LittleEndian,
+        PixelFormat::Gray8,
+    )
+    .unwrap_or_else(|msg| panic!("Decode error:\n{}", msg));
+    let expected = utils::decode_buffer_as(
+        &big,
+        5u32,
+        10u32,
+        WordRepresentation::BigEndian,
+        PixelFormat::Gray8,
+    )
+    .unwrap_or_else(|msg| panic!("Decode error:\n{}", msg));
+    assert_eq!(region, expected);
+}
+
+#[test]
+fn test_decode_buffer_as() {
+    // Opaque, semi-transparent and transparent pixels
+    let buffer = [0xff80_4020, 0x8040_2010, 0x0000_0000];
+    let decode = |format| {
+        utils::decode_buffer_as(&buffer, 1u32, 3u32, WordRepresentation::BigEndian, format)
+            .unwrap_or_else(|msg| panic!("Decode error:\n{}", msg))
+    };
+
+    let rgba = decode(PixelFormat::Rgba8);
+    assert_eq!(
+        rgba.as_u8(),
+        Some(&[128, 64, 32, 255, 128, 64, 32, 128, 0, 0, 0, 0][..])
+    );
+    assert_eq!(rgba.as_f32(), None);
+    assert_eq!(
+        decode(PixelFormat::Rgb8).as_u8(),
+        Some(&[128, 64, 32, 128, 64, 32, 0, 0, 0][..])
+    );
+    assert_eq!(
+        decode(PixelFormat::Composited([255, 255, 255])).as_u8(),
+        Some(&[128, 64, 32, 191, 159, 143, 255, 255, 255][..])
+    );
+    assert_eq!(
+        decode(PixelFormat::Composited([0, 0, 0])).as_u8(),
+        Some(&[128, 64, 32, 64, 32, 16, 0, 0, 0][..])
+    );
+    assert_eq!(decode(PixelFormat::Gray8).as_u8(), Some(&[75, 75, 0][..]));
+
+    let interleaved = decode(PixelFormat::RgbF32);
+    let planar = decode(PixelFormat::PlanarRgbF32);
+    assert_eq!((planar.width, planar.height), (3, 1));
+    assert_eq!(planar.format.channels(), 3);
+    let interleaved = interleaved.as_f32().expect("Missing float samples");
+    let planar = planar.as_f32().expect("Missing float samples");
+    assert_eq!(interleaved.len(), 9);
+    assert_eq!(interleaved[..3], [128.0 / 255.0, 64.0 / 255.0, 32.0 / 255.0]);
+    for pixel in 0..3 {
+        for channel in 0..3 {
+            assert_eq!(planar[channel * 3 + pixel], interleaved[pixel * 3 + channel]);
+        }
+    }
+}